@@ -1,215 +1,132 @@
-use std::{ffi::c_void, ptr};
-use csv::StringRecord;
-use pgrx::{ prelude::*, AllocatedByRust, PgBox };
-use crate::fdw::{csv_fdw::state::get_csv_reader, utils_share::{utils::{
-        build_attr_name_to_index_map, build_header_index_map, deserialize_from_list, exec_clear_tuple, get_datum, get_foreign_table_options, serialize_to_list, string_to_cstr, tuple_desc_attr
-    }}};
-use crate::fdw::csv_fdw::state::CsvFdwState;
-
-pub type FdwRoutine<A = AllocatedByRust> = PgBox<pg_sys::FdwRoutine, A>;
-
-#[pg_extern(create_or_replace)]
-pub extern "C" fn csv_fdw_handler() -> FdwRoutine {
-    log!("---> csv_fdw_handler");
-    unsafe {
-        let mut fdw_routine = PgBox::<pg_sys::FdwRoutine, AllocatedByRust>::alloc_node(pg_sys::NodeTag::T_FdwRoutine);
-
-        fdw_routine.GetForeignRelSize = Some(get_foreign_rel_size);
-        fdw_routine.GetForeignPaths = Some(get_foreign_paths);
-        fdw_routine.GetForeignPlan = Some(get_foreign_plan);
-        fdw_routine.ExplainForeignScan = Some(explain_foreign_scan);
-
-        // scan phase
-        fdw_routine.BeginForeignScan = Some(begin_foreign_scan);
-        fdw_routine.IterateForeignScan = Some(iterate_foreign_scan);
-        fdw_routine.ReScanForeignScan = Some(re_scan_foreign_scan);
-        fdw_routine.EndForeignScan = Some(end_foreign_scan); 
-
-        fdw_routine
+use std::collections::HashMap;
+use pgrx::{pg_sys::Oid, prelude::*};
+use crate::fdw::csv_fdw::state::{get_csv_reader, CsvFdw};
+use crate::fdw::utils_share::{
+    cell::Cell,
+    fdw::{foreign_data_wrapper, Column, FdwError, FdwResult, ForeignDataWrapper},
+    limit::Limit,
+    options::{check_options, FdwOption, OptionKind, TABLE},
+    qual::{eval_quals, Qual},
+    row::Row,
+    sort::Sort,
+    utils::build_header_index_map,
+};
+
+foreign_data_wrapper!(csv_fdw_handler, csv_fdw_validator, CsvFdw);
+
+const CSV_FDW_OPTIONS: &[FdwOption] = &[
+    FdwOption { name: "filepath", catalogs: &[TABLE], kind: OptionKind::ExistingFile },
+];
+
+impl CsvFdw {
+    fn open(&self) -> FdwResult<csv::Reader<std::fs::File>> {
+        get_csv_reader(&self.file_path).map_err(|e| {
+            FdwError::new(
+                PgSqlErrorCode::ERRCODE_FDW_UNABLE_TO_CREATE_EXECUTION,
+                format!("could not open CSV file \"{}\": {}", self.file_path, e),
+            )
+        })
     }
 }
 
-#[pg_guard]
-extern "C-unwind" fn get_foreign_rel_size(
-    _root: *mut pg_sys::PlannerInfo,
-    baserel: *mut pg_sys::RelOptInfo,
-    _foreigntableid: pg_sys::Oid,
-) {
-    log!("---> get_foreign_rel_size");
-    unsafe {
-        (*baserel).rows = 1000.0;
-        let state = CsvFdwState::new();
-        (*baserel).fdw_private = Box::into_raw(Box::new(state)) as *mut CsvFdwState as *mut c_void;
+impl ForeignDataWrapper for CsvFdw {
+    fn new(_relid: Oid, options: &HashMap<String, String>) -> FdwResult<Self> {
+        log!("Foreign table options: {:?}", options);
+        Ok(CsvFdw {
+            file_path: options.get("filepath").cloned().unwrap_or_default(),
+            csv_reader: None,
+            fields: Vec::new(),
+            quals: Vec::new(),
+            limit: None,
+            rows_returned: 0,
+        })
     }
-}
 
-#[pg_guard]
-extern "C-unwind" fn get_foreign_paths(
-    root: *mut pg_sys::PlannerInfo,
-    baserel: *mut pg_sys::RelOptInfo,
-    _foreigntableid: pg_sys::Oid,
-) {
-    log!("---> get_foreign_paths");
-    unsafe {
-        let startup_cost = 100.0;
-        let total_cost = startup_cost + (*baserel).rows;
-        // create a ForeignPath node and add it as the only possible path
-        let path = pg_sys::create_foreignscan_path(
-            root,
-            baserel,
-            ptr::null_mut(), // default pathtarget
-            (*baserel).rows,
-            startup_cost,
-            total_cost,
-            ptr::null_mut(), // no pathkeys
-            ptr::null_mut(), // no outer rel either
-            ptr::null_mut(), // no extra plan
-            ptr::null_mut(), // no fdw_private data
-        );
-        pg_sys::add_path(baserel, &mut ((*path).path));
+    fn validator(options: &[(String, String)], catalog: Oid) -> FdwResult<()> {
+        check_options(options, catalog, CSV_FDW_OPTIONS)
     }
-}
-
-#[pg_guard]
-unsafe extern "C-unwind" fn get_foreign_plan(
-    _root: *mut pg_sys::PlannerInfo,
-    baserel: *mut pg_sys::RelOptInfo,
-    _foreigntableid: pg_sys::Oid,
-    _best_path: *mut pg_sys::ForeignPath,
-    tlist: *mut pg_sys::List,
-    scan_clauses: *mut pg_sys::List,
-    outer_plan: *mut pg_sys::Plan,
-) -> *mut pg_sys::ForeignScan {
-    log!("---> get_foreign_plan");
-
-    let state = PgBox::<CsvFdwState>::from_pg((*baserel).fdw_private  as _);
-    pg_sys::make_foreignscan(
-        tlist,
-        pg_sys::extract_actual_clauses(scan_clauses, false), 
-        (*baserel).relid,
-        ptr::null_mut(),
-        serialize_to_list(state),
-        ptr::null_mut(),
-        ptr::null_mut(),
-        outer_plan,
-    )
-
-}
-
-#[pg_guard]
-extern "C-unwind" fn begin_foreign_scan(
-    node: *mut pg_sys::ForeignScanState,
-    _eflags: ::std::os::raw::c_int,
-) {
-
-    log!("---> begin_foreign_scan");
-    unsafe {
-        let plan = (*node).ss.ps.plan as *mut pg_sys::ForeignScan;
-        let mut state = deserialize_from_list::<CsvFdwState>((*plan).fdw_private as _);
-        let relation = (*node).ss.ss_currentRelation;
-        let relid = (*relation).rd_id;
-        let options  = get_foreign_table_options(relid);
-        log!("Foreign table options: {:?}", options);
-        let file_path = options.get("filepath").cloned().unwrap_or_default();
-        let mut csv_reader = get_csv_reader(&file_path);
-
-        let header = csv_reader.headers().expect("Failed to read CSV headers");
 
-        let header_name_to_colno = build_attr_name_to_index_map(relation);
-        state.header_name_to_colno = build_header_index_map( header, &header_name_to_colno );
-        state.csv_reader = Some(csv_reader);
-        state.file_path = file_path;
-        (*node).fdw_state = state.into_pg() as *mut c_void;
+    fn can_limit(&self, _limit: &Limit) -> bool {
+        true
     }
-}
-
 
+    fn begin_scan(&mut self, quals: &[Qual], columns: &[Column], _sorts: &[Sort], limit: &Option<Limit>) -> FdwResult<()> {
+        let mut csv_reader = self.open()?;
+        let header = csv_reader
+            .headers()
+            .map_err(|e| format!("could not read CSV headers of \"{}\": {}", self.file_path, e))?;
+
+        let attr_map: HashMap<String, usize> = columns.iter().map(|c| (c.name.clone(), c.colno)).collect();
+        self.fields = build_header_index_map(header, &attr_map)
+            .into_iter()
+            .filter_map(|colno| columns.iter().find(|c| c.colno == colno).cloned())
+            .collect();
+        self.csv_reader = Some(csv_reader);
+        self.quals = quals.to_vec();
+        self.limit = *limit;
+        self.rows_returned = 0;
+        Ok(())
+    }
 
-#[pg_guard]
-extern "C-unwind" fn iterate_foreign_scan(
-    node: *mut pg_sys::ForeignScanState,
-) -> *mut pg_sys::TupleTableSlot {
-    log!("---> iterate_foreign_scan");
-
-    unsafe {
-        let mut state = PgBox::<CsvFdwState>::from_pg((*node).fdw_state as _);
-        let slot = (*node).ss.ss_ScanTupleSlot;
-        let tupdesc = (*slot).tts_tupleDescriptor;
-        exec_clear_tuple(slot);
-        let mut record = StringRecord::new();
-        
-        let csv_reader = match state.csv_reader {
-            Some(ref mut reader) => reader,
-            None => {
-                log!("CSV reader is not initialized.");
-                return slot;
-            }
+    fn iter_scan(&mut self) -> FdwResult<Option<Row>> {
+        let Some(csv_reader) = self.csv_reader.as_mut() else {
+            return Ok(None);
         };
+        // the rest of the file is not needed by the query
+        if self.limit.is_some_and(|limit| self.rows_returned >= limit.rows()) {
+            return Ok(None);
+        }
+        let mut record = csv::StringRecord::new();
+        loop {
+            let more = csv_reader
+                .read_record(&mut record)
+                .map_err(|e| format!("could not read CSV record of \"{}\": {}", self.file_path, e))?;
+            if !more {
+                return Ok(None);
+            }
 
-        match csv_reader.read_record(&mut record) {
-            Ok(false) => { }
-            Ok(true) => {
-                for (i,field) in record.iter().enumerate(){
-                    let colno = state.header_name_to_colno[i];
-                    let pgtype = (*tuple_desc_attr(tupdesc, colno)).atttypid;
-                    let datum_value = get_datum(field, pgtype);
-                    (*slot).tts_values.add(colno).write(datum_value);
-                    (*slot).tts_isnull.add(colno).write(false);
-                }
-                pg_sys::ExecStoreVirtualTuple(slot);
+            let mut row = Row::new();
+            for (field, column) in record.iter().zip(&self.fields) {
+                // an empty field is NULL
+                let cell = match field {
+                    "" => None,
+                    _ => Some(Cell::parse(field, column.typid, column.typmod).map_err(|e| e.in_column(&column.name))?),
+                };
+                row.push(&column.name, cell);
             }
-            Err(e) => {
-                log!("Error reading CSV record: {}", e);
+            // records failing a qual would be filtered out by Postgres anyway
+            if eval_quals(&self.quals, &row) {
+                self.rows_returned += 1;
+                return Ok(Some(row));
             }
         }
-        
-        slot
     }
-}
 
-#[pg_guard]
-extern "C-unwind" fn end_foreign_scan(
-    node: *mut pg_sys::ForeignScanState,
-) {
-    log!("---> end_foreign_scan");
-    unsafe {
-        let state = (*node).fdw_state as *mut CsvFdwState;
-        let _ = Box::from_raw(state);
+    fn re_scan(&mut self, quals: &[Qual]) -> FdwResult<()> {
+        // start over from the first record
+        let mut csv_reader = self.open()?;
+        csv_reader
+            .headers()
+            .map_err(|e| format!("could not read CSV headers of \"{}\": {}", self.file_path, e))?;
+        self.csv_reader = Some(csv_reader);
+        self.quals = quals.to_vec();
+        self.rows_returned = 0;
+        Ok(())
     }
-}
-
-#[pg_guard]
-extern "C-unwind" fn re_scan_foreign_scan(
-    _node: *mut pg_sys::ForeignScanState,
-) {
-    log!("---> re_scan_foreign_scan");
-    // Reset or reinitialize scan state here if needed
-}
-
-#[pg_guard]
-unsafe extern "C-unwind" fn explain_foreign_scan(
-    node: *mut pgrx::pg_sys::ForeignScanState,
-    es: *mut pgrx::pg_sys::ExplainState,
-) {
-    log!("---> explain_foreign_scan");
 
-    let fs_state = (*node).fdw_state as *mut CsvFdwState;
-    if fs_state.is_null() {
-        return;
+    fn end_scan(&mut self) -> FdwResult<()> {
+        self.csv_reader = None;
+        Ok(())
     }
 
-    let state = &*fs_state;
-    
-    pg_sys::ExplainPropertyText(
-        string_to_cstr("CSV Filepath").as_ptr(),
-        string_to_cstr(&state.file_path).as_ptr(),
-        es,
-    );
+    fn explain(&self) -> Vec<(String, String)> {
+        vec![
+            ("CSV Filepath".to_string(), self.file_path.clone()),
+            ("Mapped Columns".to_string(), self.fields.len().to_string()),
+        ]
+    }
 
-    let col_count = state.header_name_to_colno.len().to_string();
-    pg_sys::ExplainPropertyText(
-        string_to_cstr("Mapped Columns").as_ptr(),
-        string_to_cstr(&col_count).as_ptr(),
-        es,
-    );
-}
\ No newline at end of file
+    fn is_updatable(_options: &HashMap<String, String>) -> bool {
+        false
+    }
+}
@@ -1,41 +1,23 @@
-use std::{collections::HashMap, fs::File};
+use std::fs::File;
 
-use crate::fdw::utils_share::cell::Cell;
+use crate::fdw::utils_share::{fdw::Column, limit::Limit, qual::Qual};
 
 
-#[repr(C)]
+/// Scan of a CSV file, the header names the column of each field
 #[derive(Debug)]
-pub struct CsvFdwState {
-    pub options : HashMap<String, String>,
-    pub csv_reader : Option<csv::Reader<File>>,
-    pub header_name_to_colno: Vec<usize>,
-    pub filters: Vec<(usize, Cell)>,
+pub struct CsvFdw {
     pub file_path: String,
+    pub csv_reader: Option<csv::Reader<File>>,
+    /// table column of each field of a record, in header order
+    pub fields: Vec<Column>,
+    /// restrictions checked on each record
+    pub quals: Vec<Qual>,
+    /// the scan stops after `Limit::rows` rows
+    pub limit: Option<Limit>,
+    pub rows_returned: i64,
 }
 
-
-impl CsvFdwState {
-    pub fn new() -> Self {
-        CsvFdwState {
-            header_name_to_colno : Vec::default(),
-            options : HashMap::default(),
-            csv_reader : Option::None,
-            filters : Vec::default(),
-            file_path: String::new(),
-        }
-    }
-}
-
-#[derive(Debug)]
-struct User {
-    id: u32,
-    name: String,
-    email: String,
-    age: u8,
+pub fn get_csv_reader(file_path: &str) -> csv::Result<csv::Reader<File>> {
+    // records shorter than the header leave their last columns NULL
+    csv::ReaderBuilder::new().flexible(true).from_path(file_path)
 }
-
-
-pub fn get_csv_reader(file_path:&str) -> csv::Reader<File> {
-    let file = File::open(file_path).expect(&format!("Failed to open CSV file: {}", file_path));
-    csv::Reader::from_reader(file)
-}
\ No newline at end of file
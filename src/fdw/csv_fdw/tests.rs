@@ -1,8 +1,119 @@
 #[cfg(any(test, feature = "pg_test"))]
-#[pgrx::pg_schema] 
+#[pgrx::pg_schema]
 mod tests {
-  
-}
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn csv_fdw_prepared_select_runs_twice() {
+        let path = write_csv("prepared", "id,name\n1,alice\n2,bob\n3,carol\n");
+        Spi::connect_mut(|c| {
+            create_csv_table(c, &path);
+            // one generic plan, run twice
+            c.update("set local plan_cache_mode = force_generic_plan", None, &[]).unwrap();
+            c.update("prepare select_people(int) as select name from people where id > $1 order by id", None, &[])
+                .unwrap();
+            for _ in 0..2 {
+                assert_eq!(select_names(c, "execute select_people(1)"), vec!["bob", "carol"]);
+            }
+            assert_eq!(select_names(c, "execute select_people(2)"), vec!["carol"]);
+            c.update("deallocate select_people", None, &[]).unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn csv_fdw_select() {
+        let path = write_csv("select", "id,name\n1,alice\n2,bob\n3,carol\n");
+        Spi::connect_mut(|c| {
+            create_csv_table(c, &path);
+            assert_eq!(select_names(c, "select name from people order by id"), vec!["alice", "bob", "carol"]);
+            let count = c
+                .select("select count(*) from people", None, &[])
+                .unwrap()
+                .first()
+                .get::<i64>(1)
+                .unwrap();
+            assert_eq!(count, Some(3));
+        });
+    }
+
+    #[pg_test]
+    fn csv_fdw_where_and_limit() {
+        let path = write_csv("filter", "id,name\n1,alice\n2,bob\n3,carol\n4,dave\n");
+        Spi::connect_mut(|c| {
+            create_csv_table(c, &path);
+            assert_eq!(select_names(c, "select name from people where id = 2"), vec!["bob"]);
+            assert_eq!(select_names(c, "select name from people where id >= 3 order by id"), vec!["carol", "dave"]);
+            assert_eq!(select_names(c, "select name from people limit 2"), vec!["alice", "bob"]);
+            assert_eq!(select_names(c, "select name from people where id > 1 limit 1"), vec!["bob"]);
+        });
+    }
 
+    #[pg_test]
+    fn csv_fdw_short_rows_are_null() {
+        let path = write_csv("short", "id,name\n1,alice\n2\n3,\n");
+        Spi::connect_mut(|c| {
+            create_csv_table(c, &path);
+            let names = c
+                .select("select id, name from people order by id", None, &[])
+                .unwrap()
+                .map(|row| (row.get::<i32>(1).unwrap().unwrap(), row.get::<String>(2).unwrap()))
+                .collect::<Vec<_>>();
+            assert_eq!(names, vec![(1, Some("alice".to_string())), (2, None), (3, None)]);
+        });
+    }
 
+    #[pg_test(error = "invalid integer value \"two\" for column \"id\": invalid input syntax for type integer: \"two\"")]
+    fn csv_fdw_bad_value() {
+        let path = write_csv("bad_value", "id,name\n1,alice\ntwo,bob\n");
+        Spi::connect_mut(|c| {
+            create_csv_table(c, &path);
+            select_names(c, "select name from people");
+        });
+    }
 
+    #[pg_test(error = "invalid option \"file_path\"")]
+    fn csv_fdw_rejects_unknown_option() {
+        let path = write_csv("option", "id,name\n1,alice\n");
+        Spi::connect_mut(|c| {
+            create_csv_table(c, &path);
+            c.update(
+                &format!(
+                    "create foreign table others (id int, name text) server csv_server options (file_path '{}')",
+                    path
+                ),
+                None,
+                &[],
+            )
+            .unwrap();
+        });
+    }
+
+    /// Write a CSV file in the temporary directory, named after the test
+    fn write_csv(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("csv_fdw_{}_{}.csv", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn create_csv_table(c: &mut pgrx::spi::SpiClient<'_>, path: &str) {
+        c.update("create foreign data wrapper csv_wrapper handler csv_fdw_handler validator csv_fdw_validator", None, &[])
+            .unwrap();
+        c.update("create server csv_server foreign data wrapper csv_wrapper", None, &[]).unwrap();
+        c.update(
+            &format!(
+                "create foreign table people (id int, name text) server csv_server options (filepath '{}')",
+                path
+            ),
+            None,
+            &[],
+        )
+        .unwrap();
+    }
+
+    fn select_names(c: &mut pgrx::spi::SpiClient<'_>, query: &str) -> Vec<String> {
+        c.select(query, None, &[])
+            .unwrap()
+            .map(|row| row.get::<String>(1).unwrap().unwrap_or_default())
+            .collect()
+    }
+}
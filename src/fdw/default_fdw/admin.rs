@@ -0,0 +1,333 @@
+use std::time::UNIX_EPOCH;
+use pgrx::{datum::to_timestamp, pg_sys::{CmdType, Oid}, prelude::*, spi, JsonB, PgRelation};
+use serde_json::{Map, Value};
+
+use crate::fdw::default_fdw::{
+    handlers::{ensure_memory_table, refresh_memory_table, report_table_error, schedule_snapshot, MEMORY_TABLE},
+    memory_table::{stored_value, TableMap},
+    state::DefaultFdw,
+};
+use crate::fdw::utils_share::{
+    cell::Cell,
+    fdw::check_updatable,
+    utils::{get_datum, string_from_cstr},
+};
+
+/// List the in-memory tables of this backend
+#[pg_extern]
+pub fn default_fdw_tables() -> TableIterator<
+    'static,
+    (
+        name!(ftrelid, Oid),
+        name!(row_count, i64),
+        name!(approx_bytes, i64),
+        name!(rowid_column, Option<String>),
+        name!(last_modified, Option<TimestampWithTimeZone>),
+    ),
+> {
+    let tables = MEMORY_TABLE.read().unwrap();
+    let mut rows: Vec<_> = tables
+        .iter()
+        .map(|(relid, table)| {
+            let last_modified = table
+                .modified_at()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| to_timestamp(d.as_secs_f64()));
+            (
+                *relid,
+                table.len() as i64,
+                table.bytes() as i64,
+                Some(table.rowid_name.clone()).filter(|n| !n.is_empty()),
+                last_modified,
+            )
+        })
+        .collect();
+    rows.sort_by_key(|row| row.0);
+    TableIterator::new(rows)
+}
+
+/// Remove every row of an in-memory table, returns the number of rows removed
+#[pg_extern]
+pub fn default_fdw_clear_oid(relid: Oid) -> i64 {
+    check_foreign_table(relid);
+    check_writable(relid, CmdType::CMD_DELETE);
+    unsafe { ensure_memory_table(relid) };
+
+    let removed = match MEMORY_TABLE.write().unwrap().get_mut(&relid) {
+        Some(table) => {
+            let removed = table.len();
+            table.clear();
+            removed
+        }
+        None => 0,
+    };
+    schedule_snapshot(relid);
+    removed as i64
+}
+
+/// Return every row of an in-memory table as a jsonb object
+#[pg_extern]
+pub fn default_fdw_dump_oid(relid: Oid) -> SetOfIterator<'static, JsonB> {
+    check_foreign_table(relid);
+    unsafe { ensure_memory_table(relid) };
+
+    let rel = unsafe { PgRelation::with_lock(relid, pg_sys::AccessShareLock as _) };
+    let col_types: Vec<(String, Oid)> = rel
+        .tuple_desc()
+        .iter()
+        .filter(|a| !a.is_dropped())
+        .map(|a| (a.name().to_string(), a.type_oid().value()))
+        .collect();
+
+    let tables = MEMORY_TABLE.read().unwrap();
+    let rows: Vec<JsonB> = tables
+        .get(&relid)
+        .map(|table| {
+            table
+                .iter()
+                .map(|row| JsonB(Value::Object(table_map_to_json(row, &col_types))))
+                .collect()
+        })
+        .unwrap_or_default();
+    SetOfIterator::new(rows)
+}
+
+/// Insert the rows of `source_query` into an in-memory table, returns the
+/// number of rows loaded. The result columns are matched to the table columns
+/// by position and cast to their types.
+#[pg_extern]
+pub fn default_fdw_load_oid(relid: Oid, source_query: &str) -> i64 {
+    check_foreign_table(relid);
+    check_writable(relid, CmdType::CMD_INSERT);
+    let columns = table_columns(relid);
+
+    let aliases = columns
+        .iter()
+        .map(|c| spi::quote_identifier(&c.name))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let casts = columns
+        .iter()
+        .map(|c| format!("s.{}::{}", spi::quote_identifier(&c.name), c.type_name))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let sql = format!(
+        "SELECT {} FROM ({}) AS s({})",
+        casts,
+        source_query.trim().trim_end_matches(';'),
+        aliases
+    );
+
+    let rows: spi::SpiResult<Vec<TableMap>> = Spi::connect(|client| {
+        client
+            .select(&sql, None, &[])?
+            .map(|row| {
+                let mut map = TableMap::new();
+                for (i, column) in columns.iter().enumerate() {
+                    let value = row.get::<Cell>(i + 1)?.map(|cell| stored_value(&cell));
+                    map.insert(column.name.clone(), value);
+                }
+                Ok(map)
+            })
+            .collect()
+    });
+    match rows {
+        Ok(rows) => insert_rows(relid, rows),
+        Err(e) => error!("could not run source query: {}", e),
+    }
+}
+
+/// Insert the rows of a server side CSV file into an in-memory table, returns
+/// the number of rows loaded. The header line names the table columns, empty
+/// fields and columns missing from the file are NULL.
+#[pg_extern]
+pub fn default_fdw_load_csv_oid(relid: Oid, path: &str) -> i64 {
+    check_foreign_table(relid);
+    check_writable(relid, CmdType::CMD_INSERT);
+    if !unsafe { pg_sys::superuser() } {
+        ereport!(
+            PgLogLevel::ERROR,
+            PgSqlErrorCode::ERRCODE_INSUFFICIENT_PRIVILEGE,
+            "must be superuser to load a file"
+        );
+    }
+    let columns = table_columns(relid);
+
+    let mut reader = csv::Reader::from_path(path)
+        .unwrap_or_else(|e| error!("could not open file \"{}\": {}", path, e));
+    let header = reader
+        .headers()
+        .unwrap_or_else(|e| error!("could not read file \"{}\": {}", path, e))
+        .clone();
+    let file_columns: Vec<&TableColumn> = header
+        .iter()
+        .map(|name| {
+            columns.iter().find(|c| c.name == name).unwrap_or_else(|| {
+                error!("column \"{}\" of file \"{}\" does not exist in the table", name, path)
+            })
+        })
+        .collect();
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.unwrap_or_else(|e| error!("could not read file \"{}\": {}", path, e));
+        let mut map: TableMap = columns
+            .iter()
+            .map(|c| (c.name.clone(), None))
+            .collect();
+        for (field, column) in record.iter().zip(file_columns.iter()) {
+            if !field.is_empty() {
+                map.insert(column.name.clone(), text_to_stored_value(field, column.typid));
+            }
+        }
+        rows.push(map);
+    }
+    insert_rows(relid, rows)
+}
+
+extension_sql!(
+    r#"
+CREATE FUNCTION default_fdw_clear(rel regclass) RETURNS bigint
+    STRICT LANGUAGE sql AS $$ SELECT default_fdw_clear_oid(rel::oid) $$;
+
+CREATE FUNCTION default_fdw_dump(rel regclass) RETURNS SETOF jsonb
+    STRICT LANGUAGE sql AS $$ SELECT default_fdw_dump_oid(rel::oid) $$;
+
+CREATE FUNCTION default_fdw_load(rel regclass, source_query text) RETURNS bigint
+    STRICT LANGUAGE sql AS $$ SELECT default_fdw_load_oid(rel::oid, source_query) $$;
+
+CREATE FUNCTION default_fdw_load_csv(rel regclass, path text) RETURNS bigint
+    STRICT LANGUAGE sql AS $$ SELECT default_fdw_load_csv_oid(rel::oid, path) $$;
+"#,
+    name = "default_fdw_regclass_functions",
+    requires = [
+        default_fdw_clear_oid,
+        default_fdw_dump_oid,
+        default_fdw_load_oid,
+        default_fdw_load_csv_oid
+    ],
+);
+
+struct TableColumn {
+    name: String,
+    typid: Oid,
+    /// type name with modifiers, for casts
+    type_name: String,
+}
+
+fn table_columns(relid: Oid) -> Vec<TableColumn> {
+    let rel = unsafe { PgRelation::with_lock(relid, pg_sys::AccessShareLock as _) };
+    rel.tuple_desc()
+        .iter()
+        .filter(|a| !a.is_dropped())
+        .map(|a| TableColumn {
+            name: a.name().to_string(),
+            typid: a.type_oid().value(),
+            type_name: string_from_cstr(unsafe {
+                pg_sys::format_type_with_typemod(a.type_oid().value(), a.type_mod())
+            }),
+        })
+        .collect()
+}
+
+/// Insert rows under a single write lock, bypassing the per-row executor
+/// callbacks. No row is stored if one of them cannot be.
+fn insert_rows(relid: Oid, rows: Vec<TableMap>) -> i64 {
+    unsafe { ensure_memory_table(relid) };
+    let rowid_name = MEMORY_TABLE
+        .read()
+        .unwrap()
+        .get(&relid)
+        .map(|t| t.rowid_name.clone())
+        .unwrap_or_default();
+    unsafe { refresh_memory_table(relid, &rowid_name) };
+
+    let count = rows.len();
+    // release the lock before raising, an error unwinds and would poison it
+    let res = {
+        let mut tables = MEMORY_TABLE.write().unwrap();
+        tables.entry(relid).or_default().insert_batch(&rowid_name, rows)
+    };
+    if let Err(e) = res {
+        report_table_error(&rowid_name, e);
+    }
+    schedule_snapshot(relid);
+    count as i64
+}
+
+/// Convert the text form of a value to the stored form of the column type
+fn text_to_stored_value(text: &str, typid: Oid) -> Option<String> {
+    let cell = unsafe { Cell::from_polymorphic_datum(get_datum(text, typid), false, typid) };
+    cell.map(|c| stored_value(&c))
+}
+
+fn check_foreign_table(relid: Oid) {
+    let relkind = unsafe { pg_sys::get_rel_relkind(relid) };
+    if relkind != pg_sys::RELKIND_FOREIGN_TABLE as std::os::raw::c_char {
+        ereport!(
+            PgLogLevel::ERROR,
+            PgSqlErrorCode::ERRCODE_WRONG_OBJECT_TYPE,
+            format!("relation with OID {} is not a foreign table", relid.to_u32())
+        );
+    }
+
+    // the rows of tables of other wrappers are not in `MEMORY_TABLE`
+    let handler = unsafe {
+        let server = pg_sys::GetForeignServer((*pg_sys::GetForeignTable(relid)).serverid);
+        let fdw = pg_sys::GetForeignDataWrapper((*server).fdwid);
+        let handler = (*fdw).fdwhandler;
+        (handler != pg_sys::InvalidOid).then(|| string_from_cstr(pg_sys::get_func_name(handler)))
+    };
+    if handler.as_deref() != Some("default_fdw_handler") {
+        ereport!(
+            PgLogLevel::ERROR,
+            PgSqlErrorCode::ERRCODE_WRONG_OBJECT_TYPE,
+            format!(
+                "foreign table \"{}\" does not use default_fdw_handler",
+                string_from_cstr(unsafe { pg_sys::get_rel_name(relid) })
+            )
+        );
+    }
+}
+
+/// Reject writes to a read-only table, as INSERT and DELETE do
+fn check_writable(relid: Oid, operation: CmdType::Type) {
+    unsafe {
+        let rel = PgRelation::with_lock(relid, pg_sys::AccessShareLock as _);
+        check_updatable::<DefaultFdw>(&rel, operation);
+    }
+}
+
+/// Convert a stored row to a json object, typed by the column types
+fn table_map_to_json(row: &TableMap, col_types: &[(String, Oid)]) -> Map<String, Value> {
+    let mut obj = Map::new();
+    for (col_name, typid) in col_types {
+        let value = match row.get(col_name) {
+            Some(Some(v)) => stored_value_to_json(v, *typid),
+            _ => Value::Null,
+        };
+        obj.insert(col_name.clone(), value);
+    }
+    obj
+}
+
+fn stored_value_to_json(value: &str, typid: Oid) -> Value {
+    let cell = unsafe {
+        let datum = get_datum(value, typid);
+        Cell::from_polymorphic_datum(datum, false, typid)
+    };
+    match cell {
+        Some(Cell::Bool(v)) => Value::from(v),
+        Some(Cell::I8(v)) => Value::from(v),
+        Some(Cell::I16(v)) => Value::from(v),
+        Some(Cell::I32(v)) => Value::from(v),
+        Some(Cell::I64(v)) => Value::from(v),
+        Some(Cell::F32(v)) => Value::from(v),
+        Some(Cell::F64(v)) => Value::from(v),
+        Some(Cell::String(v) | Cell::Varchar(v) | Cell::Bpchar(v) | Cell::Name(v)) => Value::from(v),
+        Some(Cell::Json(v)) => v.0,
+        Some(Cell::Jsonb(v)) => v.0,
+        Some(other) => Value::from(other.to_string()),
+        None => Value::from(value),
+    }
+}
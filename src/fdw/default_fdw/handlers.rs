@@ -1,490 +1,692 @@
-use std::{collections::HashMap, ffi::{c_int}, ptr, slice};
+use std::{cmp::Ordering, collections::{HashMap, HashSet}, sync::{Arc, Mutex}, time::Duration};
 use once_cell::sync::Lazy;
-use pgrx::{ pg_sys::{ CmdType, Datum, Index, MemoryContextData, ModifyTable, PlannerInfo, TargetEntry}, prelude::*, AllocatedByRust, PgBox, PgMemoryContexts, PgRelation, PgTupleDesc
-};
+use pgrx::{pg_sys::{CmdType, Datum, Oid}, prelude::*, register_xact_callback, PgRelation, PgTupleDesc, PgXactCallbackEvent};
 use crate::fdw::utils_share::{
     cell::Cell,
-    memory::create_wrappers_memctx,
+    fdw::{foreign_data_wrapper, Column, DirectModify, FdwError, FdwResult, ForeignDataWrapper, ImportSchema},
+    join::{Join, JoinKind},
+    limit::Limit,
+    options::{check_options, parse_bool, parse_positive_int, FdwOption, OptionKind, SERVER, TABLE},
+    qual::{eval_quals, Qual},
     row::Row,
-    utils::{
-        self, build_attr_name_to_index_map, delete_wrappers_memctx, deserialize_from_list, exec_clear_tuple, find_rowid_column, get_datum, get_foreign_table_options, serialize_to_list, tuple_desc_attr, tuple_table_slot_to_row
-    }
+    sort::Sort,
+    utils::{self, find_rowid_column, get_datum, get_foreign_table_options, text_to_cell},
 };
-use crate::fdw::default_fdw::state::{DefaultFdwState, FdwModifyState};
-
-type TableMap = HashMap<String, String>;
-static MEMORY_TABLE: Lazy<std::sync::RwLock<Vec<TableMap>>> = Lazy::new(|| std::sync::RwLock::new(Vec::new()));
-
-pub type FdwRoutine<A = AllocatedByRust> = PgBox<pgrx::pg_sys::FdwRoutine, A>;
-
-#[pg_extern(create_or_replace)]
-pub extern "C" fn default_fdw_handler() -> FdwRoutine {
-    log!("---> default_fdw_handler");
-    unsafe {
-        let mut fdw_routine = PgBox::<pgrx::pg_sys::FdwRoutine, AllocatedByRust>::alloc_node(pgrx::pg_sys::NodeTag::T_FdwRoutine);
-        fdw_routine.ImportForeignSchema = Some(import_foreign_schema);
-
-        fdw_routine.GetForeignRelSize = Some(get_foreign_rel_size);
-        fdw_routine.GetForeignPaths = Some(get_foreign_paths);
-        fdw_routine.GetForeignPlan = Some(get_foreign_plan);
-        fdw_routine.ExplainForeignScan = Some(explain_foreign_scan);
-
-        // scan phase
-        fdw_routine.BeginForeignScan = Some(begin_foreign_scan);
-        fdw_routine.IterateForeignScan = Some(iterate_foreign_scan);
-        fdw_routine.ReScanForeignScan = Some(re_scan_foreign_scan);
-        fdw_routine.EndForeignScan = Some(end_foreign_scan); 
-        
-        
-        fdw_routine.AddForeignUpdateTargets = Some(add_foreign_update_targets);
-        fdw_routine.PlanForeignModify = Some(plan_foreign_modify);
-        fdw_routine.BeginForeignModify = Some(begin_foreign_modify);
-        fdw_routine.ExecForeignInsert = Some(exec_foreign_insert);
-        fdw_routine.ExecForeignDelete = Some(exec_foreign_delete);
-        fdw_routine.ExecForeignUpdate = Some(exec_foreign_update);
-        fdw_routine.EndForeignModify = Some(end_foreign_modify);
-        //fdw_routine.IsForeignRelUpdatable =
-
-        fdw_routine
+use crate::fdw::default_fdw::{
+    memory_table::{stored_value, EvictionPolicy, MemoryTable, TableError, TableLimits, TableMap},
+    persist, registry,
+    state::{DefaultFdw, JoinedRow},
+};
+
+/// in-memory tables keyed by foreign table oid
+pub(super) static MEMORY_TABLE: Lazy<std::sync::RwLock<HashMap<Oid, MemoryTable>>> = Lazy::new(|| std::sync::RwLock::new(HashMap::new()));
+/// tables modified in the current transaction that need a snapshot at commit
+static PENDING_SNAPSHOTS: Lazy<Mutex<HashSet<Oid>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// options of servers and tables, a table option overrides the server one
+const DEFAULT_FDW_OPTIONS: &[FdwOption] = &[
+    FdwOption { name: "updatable", catalogs: &[SERVER, TABLE], kind: OptionKind::Bool },
+    FdwOption { name: "batch_size", catalogs: &[SERVER, TABLE], kind: OptionKind::PositiveInt },
+    FdwOption { name: persist::PERSIST_PATH_OPTION, catalogs: &[SERVER, TABLE], kind: OptionKind::OutputPath },
+    FdwOption { name: "max_rows", catalogs: &[SERVER, TABLE], kind: OptionKind::PositiveInt },
+    FdwOption { name: "max_bytes", catalogs: &[SERVER, TABLE], kind: OptionKind::PositiveInt },
+    FdwOption { name: "eviction", catalogs: &[SERVER, TABLE], kind: OptionKind::Choice(&["error", "lru", "fifo"]) },
+    FdwOption { name: "ttl_seconds", catalogs: &[SERVER, TABLE], kind: OptionKind::PositiveInt },
+    FdwOption { name: "sort_key", catalogs: &[TABLE], kind: OptionKind::Text },
+    FdwOption { name: utils::ROWID_OPTION, catalogs: &[TABLE], kind: OptionKind::Text },
+];
+
+foreign_data_wrapper!(default_fdw_handler, default_fdw_validator, DefaultFdw);
+
+impl ForeignDataWrapper for DefaultFdw {
+    fn new(relid: Oid, options: &HashMap<String, String>) -> FdwResult<Self> {
+        unsafe {
+            ensure_memory_table(relid);
+            let rel = PgRelation::with_lock(relid, pg_sys::NoLock as _);
+            let rowid_name = find_rowid_column(rel.as_ptr())
+                .map(|attr| pgrx::name_data_to_str(&attr.attname).to_string())
+                .unwrap_or_default();
+            Ok(DefaultFdw {
+                relid,
+                rowid_name,
+                columns: Vec::new(),
+                batch_size: positive_option(options, "batch_size").unwrap_or(1),
+                returning: false,
+                rowid_filter: None,
+                sort_key: options.get("sort_key").cloned(),
+                sort: None,
+                quals: Vec::new(),
+                limit: None,
+                snapshot: None,
+                row_count: 0,
+                rows_scanned: 0,
+                rows_returned: 0,
+                join: None,
+                pairs: None,
+                scanning: false,
+            })
+        }
     }
-}
 
-#[pg_guard]
-extern "C-unwind" fn import_foreign_schema(
-    _stmt: *mut pgrx::pg_sys::ImportForeignSchemaStmt,
-    _server_oid: pgrx::pg_sys::Oid,
-) -> *mut pgrx::pg_sys::List {
-    log!("---> import_foreign_schema");
-    std::ptr::null_mut()
-}
+    fn estimate_rows(&mut self, quals: &[Qual]) -> FdwResult<f64> {
+        if let Some(column) = &self.sort_key {
+            let exists = unsafe {
+                let rel = PgRelation::with_lock(self.relid, pg_sys::NoLock as _);
+                PgTupleDesc::from_relation(&rel)
+                    .iter()
+                    .any(|attr| !attr.is_dropped() && pgrx::name_data_to_str(&attr.attname) == column)
+            };
+            if !exists {
+                return Err(FdwError::new(
+                    PgSqlErrorCode::ERRCODE_UNDEFINED_COLUMN,
+                    format!("sort_key column \"{}\" does not exist", column),
+                ));
+            }
+        }
+        // a rowid lookup reads a single row
+        if self.rowid_lookup(quals).is_some() {
+            return Ok(1.0);
+        }
+        let table_rows = MEMORY_TABLE.read().unwrap().get(&self.relid).map_or(0, |t| t.len());
+        Ok(table_rows as f64)
+    }
 
-#[pg_guard]
-extern "C-unwind" fn get_foreign_rel_size(
-    _root: *mut pgrx::pg_sys::PlannerInfo,
-    baserel: *mut pgrx::pg_sys::RelOptInfo,
-    foreigntableid: pgrx::pg_sys::Oid,
-) {
-    log!("---> get_foreign_rel_size");
-    unsafe {
-        let ctx_name = format!("Wrappers_scan_{}", foreigntableid.to_u32());
-        log!("Creating memory context: {}", ctx_name);
-        let ctx = create_wrappers_memctx(&ctx_name);
-        let state = DefaultFdwState::new(ctx);
-
-        (*baserel).fdw_private = Box::into_raw(Box::new(state)) as *mut DefaultFdwState as *mut std::os::raw::c_void;
-        log!("(*baserel).fdw_private {:?}",(*baserel).fdw_private);
-        (*baserel).rows = 1000.0;
+    fn can_sort(&self, sorts: &[Sort]) -> usize {
+        // rows are sorted on the `sort_key` column only, when its type has a
+        // comparison function
+        let Some(sort) = sorts.first().filter(|sort| self.sort_key.as_ref() == Some(&sort.field)) else {
+            return 0;
+        };
+        let sortable = unsafe {
+            let typid = pg_sys::get_atttype(self.relid, sort.field_no as _);
+            let typentry = pg_sys::lookup_type_cache(typid, pg_sys::TYPECACHE_CMP_PROC as _);
+            (*typentry).cmp_proc != pg_sys::InvalidOid
+        };
+        sortable as usize
     }
-}
 
-#[pg_guard]
-extern "C-unwind" fn get_foreign_paths(
-    _root: *mut pgrx::pg_sys::PlannerInfo,
-    baserel: *mut pgrx::pg_sys::RelOptInfo,
-    _foreigntableid: pgrx::pg_sys::Oid,
-) {
-    log!("---> get_foreign_paths");
-    unsafe {
-        let path = pgrx::pg_sys::create_foreignscan_path(
-            _root,
-            baserel,
-            ptr::null_mut(),           
-            (*baserel).rows,            
-            10.0,                       
-            100.0,                     
-            ptr::null_mut(),            
-            ptr::null_mut(),            
-            ptr::null_mut(),           
-            ptr::null_mut(),           
-        );
-        pgrx::pg_sys::add_path(baserel, path as *mut pgrx::pg_sys::Path);
+    fn can_limit(&self, _limit: &Limit) -> bool {
+        true
     }
-}
 
-#[pg_guard]
-unsafe extern "C-unwind" fn get_foreign_plan(
-    _root: *mut pgrx::pg_sys::PlannerInfo,
-    baserel: *mut pgrx::pg_sys::RelOptInfo,
-    _foreigntableid: pgrx::pg_sys::Oid,
-    _best_path: *mut pgrx::pg_sys::ForeignPath,
-    tlist: *mut pgrx::pg_sys::List,
-    scan_clauses: *mut pgrx::pg_sys::List,
-    outer_plan: *mut pgrx::pg_sys::Plan,
-) -> *mut pgrx::pg_sys::ForeignScan {
-    log!("---> get_foreign_plan");
-    pgrx::pg_sys::make_foreignscan(
-        tlist,
-        pg_sys::extract_actual_clauses(scan_clauses, false),
-        (*baserel).relid,
-        ptr::null_mut(),
-        (*baserel).fdw_private as _,
-        ptr::null_mut(),
-        ptr::null_mut(),
-        outer_plan,
-    )
-}
+    fn begin_scan(&mut self, quals: &[Qual], columns: &[Column], sorts: &[Sort], limit: &Option<Limit>) -> FdwResult<()> {
+        self.columns = columns.to_vec();
+        self.quals = quals.to_vec();
+        self.rowid_filter = self.rowid_lookup(quals);
+        self.sort = sorts.first().cloned();
+        self.limit = *limit;
+        self.scanning = true;
+        Ok(())
+    }
 
-#[pg_guard]
-unsafe extern "C-unwind" fn explain_foreign_scan(
-    _node: *mut pgrx::pg_sys::ForeignScanState,
-    es: *mut pgrx::pg_sys::ExplainState,
-) {
-    log!("---> explain_foreign_scan");
-}
+    fn iter_scan(&mut self) -> FdwResult<Option<Row>> {
+        if self.join.is_some() {
+            return Ok(self.next_joined_row());
+        }
+        // the scan reads a snapshot, writes of the statement do not show up
+        if self.snapshot.is_none() {
+            self.snapshot = Some(unsafe { self.scan_snapshot() });
+        }
+        if self.limit.is_some_and(|limit| self.rows_returned as i64 >= limit.rows()) {
+            return Ok(None);
+        }
+        while let Some(tuple_row) = self.snapshot.as_ref().and_then(|rows| rows.get(self.row_count)).cloned() {
+            self.row_count += 1;
+            self.rows_scanned += 1;
+            log!("iterate_foreign_scan tuple_row: {:?}", tuple_row);
+            let row = self.stored_row(&tuple_row);
+            if eval_quals(&self.quals, &row) {
+                self.rows_returned += 1;
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+
+    fn re_scan(&mut self, quals: &[Qual]) -> FdwResult<()> {
+        // start over from the first row of the same snapshot, e.g. on the
+        // inner side of a nested loop, unless the rowid to look up changed
+        self.row_count = 0;
+        self.rows_returned = 0;
+        self.quals = quals.to_vec();
+        let rowid_filter = self.rowid_lookup(quals);
+        if rowid_filter != self.rowid_filter {
+            self.rowid_filter = rowid_filter;
+            self.snapshot = None;
+        }
+        Ok(())
+    }
+
+    fn can_join(&self, join: &Join) -> bool {
+        // rows are matched on their stored keys
+        join.keys.iter().all(|key| unsafe { equal_image(key.typid, key.collation) })
+    }
+
+    fn begin_join(&mut self, join: &Join) -> FdwResult<()> {
+        unsafe { ensure_memory_table(join.inner) };
+        self.join = Some(join.clone());
+        self.scanning = true;
+        Ok(())
+    }
 
-#[pg_guard]
-extern "C-unwind" fn begin_foreign_scan(
-    node: *mut pgrx::pg_sys::ForeignScanState,
-    eflags: ::std::os::raw::c_int,
-) {
+    fn explain(&self) -> Vec<(String, String)> {
+        let mut props = Vec::new();
+        if self.join.is_none() {
+            let table_rows = MEMORY_TABLE.read().unwrap().get(&self.relid).map_or(0, |t| t.len());
+            props.push(("Table Rows".to_string(), table_rows.to_string()));
+            props.push(("Rowid Column".to_string(), self.rowid_name.clone()));
+        }
+        // the quals and counts are known once the scan started, with EXPLAIN
+        // ANALYZE
+        if self.scanning {
+            if self.join.is_none() {
+                let quals: Vec<String> = self.quals.iter().map(|qual| qual.to_string()).collect();
+                let quals = if quals.is_empty() { "none".to_string() } else { quals.join(" AND ") };
+                props.push(("Pushed Quals".to_string(), quals));
+            }
+            props.push(("Rows Scanned".to_string(), self.rows_scanned.to_string()));
+            props.push(("Rows Returned".to_string(), self.rows_returned.to_string()));
+        }
+        props
+    }
 
-    log!("---> begin_foreign_scan");
-    unsafe {
-        let scan_state = (*node).ss;
-        let plan = scan_state.ps.plan as *mut pg_sys::ForeignScan;
-        let relation = (*node).ss.ss_currentRelation;
-        let relid = (*relation).rd_id;
-        let mut state =  PgBox::<DefaultFdwState>::from_pg((*plan).fdw_private as _); 
+    fn is_updatable(options: &HashMap<String, String>) -> bool {
+        // the table option overrides the server option, tables are updatable
+        // by default
+        bool_option(options, "updatable").unwrap_or(true)
+    }
 
-        let options = get_foreign_table_options(relid);
-        log!("Foreign table options: {:?}", options);
+    fn rowid_column(options: &HashMap<String, String>) -> Option<String> {
+        Some(utils::rowid_column_name(options))
+    }
 
-        state.header_name_to_colno = build_attr_name_to_index_map(relation);
+    fn validator(options: &[(String, String)], catalog: Oid) -> FdwResult<()> {
+        check_options(options, catalog, DEFAULT_FDW_OPTIONS)
+    }
 
-        log!("Header name to column number mapping: {:?}", state.header_name_to_colno);
-        
-        (*node).fdw_state = state.into_pg() as _;
+    fn begin_modify(&mut self, columns: &[Column], returning: bool) -> FdwResult<()> {
+        self.columns = columns.to_vec();
+        self.returning = returning;
+        unsafe { refresh_memory_table(self.relid, &self.rowid_name) };
+        Ok(())
     }
-}
 
-#[pg_guard]
-extern "C-unwind" fn iterate_foreign_scan(
-    node: *mut pgrx::pg_sys::ForeignScanState,
-) -> *mut pgrx::pg_sys::TupleTableSlot {
-    log!("---> iterate_foreign_scan");
-
-    unsafe {
-        let mut state = PgBox::<DefaultFdwState>::from_pg((*node).fdw_state as _);
-        let slot = (*node).ss.ss_ScanTupleSlot;
-        let tupdesc = (*slot).tts_tupleDescriptor;
-        let header_name_to_colno = &state.header_name_to_colno;
-        let data = MEMORY_TABLE.read().unwrap();
-        
-        exec_clear_tuple(slot);
-        if data.len() == 0 || state.row_count >= data.len() {
-            return slot;
-        }
-    
-        let tuple_row = &data[state.row_count];
-        log!("iterate_foreign_scan tuple_row: {:?}", tuple_row);
-        
-        for (col_name, value_str) in tuple_row.iter() {
-            let colno = header_name_to_colno[col_name];
-            let pgtype = (*tuple_desc_attr(tupdesc, colno )).atttypid;
-            let datum_value = get_datum(value_str, pgtype);
-            (*slot).tts_values.add(colno).write(datum_value);
-            (*slot).tts_isnull.add(colno).write(false);
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    fn insert(&mut self, row: &Row) -> FdwResult<Option<Row>> {
+        let map = row_to_table_map(row);
+        // release the lock before raising, an error unwinds and would poison it
+        let stored = {
+            let mut tables = MEMORY_TABLE.write().unwrap();
+            let table = tables.entry(self.relid).or_default();
+            table
+                .insert(&self.rowid_name, map)
+                .map(|index| table.get(index).cloned())
         }
+        .map_err(|e| table_error(&self.rowid_name, e))?;
+        Ok(stored.filter(|_| self.returning).map(|row| self.stored_row(&row)))
+    }
 
-        pgrx::pg_sys::ExecStoreVirtualTuple(slot);
-        
-        state.row_count += 1;
-        slot
+    fn insert_batch(&mut self, rows: &[Row]) -> FdwResult<()> {
+        let maps: Vec<TableMap> = rows.iter().map(row_to_table_map).collect();
+        // take the write lock once for the whole batch
+        let res = {
+            let mut tables = MEMORY_TABLE.write().unwrap();
+            tables.entry(self.relid).or_default().insert_batch(&self.rowid_name, maps)
+        };
+        res.map_err(|e| table_error(&self.rowid_name, e))?;
+        log!("Inserted batch of {} rows", rows.len());
+        Ok(())
     }
-}
 
-#[pg_guard]
-extern "C-unwind" fn end_foreign_scan(
-    node: *mut pgrx::pg_sys::ForeignScanState,
-) {
-    log!("---> end_foreign_scan");
-    unsafe {
-        let fdw_state = (*node).fdw_state as *mut DefaultFdwState;
-        if fdw_state.is_null() {
-            return;
+    fn update(&mut self, rowid: &Cell, new_row: &Row) -> FdwResult<Option<Row>> {
+        let rowid_val = stored_value(rowid);
+        let new_row = row_to_table_map(new_row);
+        let stored = {
+            let mut tables = MEMORY_TABLE.write().unwrap();
+            let table = tables.entry(self.relid).or_default();
+            match table.position(&rowid_val) {
+                None => Ok(None),
+                Some(index) => table
+                    .update(index, &self.rowid_name, |target_row| *target_row = new_row)
+                    .map(|_| table.get(index).cloned()),
+            }
+        }
+        .map_err(|e| table_error(&self.rowid_name, e))?;
+        match stored {
+            None => {
+                log!("Row with id {} not found for update", rowid_val);
+                Ok(None)
+            }
+            Some(row) => Ok(Some(self.returned_row(&row))),
         }
-        let mut state = PgBox::<DefaultFdwState>::from_pg(fdw_state);
-        delete_wrappers_memctx(state.tmp_ctx);
-        state.tmp_ctx = ptr::null::<MemoryContextData>() as _;
-        
-        let _ = Box::from_raw(fdw_state);
     }
-}
 
-#[pg_guard]
-extern "C-unwind" fn re_scan_foreign_scan(
-    _node: *mut pgrx::pg_sys::ForeignScanState,
-) {
-    log!("---> re_scan_foreign_scan");
-    // Reset or reinitialize scan state here if needed
-}
+    fn delete(&mut self, rowid: &Cell) -> FdwResult<Option<Row>> {
+        let row_key = stored_value(rowid);
+        let deleted = {
+            let mut tables = MEMORY_TABLE.write().unwrap();
+            tables.entry(self.relid).or_default().delete(&row_key)
+        };
+        match deleted {
+            Some(row) => {
+                log!("Deleted row with id {}", row_key);
+                Ok(Some(self.returned_row(&row)))
+            }
+            None => {
+                log!("Row with id {} not found for deletion", row_key);
+                Ok(None)
+            }
+        }
+    }
 
-#[cfg(feature = "pg13")]
-#[pg_guard]
-unsafe extern "C-unwind" fn add_foreign_update_targets(
-    parsetree: *mut pgrx::pg_sys::Query,
-    _target_rte: *mut pgrx::pg_sys::RangeTblEntry,
-    target_relation: pgrx::pg_sys::Relation,
-) {
-    log!("---> add_foreign_update_targets");
-}
+    fn end_modify(&mut self) -> FdwResult<()> {
+        schedule_snapshot(self.relid);
+        Ok(())
+    }
 
+    fn can_modify_directly(&self, modify: &DirectModify) -> bool {
+        // every row, or the row of a `rowid = constant` qual
+        match &modify.quals[..] {
+            [] => true,
+            [qual] => qual.param.is_none() && self.rowid_lookup(std::slice::from_ref(qual)).is_some(),
+            _ => false,
+        }
+    }
 
-#[cfg(not(feature = "pg13"))]
-#[pg_guard]
-unsafe extern "C-unwind" fn add_foreign_update_targets(
-    root: *mut pgrx::pg_sys::PlannerInfo,
-    rtindex: pgrx::pg_sys::Index,
-    _target_rte: *mut pgrx::pg_sys::RangeTblEntry,
-    target_relation: pgrx::pg_sys::Relation,
-) {
- 
-    log!("---> add_foreign_update_targets");
-    if let Some(attr) = find_rowid_column(target_relation) {
-        // make a Var representing the desired value
-        let var = pg_sys::makeVar(
-            rtindex as _,
-            attr.attnum,
-            attr.atttypid,
-            attr.atttypmod,
-            attr.attcollation,
-            0,
-        );
-
-        // register it as a row-identity column needed by this target rel
-        pg_sys::add_row_identity_var(root, var, rtindex, &attr.attname.data as _);
+    fn modify_directly(&mut self, modify: &DirectModify) -> FdwResult<Vec<Row>> {
+        let rowid = self.rowid_lookup(&modify.quals);
+        let set: Vec<(String, Option<String>)> = modify
+            .set
+            .iter()
+            .map(|(name, cell)| (name.clone(), cell.as_ref().map(stored_value)))
+            .collect();
+
+        // stops at the first row an UPDATE cannot store
+        let mut modified = Vec::new();
+        let res = {
+            let mut tables = MEMORY_TABLE.write().unwrap();
+            let table = tables.entry(self.relid).or_default();
+            let slots = match &rowid {
+                Some(rowid) => table.position(rowid).into_iter().collect(),
+                None => table.live_slots(),
+            };
+            slots.into_iter().try_for_each(|slot| {
+                if modify.operation == CmdType::CMD_DELETE {
+                    modified.extend(table.delete_slot(slot, &self.rowid_name));
+                    return Ok(());
+                }
+                table.update(slot, &self.rowid_name, |target_row| {
+                    for (name, value) in set.iter() {
+                        target_row.insert(name.clone(), value.clone());
+                    }
+                })?;
+                modified.extend(table.get(slot).cloned());
+                Ok(())
+            })
+        };
+        res.map_err(|e| table_error(&self.rowid_name, e))?;
+        log!("Direct modify affected {} rows", modified.len());
+        Ok(modified.iter().map(|row| self.returned_row(row)).collect())
+    }
+
+    fn truncate(&mut self) -> FdwResult<()> {
+        // in-memory tables own no sequences and cannot be referenced by
+        // foreign keys, RESTART IDENTITY and CASCADE have nothing more to do
+        if let Some(table) = MEMORY_TABLE.write().unwrap().get_mut(&self.relid) {
+            log!("Truncating {} rows of table {}", table.len(), self.relid.to_u32());
+            table.clear();
+        }
+        schedule_snapshot(self.relid);
+        Ok(())
+    }
+
+    fn import_schema(stmt: &ImportSchema) -> FdwResult<Vec<String>> {
+        Ok(registry::registered_tables(&stmt.server_name, &stmt.remote_schema)
+            .iter()
+            .filter(|def| stmt.includes(&def.name))
+            .map(|def| registry::create_foreign_table_sql(def, &stmt.server_name))
+            .collect())
     }
 }
 
+impl DefaultFdw {
+    /// Convert a stored row into the columns of the table
+    fn stored_row(&self, map: &TableMap) -> Row {
+        let mut row = Row::new();
+        for column in &self.columns {
+            let cell = stored_cell(map, &column.name, column.typid);
+            row.push(&column.name, cell);
+        }
+        row
+    }
 
-#[pg_guard]
-unsafe extern "C-unwind" fn plan_foreign_modify(
-    root: *mut PlannerInfo,
-    plan: *mut ModifyTable,
-    result_relation: Index,
-    _subplan_index: ::core::ffi::c_int,
-) -> *mut pgrx::pg_sys::List {
-    log!("---> plan_foreign_modify");
-    //	RelOptInfo *rel = find_base_rel(root PlannerInfo, resultRelation Index);
-    let rte = pg_sys::planner_rt_fetch(result_relation, root);
-    let rel = PgRelation::with_lock((*rte).relid, pg_sys::NoLock as _);
-    // search for rowid attribute in tuple descrition
-    let tup_desc = PgTupleDesc::from_relation(&rel);
-
-    let cmd: CmdType::Type = (*plan).operation;
-    match cmd {
-        CmdType::CMD_DELETE => info!("DELETE Ops!"),
-        CmdType::CMD_INSERT => info!("INSERT Ops!"),
-        CmdType::CMD_UPDATE => info!("UPDATE Ops!"),
-        CmdType::CMD_NOTHING => info!("NOTHING Ops!"),
-        _ => info!("{} Ops!",cmd)
-    }
-    
-    let rowid_name = utils::ROWID; //todo
-    for attr in tup_desc.iter().filter(|a| !a.attisdropped) {
-        let attname = pgrx::name_data_to_str(&attr.attname);
-        if attname == rowid_name {
-            let ftable_id = rel.oid();
-            let ctx_name = format!("Wrappers_modify_{}", ftable_id.to_u32());
-            let ctx = create_wrappers_memctx(&ctx_name);
-            let mut state = FdwModifyState::new(ctx);
-
-            state.rowid_name = rowid_name.to_string();
-            state.rowid_typid = attr.atttypid;
-            
-            let p = Box::leak(Box::new(state)) as *mut FdwModifyState;
-            let state: PgBox<FdwModifyState> = PgBox::<FdwModifyState>::from_pg(p as _);
-            return serialize_to_list(state);
-            
+    /// Row returned by UPDATE and DELETE, only converted for RETURNING
+    fn returned_row(&self, map: &TableMap) -> Row {
+        if self.returning {
+            self.stored_row(map)
+        } else {
+            Row::new()
         }
     }
 
-    info!("rowid_column attribute id does not exist");
+    /// Stored value of a `rowid = value` qual, looked up in the rowid index.
+    /// A NULL rowid never matches. Under a nondeterministic collation equal
+    /// rowids may be stored differently, the rows are scanned instead.
+    fn rowid_lookup(&self, quals: &[Qual]) -> Option<String> {
+        quals
+            .iter()
+            .filter(|qual| qual.field == self.rowid_name && qual.operator == "=" && !qual.is_scalar_array_op)
+            .filter(|qual| qual.is_deterministic())
+            .filter(|qual| qual.param.as_ref().map_or(true, |param| param.resolved))
+            .map(|qual| stored_value(&qual.value))
+            .next()
+    }
 
-    ptr::null_mut()
-}
+    /// Rows a scan returns in order: the row of a rowid lookup, or the
+    /// visible rows, sorted on the `sort_key` column if the plan relies on it
+    unsafe fn scan_snapshot(&self) -> Vec<Arc<TableMap>> {
+        let mut rows = {
+            let tables = MEMORY_TABLE.read().unwrap();
+            let Some(table) = tables.get(&self.relid) else {
+                return Vec::new();
+            };
+            let slots = match &self.rowid_filter {
+                Some(rowid) => table.position(rowid).into_iter().collect(),
+                None => table.live_slots(),
+            };
+            table.snapshot(&slots)
+        };
+        let column = self.sort.as_ref().and_then(|sort| self.columns.iter().find(|c| c.name == sort.field));
+        if let (Some(sort), Some(column)) = (&self.sort, column) {
+            sort_rows(&mut rows, sort, column.typid);
+        }
+        rows
+    }
 
-#[pg_guard]
-extern "C-unwind" fn begin_foreign_modify(
-    mtstate: *mut pgrx::pg_sys::ModifyTableState,
-    rinfo: *mut pgrx::pg_sys::ResultRelInfo,
-    fdw_private: *mut pgrx::pg_sys::List,
-    subplan_index: ::std::os::raw::c_int,
-    eflags: ::std::os::raw::c_int,
-) {
-    log!("---> begin_foreign_modify");
-    unsafe {
-        let mut state = deserialize_from_list::<FdwModifyState>(fdw_private as _);
-         // search for rowid attribute number
-        let subplan = (*outer_plan_state(&mut (*mtstate).ps)).plan;
-        let rowid_name_c = PgMemoryContexts::For(state.tmp_ctx).pstrdup(&state.rowid_name);
-        state.rowid_attno = pg_sys::ExecFindJunkAttributeInTlist((*subplan).targetlist, rowid_name_c);
-        info!("Rowid attribute number: {}, state.rowid_name {}", state.rowid_attno, state.rowid_name);
-        (*rinfo).ri_FdwState = state.into_pg() as *mut std::os::raw::c_void;
+    /// Next row of a join, joining both tables on the first call. Cells are
+    /// named after `JoinColumn::label`.
+    fn next_joined_row(&mut self) -> Option<Row> {
+        let join = self.join.as_ref()?;
+        if self.pairs.is_none() {
+            let (pairs, scanned) = join_pairs(join);
+            self.pairs = Some(pairs);
+            self.rows_scanned += scanned;
+        }
+        let (outer, inner) = self.pairs.as_ref()?.get(self.row_count)?;
+        let mut row = Row::new();
+        for column in &join.columns {
+            let stored = if column.outer { Some(outer) } else { inner.as_ref() };
+            let cell = stored.and_then(|r| stored_cell(r, &column.name, column.typid));
+            row.push(&column.label(), cell);
+        }
+        self.row_count += 1;
+        self.rows_returned += 1;
+        Some(row)
     }
 }
 
-#[inline]
-pub(super) unsafe fn outer_plan_state(node: *mut pg_sys::PlanState) -> *mut pg_sys::PlanState {
-    (*node).lefttree
+/// Sort rows on a column with the comparison function of its type. The key
+/// datums are allocated in the current memory context.
+unsafe fn sort_rows(rows: &mut Vec<Arc<TableMap>>, sort: &Sort, typid: Oid) {
+    let mut keyed: Vec<(Option<Datum>, Arc<TableMap>)> = rows
+        .drain(..)
+        .map(|row| {
+            let key = row
+                .get(&sort.field)
+                .and_then(|value| value.as_deref())
+                .map(|value| get_datum(value, typid));
+            (key, row)
+        })
+        .collect();
+
+    let typentry = pg_sys::lookup_type_cache(typid, pg_sys::TYPECACHE_CMP_PROC_FINFO as _);
+    let cmp_proc = &mut (*typentry).cmp_proc_finfo as *mut pg_sys::FmgrInfo;
+    keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) if sort.nulls_first => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) if sort.nulls_first => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => {
+            let cmp = pg_sys::FunctionCall2Coll(cmp_proc, sort.collation, *a, *b).value() as i32;
+            if sort.reversed {
+                0.cmp(&cmp)
+            } else {
+                cmp.cmp(&0)
+            }
+        }
+    });
+    rows.extend(keyed.into_iter().map(|(_, row)| row));
 }
 
+/// Whether equal values of the type have the same image under the
+/// collation, so that stored values can be matched as strings
+unsafe fn equal_image(typid: Oid, collation: Oid) -> bool {
+    let typentry = pg_sys::lookup_type_cache(typid, pg_sys::TYPECACHE_BTREE_OPFAMILY as _);
+    let proc = pg_sys::get_opfamily_proc((*typentry).btree_opf, typid, typid, pg_sys::BTEQUALIMAGE_PROC as _);
+    let Some(typid) = typid.into_datum() else {
+        return false;
+    };
+    proc != pg_sys::InvalidOid && pg_sys::OidFunctionCall1Coll(proc, collation, typid).value() != 0
+}
 
-#[pg_guard]
-extern "C-unwind" fn exec_foreign_insert(
-    _estate: *mut pgrx::pg_sys::EState,
-    _rinfo: *mut pgrx::pg_sys::ResultRelInfo,
-    slot: *mut pgrx::pg_sys::TupleTableSlot,
-    _plan_slot: *mut pgrx::pg_sys::TupleTableSlot,
-) -> *mut pgrx::pg_sys::TupleTableSlot {
-    log!("---> exec_foreign_insert");
-     unsafe {
-        let mut map = TableMap::new();
-        let row: Row = tuple_table_slot_to_row(slot);
-        for i in 0..row.cells.len() {
-            let cell = &row.cells[i];
-            let col_name = &row.cols[i];
-            let val = match cell {
-                Some(c) => c.to_string(),
-                None => "NULL".to_string(),
-            };
-            log!(
-                "Inserted column: {}, value: {}",
-                col_name.to_string(),
-                val
-            );
-            map.insert(col_name.to_string(), val);
+/// Hash join snapshots of both tables, returning the joined rows in outer
+/// table order and the number of rows read
+fn join_pairs(join: &Join) -> (Vec<JoinedRow>, usize) {
+    let (outer_rows, inner_rows) = {
+        let tables = MEMORY_TABLE.read().unwrap();
+        let snapshot = |relid: &Oid| {
+            tables
+                .get(relid)
+                .map(|t| t.snapshot(&t.live_slots()))
+                .unwrap_or_default()
+        };
+        (snapshot(&join.outer), snapshot(&join.inner))
+    };
+    // NULL keys never match
+    let key_of = |row: &TableMap, outer: bool| -> Option<Vec<String>> {
+        join.keys
+            .iter()
+            .map(|key| {
+                let col = if outer { &key.outer } else { &key.inner };
+                row.get(col).cloned().flatten()
+            })
+            .collect()
+    };
+
+    let mut hash: HashMap<Vec<String>, Vec<&Arc<TableMap>>> = HashMap::new();
+    for row in inner_rows.iter() {
+        if let Some(key) = key_of(row, false) {
+            hash.entry(key).or_default().push(row);
         }
+    }
 
-        MEMORY_TABLE.write().unwrap().push(map);
-        (*slot).tts_tableOid = pgrx::pg_sys::InvalidOid;
-        slot
+    let mut pairs = Vec::new();
+    for row in outer_rows.iter() {
+        match key_of(row, true).and_then(|key| hash.get(&key)) {
+            Some(matches) => pairs.extend(matches.iter().map(|inner| (row.clone(), Some(Arc::clone(inner))))),
+            None if join.kind == JoinKind::Left => pairs.push((row.clone(), None)),
+            None => {}
+        }
     }
+    (pairs, outer_rows.len() + inner_rows.len())
 }
 
-#[pg_guard]
-extern "C-unwind" fn exec_foreign_update(
-    _estate: *mut pgrx::pg_sys::EState,
-    rinfo: *mut pgrx::pg_sys::ResultRelInfo,
-    slot: *mut pgrx::pg_sys::TupleTableSlot,
-    plan_slot: *mut pgrx::pg_sys::TupleTableSlot,
-) -> *mut pgrx::pg_sys::TupleTableSlot {
-    log!("---> exec_foreign_update");
-    unsafe {
-        let state = PgBox::<FdwModifyState>::from_pg((*rinfo).ri_FdwState as _);
-        let mut table = MEMORY_TABLE.write().unwrap();
-        PgMemoryContexts::For(state.tmp_ctx).switch_to(|_| {
-            let new_row = tuple_table_slot_to_row(plan_slot);
-            if let Some(rowid_val) = new_row
-                .cols
-                .iter()
-                .enumerate()
-                .find_map(|(i, col)| {
-                    if col == utils::ROWID {
-                        Some(new_row.cells[i].as_ref()?.to_string())
-                    } else {
-                        None
-                    }
-                })
-            {
-                if let Some(index) = table.iter().position(|map| {
-                    map.get(utils::ROWID)
-                        .map(|v| v == &rowid_val)
-                        .unwrap_or(false)
-                }) {
-                    log!("Found row to update with rowid: {:?}", rowid_val);
-                    
-                    let target_row = &mut table[index];
-                    for (col_name, cell) in new_row.cols.iter().zip(new_row.cells.iter()) {
-                        let value = cell.as_ref().map_or("NULL".to_string(), |c| c.to_string());
-                        if let Some(entry) = target_row.get_mut(col_name) {
-                            *entry = value;
-                        }
-                    }
-                }
-            }
-        });
-    }
-    slot
+/// Cell of a column of a stored row, `None` for a missing or NULL value
+fn stored_cell(map: &TableMap, name: &str, typid: Oid) -> Option<Cell> {
+    map.get(name)
+        .and_then(|value| value.as_deref())
+        .and_then(|value| unsafe { text_to_cell(value, typid) })
 }
 
-#[pg_guard]
-extern "C-unwind" fn exec_foreign_delete(
-    _estate: *mut pgrx::pg_sys::EState,
-    rinfo: *mut pgrx::pg_sys::ResultRelInfo,
-    slot: *mut pgrx::pg_sys::TupleTableSlot,
-    plan_slot: *mut pgrx::pg_sys::TupleTableSlot,
-) -> *mut pgrx::pg_sys::TupleTableSlot {
-    log!("---> exec_foreign_delete");
-    unsafe {
-        let state = PgBox::<FdwModifyState>::from_pg((*rinfo).ri_FdwState as _);
-        let mut table = MEMORY_TABLE.write().unwrap();
-        PgMemoryContexts::For(state.tmp_ctx).switch_to(|_| {
-            let rowid_cell  = get_rowid_cell(&state, plan_slot);
-            log!("cell :{:?}",rowid_cell );
-            let Some(rowid) = rowid_cell else {
-                return;
-            };
-            let row_key = rowid.to_string();
-            match table.iter().position(|map| map.get(&state.rowid_name) == Some(&row_key)) {
-                Some(index) => {
-                    table.remove(index);
-                    log!("Deleted row with id {} at index {}", row_key, index);
-                }
-                None => {
-                    log!("Row with id {} not found for deletion", rowid);
-                }
-            }
-        });
+/// Convert the values of a row into a stored row
+fn row_to_table_map(row: &Row) -> TableMap {
+    let mut map = TableMap::new();
+    for (col_name, cell) in row.iter() {
+        let val = cell.as_ref().map(stored_value);
+        log!("Inserted column: {}, value: {:?}", col_name, val);
+        map.insert(col_name.to_string(), val);
     }
-    slot
+    map
+}
+
+/// Value of a positive integer option. Options are checked by
+/// `default_fdw_validator`, a value it would reject counts as unset.
+fn positive_option(options: &HashMap<String, String>, name: &str) -> Option<usize> {
+    options.get(name).and_then(|value| parse_positive_int(value))
+}
 
+/// Value of a boolean option, see `positive_option`
+fn bool_option(options: &HashMap<String, String>, name: &str) -> Option<bool> {
+    options.get(name).and_then(|value| parse_bool(value))
 }
 
-#[pg_guard]
-extern "C-unwind" fn end_foreign_modify(
-    _estate: *mut pgrx::pg_sys::EState,
-    rinfo: *mut pgrx::pg_sys::ResultRelInfo,
-) {
-    log!("---> end_foreign_modify");
-    unsafe { 
-        let fdw_state = (*rinfo).ri_FdwState as *mut FdwModifyState;
-        if fdw_state.is_null() {
-            return;
+/// Capacity and expiry limits from the `max_rows`, `max_bytes`, `eviction`
+/// and `ttl_seconds` options
+fn table_limits(options: &HashMap<String, String>) -> TableLimits {
+    TableLimits {
+        max_rows: positive_option(options, "max_rows"),
+        max_bytes: positive_option(options, "max_bytes"),
+        eviction: options
+            .get("eviction")
+            .and_then(|value| EvictionPolicy::parse(value))
+            .unwrap_or_default(),
+        ttl: positive_option(options, "ttl_seconds").map(|secs| Duration::from_secs(secs as u64)),
+    }
+}
+
+/// Pick up changed limit options and reclaim expired rows before a write
+pub(super) unsafe fn refresh_memory_table(relid: Oid, rowid_name: &str) {
+    let limits = table_limits(&get_foreign_table_options(relid));
+    if let Some(table) = MEMORY_TABLE.write().unwrap().get_mut(&relid) {
+        table.limits = limits;
+        let purged = table.purge_expired(rowid_name);
+        if purged > 0 {
+            log!("Purged {} expired rows of table {}", purged, relid.to_u32());
         }
+    }
+}
+
+/// Create the in-memory table on first access, reloading its snapshot if the
+/// table has a `persist_path`
+pub(super) unsafe fn ensure_memory_table(relid: Oid) {
+    if MEMORY_TABLE.read().unwrap().contains_key(&relid) {
+        return;
+    }
 
-        let mut state: PgBox<FdwModifyState> = PgBox::<FdwModifyState>::from_pg(fdw_state as _);
-        delete_wrappers_memctx(state.tmp_ctx);
-        state.tmp_ctx = ptr::null::<MemoryContextData>() as _;
-        let _ =  Box::from_raw(fdw_state);
+    let options = get_foreign_table_options(relid);
+    let rel = PgRelation::with_lock(relid, pg_sys::NoLock as _);
+    let mut table = MemoryTable::new();
+    table.snapshot_path = persist::snapshot_path(&options, rel.namespace(), rel.name());
+    table.limits = table_limits(&options);
+    table.rowid_name = find_rowid_column(rel.as_ptr())
+        .map(|attr| pgrx::name_data_to_str(&attr.attname).to_string())
+        .unwrap_or_default();
+
+    if let Some(path) = table.snapshot_path.clone().filter(|p| p.exists()) {
+        // version 1 snapshots quoted the values of these types
+        let quoted: HashSet<String> = rel
+            .tuple_desc()
+            .iter()
+            .filter(|a| !a.is_dropped())
+            .filter(|a| {
+                [
+                    pg_sys::TEXTOID,
+                    pg_sys::VARCHAROID,
+                    pg_sys::BPCHAROID,
+                    pg_sys::NAMEOID,
+                    pg_sys::DATEOID,
+                    pg_sys::TIMEOID,
+                    pg_sys::TIMESTAMPOID,
+                    pg_sys::TIMESTAMPTZOID,
+                ]
+                .contains(&a.type_oid().value())
+            })
+            .map(|a| a.name().to_string())
+            .collect();
+        let rows = match persist::load_snapshot(&path, |column| quoted.contains(column)) {
+            Ok(rows) => rows,
+            Err(e) => error!("could not load snapshot \"{}\": {}", path.display(), e),
+        };
+        let rowid_name = table.rowid_name.clone();
+        for row in rows {
+            if let Err(e) = table.insert(&rowid_name, row) {
+                warning!("skipping row of snapshot \"{}\": {:?}", path.display(), e);
+            }
+        }
+        log!("Loaded {} rows from snapshot {}", table.len(), path.display());
     }
+
+    MEMORY_TABLE.write().unwrap().entry(relid).or_insert(table);
 }
 
+/// Write the snapshot of a persistent table when the current transaction
+/// commits. Tables are only written once per transaction.
+pub(super) fn schedule_snapshot(relid: Oid) {
+    let persistent = MEMORY_TABLE
+        .read()
+        .unwrap()
+        .get(&relid)
+        .is_some_and(|t| t.snapshot_path.is_some());
+    if !persistent {
+        return;
+    }
 
-pub unsafe fn get_rowid_cell(
-    state: &FdwModifyState,
-    plan_slot: *mut pg_sys::TupleTableSlot,
-) -> Option<Cell> {
-    let mut is_null: bool = true;
-    let datum = slot_getattr(plan_slot, state.rowid_attno.into(), &mut is_null);
-    Cell::from_polymorphic_datum(datum, is_null, state.rowid_typid)
+    let mut pending = PENDING_SNAPSHOTS.lock().unwrap();
+    if pending.is_empty() {
+        register_xact_callback(PgXactCallbackEvent::PreCommit, write_pending_snapshots);
+        register_xact_callback(PgXactCallbackEvent::Abort, || {
+            PENDING_SNAPSHOTS.lock().unwrap().clear();
+        });
+    }
+    pending.insert(relid);
 }
 
-pub unsafe fn slot_getattr(
-    slot: *mut pg_sys::TupleTableSlot,
-    attnum: c_int,
-    isnull: *mut bool,
-) -> Datum {
-    assert!(attnum > 0);
+/// Write the snapshots scheduled by `schedule_snapshot`, before commit
+pub(super) fn write_pending_snapshots() {
+    let pending: Vec<Oid> = PENDING_SNAPSHOTS.lock().unwrap().drain().collect();
+    let mut failure = None;
+    {
+        let tables = MEMORY_TABLE.read().unwrap();
+        for relid in pending {
+            let Some(table) = tables.get(&relid) else {
+                continue;
+            };
+            let Some(path) = &table.snapshot_path else {
+                continue;
+            };
+            if let Err(e) = persist::save_snapshot(path, table) {
+                failure = Some(format!("could not write snapshot \"{}\": {}", path.display(), e));
+                break;
+            }
+            log!("Wrote {} rows to snapshot {}", table.len(), path.display());
+        }
+    }
 
-    if attnum > (*slot).tts_nvalid.into() {
-        pg_sys::slot_getsomeattrs_int(slot, attnum);
+    // raise after the lock is released
+    if let Some(msg) = failure {
+        error!("{}", msg);
     }
+}
 
-    let attnum = attnum as usize;
-    let values = slice::from_raw_parts((*slot).tts_values, attnum);
-    let nulls = slice::from_raw_parts((*slot).tts_isnull, attnum);
+fn table_error(rowid_name: &str, err: TableError) -> FdwError {
+    match err {
+        TableError::DuplicateRowid(rowid) => FdwError::new(
+            PgSqlErrorCode::ERRCODE_UNIQUE_VIOLATION,
+            format!("duplicate key value violates rowid column \"{}\"", rowid_name),
+        )
+        .with_detail(format!("Key ({})=({}) already exists.", rowid_name, rowid)),
+        TableError::LimitExceeded(msg) => FdwError::new(
+            PgSqlErrorCode::ERRCODE_PROGRAM_LIMIT_EXCEEDED,
+            "default_fdw table capacity exceeded",
+        )
+        .with_detail(msg),
+    }
+}
 
-    *isnull = nulls[attnum - 1];
-    values[attnum - 1]
+pub(super) fn report_table_error(rowid_name: &str, err: TableError) {
+    table_error(rowid_name, err).report()
 }
@@ -0,0 +1,651 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    mem,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use crate::fdw::utils_share::cell::Cell;
+
+/// Stored row, column name -> value in its text form, `None` for NULL
+pub type TableMap = HashMap<String, Option<String>>;
+
+/// Textual value stored for a cell, the text form of its type
+pub fn stored_value(cell: &Cell) -> String {
+    cell.to_string()
+}
+
+/// What to do when an insert would exceed `max_rows` or `max_bytes`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// reject the insert
+    #[default]
+    Error,
+    /// evict the least recently read or written row
+    Lru,
+    /// evict the oldest inserted row
+    Fifo,
+}
+
+impl EvictionPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "error" => Some(EvictionPolicy::Error),
+            "lru" => Some(EvictionPolicy::Lru),
+            "fifo" => Some(EvictionPolicy::Fifo),
+            _ => None,
+        }
+    }
+}
+
+/// Capacity and expiry settings of a table, from the `max_rows`, `max_bytes`,
+/// `eviction` and `ttl_seconds` options
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TableLimits {
+    pub max_rows: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub eviction: EvictionPolicy,
+    pub ttl: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableError {
+    /// another row already uses the rowid value
+    DuplicateRowid(String),
+    /// the row does not fit into `max_rows`/`max_bytes`
+    LimitExceeded(String),
+}
+
+#[derive(Debug)]
+struct RowMeta {
+    written_at: Instant,
+    /// logical clock of the last read or write, drives LRU eviction
+    last_access: AtomicU64,
+    bytes: usize,
+}
+
+/// In-memory rows of a default_fdw table.
+///
+/// Rows are immutable and shared: an update stores a new copy, so a scan can
+/// keep reading the rows it took with `snapshot` while the statement
+/// modifies the table. Deleted rows leave an empty slot behind so that slot
+/// numbers stay stable. Rows older than the TTL are invisible and reclaimed
+/// by `purge_expired`.
+///
+/// Rows live on the Rust heap, not in a Postgres memory context: they outlive
+/// every statement and transaction, while the contexts of
+/// `create_wrappers_memctx` only hold the temporary data of one scan or
+/// modification. Their size is accounted by `row_bytes` instead.
+#[derive(Debug, Default)]
+pub struct MemoryTable {
+    rows: Vec<Option<Arc<TableMap>>>,
+    /// per slot bookkeeping, parallel to `rows`
+    meta: Vec<Option<RowMeta>>,
+    /// rowid value -> slot in `rows`
+    rowid_index: HashMap<String, usize>,
+    live_rows: usize,
+    /// approximate heap size of the live rows
+    bytes: usize,
+    /// first slot that may still hold a live row, for FIFO eviction
+    head: usize,
+    clock: AtomicU64,
+    /// last access tick -> slot of every stored row, least recently used
+    /// first. Reads update it through a shared reference.
+    lru: Mutex<BTreeMap<u64, usize>>,
+    /// time of the last insert, update or delete
+    modified_at: Option<SystemTime>,
+    /// name of the rowid column, informational
+    pub rowid_name: String,
+    pub limits: TableLimits,
+    /// snapshot file written at commit, see `persist_path`
+    pub snapshot_path: Option<PathBuf>,
+}
+
+impl MemoryTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of slots, including deleted ones
+    pub fn slot_count(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Number of visible rows, expired rows not purged yet left out
+    pub fn len(&self) -> usize {
+        match self.limits.ttl {
+            Some(_) => (0..self.rows.len()).filter(|slot| self.is_visible(*slot)).count(),
+            None => self.live_rows,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Approximate heap size of the stored rows in bytes, expired rows not
+    /// purged yet included
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Time of the last modification, `None` if never modified
+    pub fn modified_at(&self) -> Option<SystemTime> {
+        self.modified_at
+    }
+
+    /// Get the row stored in `slot`, `None` if the slot was deleted or the
+    /// row expired. Counts as an access for LRU eviction.
+    pub fn get(&self, slot: usize) -> Option<&TableMap> {
+        if !self.is_visible(slot) {
+            return None;
+        }
+        self.touch(slot);
+        self.rows.get(slot).and_then(|r| r.as_deref())
+    }
+
+    /// Visible rows of `slots` in the given order. The rows stay unchanged
+    /// for the caller whatever happens to the table afterwards.
+    pub fn snapshot(&self, slots: &[usize]) -> Vec<Arc<TableMap>> {
+        slots
+            .iter()
+            .filter(|slot| self.is_visible(**slot))
+            .filter_map(|slot| {
+                self.touch(*slot);
+                self.rows[*slot].clone()
+            })
+            .collect()
+    }
+
+    /// Iterate visible rows in slot order
+    pub fn iter(&self) -> impl Iterator<Item = &TableMap> {
+        self.rows
+            .iter()
+            .enumerate()
+            .filter(|(slot, _)| self.is_visible(*slot))
+            .filter_map(|(_, r)| r.as_deref())
+    }
+
+    /// Find the slot of the visible row with the given rowid value
+    pub fn position(&self, rowid: &str) -> Option<usize> {
+        self.rowid_index
+            .get(rowid)
+            .copied()
+            .filter(|slot| self.is_visible(*slot))
+    }
+
+    /// Slots of all visible rows
+    pub fn live_slots(&self) -> Vec<usize> {
+        (0..self.rows.len())
+            .filter(|slot| self.is_visible(*slot))
+            .collect()
+    }
+
+    /// Append a row, evicting other rows first if the table is full and the
+    /// eviction policy allows it. NULL rowids are never indexed.
+    pub fn insert(&mut self, rowid_name: &str, row: TableMap) -> Result<usize, TableError> {
+        let rowid = indexed_rowid(&row, rowid_name);
+        if let Some(rowid) = &rowid {
+            if let Some(slot) = self.rowid_index.get(rowid).copied() {
+                if self.is_visible(slot) {
+                    return Err(TableError::DuplicateRowid(rowid.clone()));
+                }
+                // the rowid is taken by an expired row
+                self.take_slot(slot, rowid_name);
+            }
+        }
+
+        let bytes = row_bytes(&row);
+        self.make_room(bytes, rowid_name)?;
+
+        let slot = self.rows.len();
+        if let Some(rowid) = rowid {
+            self.rowid_index.insert(rowid, slot);
+        }
+        let tick = self.tick();
+        self.rows.push(Some(Arc::new(row)));
+        self.meta.push(Some(RowMeta {
+            written_at: Instant::now(),
+            last_access: AtomicU64::new(tick),
+            bytes,
+        }));
+        self.lru.get_mut().unwrap().insert(tick, slot);
+        self.live_rows += 1;
+        self.bytes += bytes;
+        self.modified_at = Some(SystemTime::now());
+        Ok(slot)
+    }
+
+    /// Modify the row in `slot` in place and keep the rowid index up to date.
+    /// When the new rowid collides with another row, or the new row does not
+    /// fit into `max_bytes`, nothing is changed.
+    pub fn update<F>(&mut self, slot: usize, rowid_name: &str, f: F) -> Result<(), TableError>
+    where
+        F: FnOnce(&mut TableMap),
+    {
+        if !self.is_visible(slot) {
+            return Ok(());
+        }
+        let Some(Some(row)) = self.rows.get(slot) else {
+            return Ok(());
+        };
+
+        let old_rowid = indexed_rowid(row, rowid_name);
+        let mut new_row = TableMap::clone(row);
+        f(&mut new_row);
+        let new_rowid = indexed_rowid(&new_row, rowid_name);
+
+        if old_rowid != new_rowid {
+            if let Some(rowid) = &new_rowid {
+                if self.position(rowid).is_some() {
+                    return Err(TableError::DuplicateRowid(rowid.clone()));
+                }
+            }
+        }
+
+        let old_bytes = self.meta[slot].as_ref().map_or(0, |m| m.bytes);
+        let new_bytes = row_bytes(&new_row);
+        if let Some(max_bytes) = self.limits.max_bytes {
+            if self.bytes - old_bytes + new_bytes > max_bytes {
+                return Err(TableError::LimitExceeded(format!(
+                    "updated row does not fit into max_bytes {}",
+                    max_bytes
+                )));
+            }
+        }
+
+        if old_rowid != new_rowid {
+            if let Some(rowid) = &old_rowid {
+                self.rowid_index.remove(rowid);
+            }
+            if let Some(rowid) = new_rowid {
+                // drop an expired row still holding the new rowid
+                if let Some(expired) = self.rowid_index.get(&rowid).copied() {
+                    self.take_slot(expired, rowid_name);
+                }
+                self.rowid_index.insert(rowid, slot);
+            }
+        }
+
+        if let Some(meta) = self.meta[slot].as_mut() {
+            meta.written_at = Instant::now();
+            meta.bytes = new_bytes;
+        }
+        self.touch(slot);
+        self.bytes = self.bytes - old_bytes + new_bytes;
+        self.rows[slot] = Some(Arc::new(new_row));
+        self.modified_at = Some(SystemTime::now());
+        Ok(())
+    }
+
+    /// Delete the visible row with the given rowid value and return it
+    pub fn delete(&mut self, rowid: &str) -> Option<TableMap> {
+        let slot = self.position(rowid)?;
+        self.rowid_index.remove(rowid);
+        self.take_slot(slot, "")
+    }
+
+    /// Delete the row stored in `slot` and return it
+    pub fn delete_slot(&mut self, slot: usize, rowid_name: &str) -> Option<TableMap> {
+        if !self.is_visible(slot) {
+            return None;
+        }
+        self.take_slot(slot, rowid_name)
+    }
+
+    /// Reclaim the slots of expired rows, returns the number of rows removed
+    pub fn purge_expired(&mut self, rowid_name: &str) -> usize {
+        if self.limits.ttl.is_none() {
+            return 0;
+        }
+        let expired: Vec<usize> = (0..self.rows.len())
+            .filter(|slot| self.rows[*slot].is_some() && !self.is_visible(*slot))
+            .collect();
+        for slot in expired.iter() {
+            self.take_slot(*slot, rowid_name);
+        }
+        expired.len()
+    }
+
+    /// Append rows, all of them or none: a duplicate rowid, in the table or
+    /// within the batch, or a batch the `error` policy cannot fit fails before
+    /// any row is stored
+    pub fn insert_batch(&mut self, rowid_name: &str, rows: Vec<TableMap>) -> Result<(), TableError> {
+        let mut batch_rowids = HashSet::new();
+        for rowid in rows.iter().filter_map(|row| indexed_rowid(row, rowid_name)) {
+            let taken = self.rowid_index.get(&rowid).is_some_and(|slot| self.is_visible(*slot));
+            if taken || !batch_rowids.insert(rowid.clone()) {
+                return Err(TableError::DuplicateRowid(rowid));
+            }
+        }
+
+        let sizes: Vec<usize> = rows.iter().map(row_bytes).collect();
+        if let Some((max_bytes, bytes)) = self.limits.max_bytes.zip(sizes.iter().max()) {
+            if *bytes > max_bytes {
+                return Err(TableError::LimitExceeded(format!(
+                    "row of {} bytes does not fit into max_bytes {}",
+                    bytes, max_bytes
+                )));
+            }
+        }
+        if self.limits.eviction == EvictionPolicy::Error {
+            self.purge_expired(rowid_name);
+            if let Some(max_rows) = self.limits.max_rows.filter(|max| self.live_rows + rows.len() > *max) {
+                return Err(TableError::LimitExceeded(format!("table is full, max_rows is {}", max_rows)));
+            }
+            let bytes: usize = sizes.iter().sum();
+            if let Some(max_bytes) = self.limits.max_bytes.filter(|max| self.bytes + bytes > *max) {
+                return Err(TableError::LimitExceeded(format!("table is full, max_bytes is {}", max_bytes)));
+            }
+        }
+
+        rows.into_iter().try_for_each(|row| self.insert(rowid_name, row).map(|_| ()))
+    }
+
+    /// Remove all rows
+    pub fn clear(&mut self) {
+        self.rows.clear();
+        self.meta.clear();
+        self.rowid_index.clear();
+        self.lru.get_mut().unwrap().clear();
+        self.live_rows = 0;
+        self.bytes = 0;
+        self.head = 0;
+        self.modified_at = Some(SystemTime::now());
+    }
+
+    fn is_visible(&self, slot: usize) -> bool {
+        match self.meta.get(slot) {
+            Some(Some(meta)) => match self.limits.ttl {
+                Some(ttl) => meta.written_at.elapsed() < ttl,
+                None => true,
+            },
+            _ => false,
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn touch(&self, slot: usize) {
+        if let Some(Some(meta)) = self.meta.get(slot) {
+            let tick = self.tick();
+            let last = meta.last_access.swap(tick, Ordering::Relaxed);
+            let mut lru = self.lru.lock().unwrap();
+            lru.remove(&last);
+            lru.insert(tick, slot);
+        }
+    }
+
+    /// Evict rows until a row of `bytes` fits, or fail under the `error`
+    /// policy
+    fn make_room(&mut self, bytes: usize, rowid_name: &str) -> Result<(), TableError> {
+        if let Some(max_bytes) = self.limits.max_bytes {
+            if bytes > max_bytes {
+                return Err(TableError::LimitExceeded(format!(
+                    "row of {} bytes does not fit into max_bytes {}",
+                    bytes, max_bytes
+                )));
+            }
+        }
+
+        let mut purged = false;
+        loop {
+            let rows_full = self.limits.max_rows.is_some_and(|max| self.live_rows + 1 > max);
+            let bytes_full = self.limits.max_bytes.is_some_and(|max| self.bytes + bytes > max);
+            if !rows_full && !bytes_full {
+                return Ok(());
+            }
+
+            // expired rows go first
+            if !purged {
+                purged = true;
+                if self.purge_expired(rowid_name) > 0 {
+                    continue;
+                }
+            }
+
+            let victim = match self.limits.eviction {
+                EvictionPolicy::Error => None,
+                EvictionPolicy::Fifo => self.fifo_victim(),
+                EvictionPolicy::Lru => self.lru_victim(),
+            };
+            let Some(victim) = victim else {
+                return Err(TableError::LimitExceeded(if rows_full {
+                    format!("table is full, max_rows is {}", self.limits.max_rows.unwrap_or_default())
+                } else {
+                    format!("table is full, max_bytes is {}", self.limits.max_bytes.unwrap_or_default())
+                }));
+            };
+            self.take_slot(victim, rowid_name);
+        }
+    }
+
+    fn fifo_victim(&mut self) -> Option<usize> {
+        while self.head < self.rows.len() && self.rows[self.head].is_none() {
+            self.head += 1;
+        }
+        (self.head < self.rows.len()).then_some(self.head)
+    }
+
+    fn lru_victim(&mut self) -> Option<usize> {
+        self.lru.get_mut().unwrap().values().next().copied()
+    }
+
+    /// Remove whatever is stored in `slot`, dropping its rowid from the index
+    /// when `rowid_name` is given
+    fn take_slot(&mut self, slot: usize, rowid_name: &str) -> Option<TableMap> {
+        let row = self.rows.get_mut(slot)?.take()?;
+        // scans may still share the row
+        let row = Arc::try_unwrap(row).unwrap_or_else(|shared| TableMap::clone(&shared));
+        if let Some(rowid) = indexed_rowid(&row, rowid_name) {
+            if self.rowid_index.get(&rowid) == Some(&slot) {
+                self.rowid_index.remove(&rowid);
+            }
+        }
+        if let Some(meta) = self.meta[slot].take() {
+            self.bytes -= meta.bytes;
+            self.lru.get_mut().unwrap().remove(&meta.last_access.into_inner());
+        }
+        self.live_rows -= 1;
+        self.modified_at = Some(SystemTime::now());
+        // every slot is empty, reclaim them
+        if self.live_rows == 0 {
+            self.clear();
+        }
+        Some(row)
+    }
+}
+
+fn indexed_rowid(row: &TableMap, rowid_name: &str) -> Option<String> {
+    row.get(rowid_name).cloned().flatten()
+}
+
+/// Approximate heap size of a stored row
+fn row_bytes(row: &TableMap) -> usize {
+    row.iter()
+        .map(|(k, v)| k.len() + v.as_ref().map_or(0, String::len) + 2 * mem::size_of::<String>())
+        .sum()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use super::*;
+
+    fn row(id: &str, col: &str) -> TableMap {
+        let mut map = TableMap::new();
+        map.insert("id".to_string(), Some(id.to_string()));
+        map.insert("col".to_string(), Some(col.to_string()));
+        map
+    }
+
+    fn null_rowid_row(col: &str) -> TableMap {
+        let mut map = row("", col);
+        map.insert("id".to_string(), None);
+        map
+    }
+
+    #[test]
+    fn test_memory_table_insert_duplicate_rowid() {
+        let mut table = MemoryTable::new();
+        assert_eq!(table.insert("id", row("1", "a")), Ok(0));
+        assert_eq!(
+            table.insert("id", row("1", "b")),
+            Err(TableError::DuplicateRowid("1".to_string()))
+        );
+        assert_eq!(table.insert("id", null_rowid_row("c")), Ok(1));
+        assert_eq!(table.insert("id", null_rowid_row("d")), Ok(2));
+        assert_eq!(table.len(), 3);
+    }
+
+    #[test]
+    fn test_memory_table_insert_batch_all_or_none() {
+        let mut table = MemoryTable::new();
+        table.insert("id", row("1", "a")).unwrap();
+        assert_eq!(
+            table.insert_batch("id", vec![row("2", "b"), row("1", "c")]),
+            Err(TableError::DuplicateRowid("1".to_string()))
+        );
+        assert_eq!(
+            table.insert_batch("id", vec![row("3", "d"), row("3", "e")]),
+            Err(TableError::DuplicateRowid("3".to_string()))
+        );
+        assert_eq!(table.len(), 1);
+
+        table.limits.max_rows = Some(2);
+        assert!(matches!(
+            table.insert_batch("id", vec![row("2", "b"), row("3", "c")]),
+            Err(TableError::LimitExceeded(_))
+        ));
+        assert_eq!(table.len(), 1);
+        table.insert_batch("id", vec![row("2", "b")]).unwrap();
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn test_memory_table_delete_keeps_slots() {
+        let mut table = MemoryTable::new();
+        table.insert("id", row("1", "a")).unwrap();
+        table.insert("id", row("2", "b")).unwrap();
+        table.insert("id", row("3", "c")).unwrap();
+
+        assert!(table.delete("1").is_some());
+        assert!(table.delete("1").is_none());
+        assert_eq!(table.slot_count(), 3);
+        assert_eq!(table.position("3"), Some(2));
+        assert!(table.get(0).is_none());
+
+        table.delete("2");
+        table.delete("3");
+        assert!(table.is_empty());
+        assert_eq!(table.slot_count(), 0);
+        assert_eq!(table.bytes(), 0);
+    }
+
+    #[test]
+    fn test_memory_table_snapshot() {
+        let mut table = MemoryTable::new();
+        table.insert("id", row("1", "a")).unwrap();
+        table.insert("id", row("2", "b")).unwrap();
+        let snapshot = table.snapshot(&table.live_slots());
+
+        table
+            .update(0, "id", |r| {
+                r.insert("col".to_string(), Some("x".to_string()));
+            })
+            .unwrap();
+        table.delete("2");
+        table.insert("id", row("3", "c")).unwrap();
+
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].get("col"), Some(&Some("a".to_string())));
+        assert_eq!(snapshot[1].get("id"), Some(&Some("2".to_string())));
+        assert_eq!(table.get(0).unwrap().get("col"), Some(&Some("x".to_string())));
+    }
+
+    #[test]
+    fn test_memory_table_update_rowid() {
+        let mut table = MemoryTable::new();
+        table.insert("id", row("1", "a")).unwrap();
+        table.insert("id", row("2", "b")).unwrap();
+
+        let res = table.update(0, "id", |r| {
+            r.insert("id".to_string(), Some("2".to_string()));
+        });
+        assert_eq!(res, Err(TableError::DuplicateRowid("2".to_string())));
+        assert_eq!(table.position("1"), Some(0));
+
+        table
+            .update(0, "id", |r| {
+                r.insert("id".to_string(), Some("5".to_string()));
+            })
+            .unwrap();
+        assert_eq!(table.position("1"), None);
+        assert_eq!(table.position("5"), Some(0));
+    }
+
+    #[test]
+    fn test_memory_table_max_rows() {
+        let mut table = MemoryTable::new();
+        table.limits.max_rows = Some(2);
+        table.insert("id", row("1", "a")).unwrap();
+        table.insert("id", row("2", "b")).unwrap();
+        assert!(matches!(
+            table.insert("id", row("3", "c")),
+            Err(TableError::LimitExceeded(_))
+        ));
+
+        table.limits.eviction = EvictionPolicy::Fifo;
+        table.insert("id", row("3", "c")).unwrap();
+        assert_eq!(table.position("1"), None);
+        assert_eq!(table.len(), 2);
+
+        table.limits.eviction = EvictionPolicy::Lru;
+        // reading row 2 makes row 3 the least recently used
+        let slot = table.position("2").unwrap();
+        table.get(slot);
+        table.insert("id", row("4", "d")).unwrap();
+        assert!(table.position("2").is_some());
+        assert_eq!(table.position("3"), None);
+    }
+
+    #[test]
+    fn test_memory_table_max_bytes() {
+        let mut table = MemoryTable::new();
+        let size = row_bytes(&row("1", "a"));
+        table.limits.max_bytes = Some(size * 2);
+        table.limits.eviction = EvictionPolicy::Fifo;
+        for id in 1..=5 {
+            table.insert("id", row(&id.to_string(), "a")).unwrap();
+        }
+        assert_eq!(table.len(), 2);
+        assert!(table.bytes() <= size * 2);
+
+        let mut big = row("9", "a");
+        big.insert("col".to_string(), Some("x".repeat(size * 2)));
+        assert!(matches!(table.insert("id", big), Err(TableError::LimitExceeded(_))));
+    }
+
+    #[test]
+    fn test_memory_table_ttl() {
+        let mut table = MemoryTable::new();
+        table.limits.ttl = Some(Duration::from_millis(20));
+        table.insert("id", row("1", "a")).unwrap();
+        assert_eq!(table.iter().count(), 1);
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(table.iter().count(), 0);
+        assert_eq!(table.len(), 0);
+        assert_eq!(table.position("1"), None);
+
+        // the expired rowid can be reused
+        table.insert("id", row("1", "b")).unwrap();
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.purge_expired("id"), 0);
+    }
+}
@@ -1,3 +1,7 @@
 mod handlers;
 mod tests;
 mod state;
+mod memory_table;
+mod persist;
+mod registry;
+mod admin;
@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::fdw::default_fdw::memory_table::{MemoryTable, TableMap};
+
+/// server or table option naming the snapshot file (or a directory of snapshots)
+pub const PERSIST_PATH_OPTION: &str = "persist_path";
+
+/// Version 1 snapshots kept character, date and time values in single
+/// quotes, versions 1 and 2 wrote NULL as the string `NULL`
+const SNAPSHOT_VERSION: u32 = 3;
+
+/// Rows are written as stored, each value in the text form of its column
+/// type or `null`, rather than with the typed encoding of `cell_serde`.
+/// Stored rows are already that text, so saving at commit needs no type
+/// lookups and no conversion of every value.
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    rows: Vec<&'a TableMap>,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    version: u32,
+    rows: Vec<TableMap>,
+}
+
+/// Resolve the snapshot file of a table from its options.
+///
+/// `persist_path` may name a file, or an existing directory in which case the
+/// snapshot is stored as `<schema>.<table>.json` inside it. That makes it
+/// possible to set the option once on the server.
+pub fn snapshot_path(
+    options: &HashMap<String, String>,
+    schema: &str,
+    table: &str,
+) -> Option<PathBuf> {
+    let path = options.get(PERSIST_PATH_OPTION)?;
+    if path.is_empty() {
+        return None;
+    }
+
+    let path = PathBuf::from(path);
+    if path.is_dir() {
+        Some(path.join(format!("{}.{}.json", schema, table)))
+    } else {
+        Some(path)
+    }
+}
+
+/// Read the rows of a snapshot file. Rows of older versions are brought to
+/// the current form: the string `NULL` becomes NULL, and the values of the
+/// columns `quoted` tells were stored in single quotes by version 1 lose
+/// them. Other values are kept as they were written.
+pub fn load_snapshot(path: &Path, quoted: impl Fn(&str) -> bool) -> io::Result<Vec<TableMap>> {
+    let reader = BufReader::new(File::open(path)?);
+    let Snapshot { version, mut rows } = serde_json::from_reader(reader)?;
+    match version {
+        1 | 2 => {
+            for (column, value) in rows.iter_mut().flat_map(|row| row.iter_mut()) {
+                match value {
+                    Some(v) if v == "NULL" => *value = None,
+                    Some(v) if version == 1 && quoted(column) => {
+                        if let Some(unquoted) = v.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+                            *v = unquoted.to_string();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Ok(rows)
+        }
+        SNAPSHOT_VERSION => Ok(rows),
+        version => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported snapshot version {}", version),
+        )),
+    }
+}
+
+/// Write all live rows of `table` to `path`.
+///
+/// The snapshot is written to a temporary file first and renamed over the old
+/// one, so a crash never leaves a half-written snapshot behind.
+pub fn save_snapshot(path: &Path, table: &MemoryTable) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        let snapshot = SnapshotRef {
+            version: SNAPSHOT_VERSION,
+            rows: table.iter().collect(),
+        };
+        serde_json::to_writer(&mut writer, &snapshot)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let path = std::env::temp_dir().join(format!("default_fdw_snapshot_{}.json", std::process::id()));
+        let mut table = MemoryTable::new();
+        for (id, col) in [("1", Some("a")), ("2", None), ("3", Some("NULL"))] {
+            let mut row = TableMap::new();
+            row.insert("id".to_string(), Some(id.to_string()));
+            row.insert("col".to_string(), col.map(str::to_string));
+            table.insert("id", row).unwrap();
+        }
+        table.delete("1");
+
+        save_snapshot(&path, &table).unwrap();
+        let rows = load_snapshot(&path, |_| true).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get("id"), Some(&Some("2".to_string())));
+        assert_eq!(rows[0].get("col"), Some(&None));
+        assert_eq!(rows[1].get("col"), Some(&Some("NULL".to_string())));
+    }
+
+    #[test]
+    fn test_snapshot_version_1_unquoted() {
+        let path = std::env::temp_dir().join(format!("default_fdw_snapshot_v1_{}.json", std::process::id()));
+        fs::write(
+            &path,
+            r#"{"version":1,"rows":[
+                {"id":"1","col":"''it's''","day":"'2024-01-31'","note":"'x'"},
+                {"id":"2","col":"'NULL'","day":"NULL","note":"NULL"}
+            ]}"#,
+        )
+        .unwrap();
+        let rows = load_snapshot(&path, |column| column == "col" || column == "day").unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows[0].get("id"), Some(&Some("1".to_string())));
+        // one pair of quotes is dropped, quotes of the value itself stay
+        assert_eq!(rows[0].get("col"), Some(&Some("'it's'".to_string())));
+        assert_eq!(rows[0].get("day"), Some(&Some("2024-01-31".to_string())));
+        // columns that were not quoted keep their value as written
+        assert_eq!(rows[0].get("note"), Some(&Some("'x'".to_string())));
+        // the text 'NULL' is told from NULL by its quotes
+        assert_eq!(rows[1].get("col"), Some(&Some("NULL".to_string())));
+        assert_eq!(rows[1].get("day"), Some(&None));
+        assert_eq!(rows[1].get("note"), Some(&None));
+    }
+
+    #[test]
+    fn test_snapshot_path_in_directory() {
+        let mut options = HashMap::new();
+        assert_eq!(snapshot_path(&options, "public", "hello"), None);
+
+        let dir = std::env::temp_dir();
+        options.insert(PERSIST_PATH_OPTION.to_string(), dir.to_string_lossy().to_string());
+        assert_eq!(
+            snapshot_path(&options, "public", "hello"),
+            Some(dir.join("public.hello.json"))
+        );
+    }
+}
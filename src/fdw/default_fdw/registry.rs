@@ -0,0 +1,156 @@
+use pgrx::{prelude::*, spi};
+
+use crate::fdw::utils_share::utils::{list_items, string_to_cstr};
+
+/// Column definitions of an in-memory table that can be imported with
+/// `IMPORT FOREIGN SCHEMA`
+#[derive(Debug, Clone)]
+pub struct TableDef {
+    pub name: String,
+    pub columns: String,
+}
+
+/// Register an in-memory table on a default_fdw server, so that it can be
+/// imported into one or more local schemas:
+///
+/// ```sql
+/// SELECT default_fdw_create_table('my_default_server', 'hello', 'id bigint, col text');
+/// IMPORT FOREIGN SCHEMA public FROM SERVER my_default_server INTO app;
+/// ```
+///
+/// Definitions are kept in the `default_fdw_registry` table of the
+/// extension, registering a table again replaces its columns.
+#[pg_extern]
+pub fn default_fdw_create_table(
+    server_name: &str,
+    table_name: &str,
+    columns: &str,
+    remote_schema: default!(&str, "'public'"),
+) {
+    let server = unsafe { pg_sys::GetForeignServerByName(string_to_cstr(server_name).as_ptr(), true) };
+    if server.is_null() {
+        ereport!(
+            PgLogLevel::ERROR,
+            PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+            format!("server \"{}\" does not exist", server_name)
+        );
+    }
+    if table_name.is_empty() || columns.trim().is_empty() {
+        ereport!(
+            PgLogLevel::ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            "table name and column definitions must not be empty"
+        );
+    }
+    check_columns(columns);
+
+    let res = Spi::connect_mut(|client| {
+        client.update(
+            "INSERT INTO default_fdw_registry (srvid, remote_schema, table_name, columns)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (srvid, remote_schema, table_name) DO UPDATE SET columns = excluded.columns",
+            None,
+            &[
+                unsafe { (*server).serverid }.into(),
+                remote_schema.into(),
+                table_name.into(),
+                columns.trim().into(),
+            ],
+        )
+        .map(|_| ())
+    });
+    if let Err(e) = res {
+        error!("could not register table \"{}\": {}", table_name, e);
+    }
+}
+
+/// Registered tables of a server in `remote_schema`, ordered by name
+pub fn registered_tables(server_name: &str, remote_schema: &str) -> Vec<TableDef> {
+    let res = Spi::connect(|client| {
+        client
+            .select(
+                "SELECT r.table_name, r.columns
+                 FROM default_fdw_registry r JOIN pg_foreign_server s ON s.oid = r.srvid
+                 WHERE s.srvname = $1 AND r.remote_schema = $2
+                 ORDER BY r.table_name",
+                None,
+                &[server_name.into(), remote_schema.into()],
+            )?
+            .map(|row| {
+                Ok(TableDef {
+                    name: row.get::<String>(1)?.unwrap_or_default(),
+                    columns: row.get::<String>(2)?.unwrap_or_default(),
+                })
+            })
+            .collect::<spi::SpiResult<Vec<_>>>()
+    });
+    res.unwrap_or_else(|e| error!("could not read registered tables: {}", e))
+}
+
+/// Build the `CREATE FOREIGN TABLE` statement of a registered table
+pub fn create_foreign_table_sql(def: &TableDef, server_name: &str) -> String {
+    format!(
+        "CREATE FOREIGN TABLE {} ({}) SERVER {}",
+        spi::quote_identifier(&def.name),
+        def.columns,
+        spi::quote_identifier(server_name)
+    )
+}
+
+/// Check that `columns` is a list of column definitions and nothing else,
+/// it is pasted into the `CREATE FOREIGN TABLE` statement of an import
+fn check_columns(columns: &str) {
+    let sql = string_to_cstr(&format!("CREATE TABLE t ({})", columns));
+    let valid = unsafe {
+        #[cfg(feature = "pg13")]
+        let stmts = pg_sys::raw_parser(sql.as_ptr());
+        #[cfg(not(feature = "pg13"))]
+        let stmts = pg_sys::raw_parser(sql.as_ptr(), pg_sys::RawParseMode::RAW_PARSE_DEFAULT);
+        match list_items(stmts)[..] {
+            [stmt] => {
+                let node = (*(stmt as *mut pg_sys::RawStmt)).stmt;
+                (*node).type_ == pg_sys::NodeTag::T_CreateStmt && only_column_defs(node as *mut pg_sys::CreateStmt)
+            }
+            _ => false,
+        }
+    };
+    if !valid {
+        ereport!(
+            PgLogLevel::ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("invalid column definitions \"{}\"", columns),
+            "Only column names, types and column options are allowed."
+        );
+    }
+}
+
+/// Whether a parsed `CREATE TABLE` has column definitions and no other clause
+unsafe fn only_column_defs(stmt: *mut pg_sys::CreateStmt) -> bool {
+    let stmt = &*stmt;
+    stmt.inhRelations.is_null()
+        && stmt.partspec.is_null()
+        && stmt.partbound.is_null()
+        && stmt.ofTypename.is_null()
+        && stmt.constraints.is_null()
+        && stmt.options.is_null()
+        && stmt.accessMethod.is_null()
+        && stmt.tablespacename.is_null()
+        && stmt.oncommit == pg_sys::OnCommitAction::ONCOMMIT_NOOP
+        && list_items(stmt.tableElts)
+            .iter()
+            .all(|elt| (*(*elt as *mut pg_sys::Node)).type_ == pg_sys::NodeTag::T_ColumnDef)
+}
+
+extension_sql!(
+    r#"
+CREATE TABLE default_fdw_registry (
+    srvid oid NOT NULL,
+    remote_schema text NOT NULL,
+    table_name text NOT NULL,
+    columns text NOT NULL,
+    PRIMARY KEY (srvid, remote_schema, table_name)
+);
+SELECT pg_catalog.pg_extension_config_dump('default_fdw_registry', '');
+"#,
+    name = "default_fdw_registry",
+);
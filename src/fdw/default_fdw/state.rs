@@ -1,44 +1,41 @@
-use std::collections::HashMap;
-use pgrx::pg_sys::{Oid, Datum, MemoryContext};
+use std::sync::Arc;
+use pgrx::pg_sys::Oid;
+use crate::fdw::default_fdw::memory_table::TableMap;
+use crate::fdw::utils_share::{fdw::Column, join::Join, limit::Limit, qual::Qual, sort::Sort};
 
-#[repr(C)]
-pub struct DefaultFdwState {
-    pub row_count: usize,
-    pub values: Vec<Datum>,
-    pub nulls: Vec<bool>,
-    pub tmp_ctx: MemoryContext,
-    pub header_name_to_colno: HashMap<String, usize> ,
-}
-
-#[repr(C)]
+/// Scan, join or modification of an in-memory table through the
+/// `ForeignDataWrapper` trait
 #[derive(Debug)]
-pub struct FdwModifyState {
-    // row id attribute number and type id
+pub struct DefaultFdw {
+    pub relid: Oid,
     pub rowid_name: String,
-    pub rowid_attno: pgrx::pg_sys::AttrNumber,
-    pub rowid_typid: Oid,
-    pub tmp_ctx: MemoryContext,
-}
-
-impl DefaultFdwState {
-    pub fn new(tmp_ctx: MemoryContext) -> Self {
-        DefaultFdwState {
-            row_count: 0,
-            values: Vec::new(),
-            nulls: Vec::new(),
-            tmp_ctx,
-            header_name_to_colno : HashMap::default()
-        }
-    }
+    pub columns: Vec<Column>,
+    pub batch_size: usize,
+    /// whether modified rows are returned for RETURNING
+    pub returning: bool,
+    /// value of a `rowid = const` qual, looked up in the rowid index
+    pub rowid_filter: Option<String>,
+    /// column of the `sort_key` option, the only one rows can be sorted on
+    pub sort_key: Option<String>,
+    /// order of the `sort_key` column, only set when the plan relies on it
+    pub sort: Option<Sort>,
+    /// restrictions the rows of the scan are checked against
+    pub quals: Vec<Qual>,
+    pub limit: Option<Limit>,
+    /// rows of the scan in order, taken on the first fetch
+    pub snapshot: Option<Vec<Arc<TableMap>>>,
+    pub row_count: usize,
+    /// rows of the snapshot read so far, returned or not
+    pub rows_scanned: usize,
+    /// rows returned since the scan started over
+    pub rows_returned: usize,
+    /// set when the scan is a join of two in-memory tables
+    pub join: Option<Join>,
+    /// joined rows, built from snapshots of both tables on the first fetch
+    pub pairs: Option<Vec<JoinedRow>>,
+    /// whether rows are read, the counts are only shown by EXPLAIN ANALYZE
+    pub scanning: bool,
 }
 
-impl FdwModifyState {
-    pub fn new(tmp_ctx: MemoryContext) -> Self {
-       Self {
-            rowid_name: String::default(),
-            rowid_attno: 0,
-            rowid_typid: Oid::INVALID,
-            tmp_ctx
-        }
-    }
-}
+/// Outer row and matching inner row of a join, `None` when null extended
+pub type JoinedRow = (Arc<TableMap>, Option<Arc<TableMap>>);
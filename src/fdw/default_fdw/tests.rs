@@ -1,7 +1,7 @@
 #[cfg(any(test, feature = "pg_test"))]
 #[pgrx::pg_schema] 
 mod tests {
-    use std::{ffi::CString, fmt::format};
+    use std::ffi::CString;
     use pgrx_macros::pg_test;
     use pgrx::{pg_sys, Spi};
     use crate::fdw::utils_share::utils::*;
@@ -38,7 +38,6 @@ mod tests {
         assert_eq!(result, "\"hello\"");
     }
     
-    #[cfg(not(feature = "pg13"))]
     #[pg_test]
     fn default_fdw_insert_and_select() {
         Spi::connect_mut(|c| {
@@ -59,16 +58,15 @@ mod tests {
             assert_eq!(
                 results,
                 vec![
-                    (1, Some("'test1'".to_string())),
-                    (2, Some("'test2'".to_string())),
-                    (21, Some("'test21'".to_string())),
-                    (123, Some("NULL".to_string())),
+                    (1, Some("test1".to_string())),
+                    (2, Some("test2".to_string())),
+                    (21, Some("test21".to_string())),
+                    (123, None),
                 ]
             );
         });
     }
 
-    #[cfg(not(feature = "pg13"))]
     #[pg_test]
     fn default_fdw_insert_and_update() {
         Spi::connect_mut(|c| {
@@ -84,10 +82,10 @@ mod tests {
             assert_eq!(
                 get_hello_result(c),
                 vec![
-                    (1, Some("'update_val'".to_string())),
-                    (2, Some("'test2'".to_string())),
-                    (21, Some("'test21'".to_string())),
-                    (123, Some("NULL".to_string())),
+                    (1, Some("update_val".to_string())),
+                    (2, Some("test2".to_string())),
+                    (21, Some("test21".to_string())),
+                    (123, None),
                 ]
             );
             let update_val = "aaaaa";
@@ -98,7 +96,7 @@ mod tests {
             )
             .unwrap();
 
-            let expect = "'aaaaa'";
+            let expect = "aaaaa";
             assert_eq!(
                 get_hello_result(c),
                 vec![
@@ -111,6 +109,1075 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn default_fdw_prepared_update_runs_twice() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+            // one generic plan, run twice; `id + 0` keeps it off the direct
+            // modify path
+            c.update("set local plan_cache_mode = force_generic_plan", None, &[]).unwrap();
+            c.update(
+                "prepare update_hello(text, bigint) as update hello set col = $1 where id + 0 = $2",
+                None,
+                &[],
+            )
+            .unwrap();
+            c.update("execute update_hello('first', 1)", None, &[]).unwrap();
+            c.update("execute update_hello('second', 2)", None, &[]).unwrap();
+            c.update("execute update_hello('third', 1)", None, &[]).unwrap();
+            c.update("deallocate update_hello", None, &[]).unwrap();
+
+            assert_eq!(
+                get_hello_result(c),
+                vec![
+                    (1, Some("third".to_string())),
+                    (2, Some("second".to_string())),
+                    (21, Some("test21".to_string())),
+                    (123, None),
+                ]
+            );
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_rowid_column_option() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+            c.update(
+                r#"
+                    create foreign table keyed (
+                    key text,
+                    id bigint
+                    )
+                    server my_default_server options (rowid_column 'key');
+                    insert into keyed values ('a', 1), ('b', 1);
+                    update keyed set id = 2 where key = 'b';
+                    delete from keyed where key = 'a';
+                "#,
+                None,
+                &[],
+            )
+            .unwrap();
+            let ids = c
+                .select("select id from keyed", None, &[])
+                .unwrap()
+                .map(|row| row.get::<i64>(1).unwrap().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, vec![2]);
+        });
+    }
+
+    #[pg_test(error = "duplicate key value violates rowid column \"id\"")]
+    fn default_fdw_insert_duplicate_rowid() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            c.update(r#"insert into hello values (2,'dup')"#, None, &[])
+                .unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_insert_and_delete() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            c.update(r#"delete from hello where id = 21"#, None, &[])
+                .unwrap();
+
+            assert_eq!(
+                get_hello_result(c),
+                vec![
+                    (1, Some("test1".to_string())),
+                    (2, Some("test2".to_string())),
+                    (123, None),
+                ]
+            );
+
+            c.update(r#"delete from hello"#, None, &[]).unwrap();
+            assert_eq!(get_hello_result(c), vec![]);
+
+            // a deleted rowid can be reused
+            c.update(r#"insert into hello values (21,'again')"#, None, &[])
+                .unwrap();
+            assert_eq!(get_hello_result(c), vec![(21, Some("again".to_string()))]);
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_reload_snapshot() {
+        let path = std::env::temp_dir().join(format!("default_fdw_reload_{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"version":1,"rows":[{"id":"7","col":"'from snapshot'"},{"id":"8","col":"NULL"}]}"#,
+        )
+        .unwrap();
+
+        Spi::connect_mut(|c| {
+            c.update(
+                r#"create foreign data wrapper default_wrapper handler default_fdw_handler validator default_fdw_validator;"#,
+                None,
+                &[],
+            )
+            .unwrap();
+            c.update(
+                r#"create server my_default_server foreign data wrapper default_wrapper;"#,
+                None,
+                &[],
+            )
+            .unwrap();
+            c.update(
+                format!(
+                    r#"create foreign table hello (id bigint, col text)
+                        server my_default_server options (persist_path '{}');"#,
+                    path.display()
+                )
+                .as_str(),
+                None,
+                &[],
+            )
+            .unwrap();
+
+            assert_eq!(
+                get_hello_result(c),
+                vec![
+                    (7, Some("from snapshot".to_string())),
+                    (8, None),
+                ]
+            );
+        });
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[pg_test]
+    fn default_fdw_reload_snapshot_by_rowid_column() {
+        let path = std::env::temp_dir().join(format!("default_fdw_rowid_reload_{}.json", std::process::id()));
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+            c.update(
+                format!(
+                    r#"create foreign table keyed (key text, id bigint)
+                        server my_default_server options (rowid_column 'key', persist_path '{}');
+                       insert into keyed values ('a', 1), ('b', 1);"#,
+                    path.display()
+                )
+                .as_str(),
+                None,
+                &[],
+            )
+            .unwrap();
+            let relid = c
+                .select("select 'keyed'::regclass::oid", None, &[])
+                .unwrap()
+                .first()
+                .get::<pg_sys::Oid>(1)
+                .unwrap()
+                .unwrap();
+
+            // what a commit writes, then a new backend reading it back
+            crate::fdw::default_fdw::handlers::write_pending_snapshots();
+            crate::fdw::default_fdw::handlers::MEMORY_TABLE.write().unwrap().remove(&relid);
+
+            // both rows share an id, they are only kept apart by their key
+            let ids = c
+                .select("select id from keyed order by id", None, &[])
+                .unwrap()
+                .map(|row| row.get::<i64>(1).unwrap().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, vec![1, 1]);
+        });
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[pg_test]
+    fn default_fdw_import_foreign_schema() {
+        Spi::connect_mut(|c| {
+            c.update(
+                r#"create foreign data wrapper default_wrapper handler default_fdw_handler validator default_fdw_validator;"#,
+                None,
+                &[],
+            )
+            .unwrap();
+            c.update(
+                r#"create server my_default_server foreign data wrapper default_wrapper;"#,
+                None,
+                &[],
+            )
+            .unwrap();
+            c.update(
+                r#"
+                    select default_fdw_create_table('my_default_server', 'hello', 'id bigint, col text');
+                    select default_fdw_create_table('my_default_server', 'world', 'id bigint, val int');
+                    create schema import_all;
+                    create schema import_limit;
+                    import foreign schema public from server my_default_server into import_all;
+                    import foreign schema public limit to (world) from server my_default_server into import_limit;
+                "#,
+                None,
+                &[],
+            )
+            .unwrap();
+
+            let imported: Vec<String> = c
+                .select(
+                    r#"select format('%s.%s', foreign_table_schema, foreign_table_name)
+                        from information_schema.foreign_tables
+                        where foreign_table_schema like 'import_%'
+                        order by 1"#,
+                    None,
+                    &[],
+                )
+                .unwrap()
+                .map(|row| row.get::<String>(1).unwrap().unwrap())
+                .collect();
+            assert_eq!(
+                imported,
+                vec!["import_all.hello", "import_all.world", "import_limit.world"]
+            );
+
+            c.update(r#"insert into import_all.hello values (1,'test1')"#, None, &[])
+                .unwrap();
+            let col = c
+                .select(r#"select col from import_all.hello where id = 1"#, None, &[])
+                .unwrap()
+                .first()
+                .get::<String>(1)
+                .unwrap();
+            assert_eq!(col, Some("test1".to_string()));
+        });
+    }
+
+    #[pg_test(error = "invalid column definitions \"id bigint) inherits (pg_class\"")]
+    fn default_fdw_create_table_rejects_other_clauses() {
+        Spi::connect_mut(|c| {
+            c.update(
+                r#"
+                    create foreign data wrapper default_wrapper handler default_fdw_handler validator default_fdw_validator;
+                    create server my_default_server foreign data wrapper default_wrapper;
+                    select default_fdw_create_table('my_default_server', 'hello', 'id bigint) inherits (pg_class');
+                "#,
+                None,
+                &[],
+            )
+            .unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_rescan_in_nested_loop() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            c.update(
+                "set local enable_hashjoin = off; set local enable_mergejoin = off; set local enable_material = off;",
+                None,
+                &[],
+            )
+            .unwrap();
+            // an expression on the join column keeps the join out of the FDW
+            let count = c
+                .select(
+                    "select count(*) from hello a join hello b on a.id + 0 = b.id",
+                    None,
+                    &[],
+                )
+                .unwrap()
+                .first()
+                .get::<i64>(1)
+                .unwrap();
+            assert_eq!(count, Some(4));
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_explain() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            let plan = c
+                .select("explain (analyze, costs off, timing off, summary off) select * from hello", None, &[])
+                .unwrap()
+                .map(|row| row.get::<String>(1).unwrap().unwrap())
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert!(plan.contains("Table Rows: 4"), "{}", plan);
+            assert!(plan.contains("Rowid Column: id"), "{}", plan);
+            assert!(plan.contains("Pushed Quals: none"), "{}", plan);
+            assert!(plan.contains("Rows Scanned: 4"), "{}", plan);
+            assert!(plan.contains("Rows Returned: 4"), "{}", plan);
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_qual_pushdown() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            let plan = c
+                .select("explain (analyze, costs off, timing off, summary off) select * from hello where id > 2", None, &[])
+                .unwrap()
+                .map(|row| row.get::<String>(1).unwrap().unwrap())
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert!(plan.contains("Pushed Quals: id > 2"), "{}", plan);
+            assert!(plan.contains("Rows Scanned: 4"), "{}", plan);
+            assert!(plan.contains("Rows Returned: 2"), "{}", plan);
+
+            let ids = c
+                .select("select id from hello where id in (1, 123) or id is null order by id", None, &[])
+                .unwrap()
+                .map(|row| row.get::<i64>(1).unwrap().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, vec![1, 123]);
+
+            // parameters are resolved when the scan starts
+            let ids = c
+                .select("select id from hello where id <= $1 order by id", None, &[2i64.into()])
+                .unwrap()
+                .map(|row| row.get::<i64>(1).unwrap().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, vec![1, 2]);
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_limit_pushdown() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            let explain = |c: &mut pgrx::spi::SpiClient<'_>, sql: &str| {
+                c.select(&format!("explain (analyze, costs off, timing off, summary off) {}", sql), None, &[])
+                    .unwrap()
+                    .map(|row| row.get::<String>(1).unwrap().unwrap())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+
+            let plan = explain(c, "select * from hello limit 2 offset 1");
+            assert!(plan.contains("Pushed Limit: 3"), "{}", plan);
+            assert!(plan.contains("Rows Scanned: 3"), "{}", plan);
+
+            // the rows have to be filtered or sorted by Postgres first
+            let plan = explain(c, "select * from hello where col like 'test%' limit 2");
+            assert!(!plan.contains("Pushed Limit"), "{}", plan);
+            let plan = explain(c, "select * from hello order by col limit 2");
+            assert!(!plan.contains("Pushed Limit"), "{}", plan);
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_sort_key_removes_sort_node() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+            c.update("alter foreign table hello options (add sort_key 'id')", None, &[]).unwrap();
+
+            let explain = |c: &mut pgrx::spi::SpiClient<'_>, sql: &str| {
+                c.select(&format!("explain (costs off) {}", sql), None, &[])
+                    .unwrap()
+                    .map(|row| row.get::<String>(1).unwrap().unwrap())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+
+            let plan = explain(c, "select * from hello order by id");
+            assert!(plan.contains("Pushed Sort: id ASC"), "{}", plan);
+            assert!(!plan.contains("Sort Key"), "{}", plan);
+            let ids = c
+                .select("select id from hello order by id", None, &[])
+                .unwrap()
+                .map(|row| row.get::<i64>(1).unwrap().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, vec![1, 2, 21, 123]);
+
+            // only the sort_key column is sorted by the wrapper
+            let plan = explain(c, "select * from hello order by col");
+            assert!(plan.contains("Sort Key: col"), "{}", plan);
+            assert!(!plan.contains("Pushed Sort"), "{}", plan);
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_rowid_lookup_and_sort_key() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+            c.update("insert into hello values (5, 'test5')", None, &[]).unwrap();
+            c.update("alter foreign table hello options (add sort_key 'id')", None, &[]).unwrap();
+
+            let explain = |c: &mut pgrx::spi::SpiClient<'_>, sql: &str| {
+                c.select(sql, None, &[])
+                    .unwrap()
+                    .map(|row| row.get::<String>(1).unwrap().unwrap())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+
+            let plan = explain(
+                c,
+                "explain (analyze, costs off, timing off, summary off) select * from hello where id = 21",
+            );
+            assert!(plan.contains("Pushed Quals: id = 21"), "{}", plan);
+            assert!(plan.contains("Rows Scanned: 1"), "{}", plan);
+            let col = c
+                .select("select col from hello where id = 21", None, &[])
+                .unwrap()
+                .first()
+                .get::<String>(1)
+                .unwrap();
+            assert_eq!(col, Some("test21".to_string()));
+
+            let plan = explain(c, "explain (costs off) select * from hello order by id desc limit 2");
+            assert!(plan.contains("Pushed Sort: id DESC"), "{}", plan);
+            assert!(!plan.contains("Sort Key"), "{}", plan);
+            let ids = c
+                .select("select id from hello order by id desc limit 3", None, &[])
+                .unwrap()
+                .map(|row| row.get::<i64>(1).unwrap().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, vec![123, 21, 5]);
+
+            c.update(
+                r#"
+                    create temp table ids (id bigint primary key);
+                    insert into ids values (1), (2), (5);
+                    set local enable_hashjoin = off;
+                    set local enable_nestloop = off;
+                    set local enable_sort = off;
+                "#,
+                None,
+                &[],
+            )
+            .unwrap();
+            let plan = explain(c, "explain (costs off) select * from hello join ids on hello.id = ids.id");
+            assert!(plan.contains("Merge Join"), "{}", plan);
+            assert!(!plan.contains("Sort Key"), "{}", plan);
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_join_pushdown() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+            c.update(
+                r#"
+                    create foreign table tags (
+                    id bigint,
+                    hello_id bigint,
+                    tag text
+                    )
+                    server my_default_server;
+                    insert into tags values (1, 1, 'a'), (2, 1, 'b'), (3, 21, 'c'), (4, 99, 'd');
+                "#,
+                None,
+                &[],
+            )
+            .unwrap();
+
+            let sql = "select h.id, t.tag from hello h left join tags t on t.hello_id = h.id";
+            let plan = c
+                .select(&format!("explain (analyze, costs off, timing off, summary off) {}", sql), None, &[])
+                .unwrap()
+                .map(|row| row.get::<String>(1).unwrap().unwrap())
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert!(plan.contains("Pushed Join: hello LEFT JOIN tags ON (id = hello_id)"), "{}", plan);
+            assert!(plan.contains("Rows Returned: 5"), "{}", plan);
+
+            let rows = c
+                .select(&format!("{} order by h.id, t.tag", sql), None, &[])
+                .unwrap()
+                .map(|row| (row.get::<i64>(1).unwrap().unwrap(), row.get::<String>(2).unwrap()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                rows,
+                vec![
+                    (1, Some("a".to_string())),
+                    (1, Some("b".to_string())),
+                    (2, None),
+                    (21, Some("c".to_string())),
+                    (123, None),
+                ]
+            );
+
+            // quals of an inner join are checked on the joined tuples
+            let tags = c
+                .select(
+                    "select t.tag from hello h join tags t on t.hello_id = h.id where t.id > 1 order by t.tag",
+                    None,
+                    &[],
+                )
+                .unwrap()
+                .map(|row| row.get::<String>(1).unwrap().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(tags, vec!["b".to_string(), "c".to_string()]);
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_returning() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            // `id + 0` keeps the statements out of direct modify
+            let row = c
+                .update("update hello set col = 'x' where id + 0 = 123 returning id, col", None, &[])
+                .unwrap()
+                .first()
+                .get_two::<i64, String>()
+                .unwrap();
+            assert_eq!(row, (Some(123), Some("x".to_string())));
+
+            let id = c
+                .update("update hello set id = 124 where id + 0 = 123 returning id", None, &[])
+                .unwrap()
+                .first()
+                .get::<i64>(1)
+                .unwrap();
+            assert_eq!(id, Some(124));
+
+            let row = c
+                .update("delete from hello where id + 0 = 2 returning id, col", None, &[])
+                .unwrap()
+                .first()
+                .get_two::<i64, String>()
+                .unwrap();
+            assert_eq!(row, (Some(2), Some("test2".to_string())));
+
+            let col = c
+                .update("insert into hello values (7, 'seven') returning col", None, &[])
+                .unwrap()
+                .first()
+                .get::<String>(1)
+                .unwrap();
+            assert_eq!(col, Some("seven".to_string()));
+
+            let results = get_hello_result(c);
+            assert_eq!(
+                results,
+                vec![
+                    (1, Some("test1".to_string())),
+                    (7, Some("seven".to_string())),
+                    (21, Some("test21".to_string())),
+                    (124, Some("x".to_string())),
+                ]
+            );
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_null_text_and_empty_string() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+            c.update("insert into hello values (3, 'NULL'), (4, '')", None, &[]).unwrap();
+            assert_eq!(
+                get_hello_result(c),
+                vec![
+                    (1, Some("test1".to_string())),
+                    (2, Some("test2".to_string())),
+                    (3, Some("NULL".to_string())),
+                    (4, Some("".to_string())),
+                    (21, Some("test21".to_string())),
+                    (123, None),
+                ]
+            );
+        });
+    }
+
+    #[pg_test(error = "duplicate key value violates rowid column \"tag\"")]
+    fn default_fdw_text_rowid_null_is_a_key() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+            // NULL rowids are never indexed, the text 'NULL' is a key like any other
+            c.update(
+                "create foreign table tags (tag text, n int) server my_default_server options (rowid_column 'tag')",
+                None,
+                &[],
+            )
+            .unwrap();
+            c.update("insert into tags values ('NULL', 1), (NULL, 2), (NULL, 3)", None, &[]).unwrap();
+            c.update("insert into tags values ('NULL', 4)", None, &[]).unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_null_in_non_text_column() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+            c.update("create foreign table amounts (id bigint, amount int) server my_default_server", None, &[])
+                .unwrap();
+
+            let row = c
+                .update("insert into amounts values (1, NULL) returning id, amount", None, &[])
+                .unwrap()
+                .first()
+                .get_two::<i64, i32>()
+                .unwrap();
+            assert_eq!(row, (Some(1), None));
+            c.update("insert into amounts values (2, 20)", None, &[]).unwrap();
+
+            let rows = c
+                .select("select id, amount from amounts order by id", None, &[])
+                .unwrap()
+                .map(|row| (row.get::<i64>(1).unwrap(), row.get::<i32>(2).unwrap()))
+                .collect::<Vec<_>>();
+            assert_eq!(rows, vec![(Some(1), None), (Some(2), Some(20))]);
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_updatable_option() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+            let insertable = |c: &mut pgrx::spi::SpiClient<'_>| {
+                c.select(
+                    "select is_insertable_into::text from information_schema.tables where table_name = 'hello'",
+                    None,
+                    &[],
+                )
+                .unwrap()
+                .first()
+                .get::<String>(1)
+                .unwrap()
+                .unwrap()
+            };
+            assert_eq!(insertable(c), "YES");
+
+            // the table option overrides the server option
+            c.update("alter server my_default_server options (add updatable 'false')", None, &[]).unwrap();
+            assert_eq!(insertable(c), "NO");
+            c.update("alter foreign table hello options (add updatable 'true')", None, &[]).unwrap();
+            assert_eq!(insertable(c), "YES");
+            c.update("insert into hello values (3, 'test3')", None, &[]).unwrap();
+        });
+    }
+
+    #[pg_test(error = "cannot delete from foreign table \"hello\"")]
+    fn default_fdw_read_only_delete() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+            c.update("alter foreign table hello options (add updatable 'false')", None, &[]).unwrap();
+            c.update("delete from hello where id = 1", None, &[]).unwrap();
+        });
+    }
+
+    #[pg_test(error = "cannot insert into foreign table \"hello\"")]
+    fn default_fdw_read_only_load() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+            c.update("alter foreign table hello options (add updatable 'false')", None, &[]).unwrap();
+            c.update("select default_fdw_load('hello', 'select 5, ''five''')", None, &[]).unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_scan_snapshot() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            // the scan must not see the rows inserted by the same statement
+            c.update("insert into hello select id + 1000, col from hello", None, &[]).unwrap();
+            let count = c
+                .select("select count(*) from hello", None, &[])
+                .unwrap()
+                .first()
+                .get::<i64>(1)
+                .unwrap();
+            assert_eq!(count, Some(8));
+
+            // nor the new versions of the rows it updated
+            c.update("update hello set id = id + 100 where id + 0 < 1000", None, &[]).unwrap();
+            let ids = c
+                .select("select id from hello where id < 1000 order by id", None, &[])
+                .unwrap()
+                .map(|row| row.get::<i64>(1).unwrap().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, vec![101, 102, 121, 223]);
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_load() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            let loaded = c
+                .select(
+                    "select default_fdw_load('hello', 'select g, ''row '' || g from generate_series(1000, 1999) g')",
+                    None,
+                    &[],
+                )
+                .unwrap()
+                .first()
+                .get::<i64>(1)
+                .unwrap();
+            assert_eq!(loaded, Some(1000));
+
+            let path = std::env::temp_dir().join(format!("default_fdw_load_{}.csv", std::process::id()));
+            std::fs::write(&path, "col,id\nfrom csv,3000\n,3001\n").unwrap();
+            let loaded = c
+                .select(
+                    &format!("select default_fdw_load_csv('hello', '{}')", path.display()),
+                    None,
+                    &[],
+                )
+                .unwrap()
+                .first()
+                .get::<i64>(1)
+                .unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded, Some(2));
+
+            let count = c
+                .select("select count(*) from hello", None, &[])
+                .unwrap()
+                .first()
+                .get::<i64>(1)
+                .unwrap();
+            assert_eq!(count, Some(1006));
+
+            let rows = c
+                .select("select id, col from hello where id in (1500, 3000, 3001) order by id", None, &[])
+                .unwrap()
+                .map(|row| (row.get::<i64>(1).unwrap().unwrap(), row.get::<String>(2).unwrap()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                rows,
+                vec![
+                    (1500, Some("row 1500".to_string())),
+                    (3000, Some("from csv".to_string())),
+                    (3001, None),
+                ]
+            );
+        });
+    }
+
+    #[pg_test(error = "default_fdw table capacity exceeded")]
+    fn default_fdw_max_rows_error() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            c.update(
+                r#"
+                    alter foreign table hello options (add max_rows '4');
+                    insert into hello values (5,'test5');
+                "#,
+                None,
+                &[],
+            )
+            .unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_max_rows_fifo() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            c.update(
+                r#"
+                    alter foreign table hello options (add max_rows '3', add eviction 'fifo');
+                    insert into hello values (5,'test5');
+                "#,
+                None,
+                &[],
+            )
+            .unwrap();
+
+            assert_eq!(
+                get_hello_result(c),
+                vec![
+                    (5, Some("test5".to_string())),
+                    (21, Some("test21".to_string())),
+                    (123, None),
+                ]
+            );
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_ttl_expiry() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            c.update(
+                r#"
+                    alter foreign table hello options (add ttl_seconds '1');
+                    select pg_sleep(1.2);
+                    insert into hello values (1,'fresh');
+                "#,
+                None,
+                &[],
+            )
+            .unwrap();
+
+            assert_eq!(get_hello_result(c), vec![(1, Some("fresh".to_string()))]);
+        });
+    }
+
+    #[pg_test]
+    fn default_fdw_admin_functions() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            let (row_count, rowid_column) = c
+                .select(
+                    "select row_count, rowid_column from default_fdw_tables() where ftrelid = 'hello'::regclass",
+                    None,
+                    &[],
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, String>()
+                .unwrap();
+            assert_eq!(row_count, Some(4));
+            assert_eq!(rowid_column, Some("id".to_string()));
+
+            let dump: Vec<String> = c
+                .select(
+                    "select d::text from default_fdw_dump('hello') d order by (d->>'id')::bigint",
+                    None,
+                    &[],
+                )
+                .unwrap()
+                .map(|row| row.get::<String>(1).unwrap().unwrap())
+                .collect();
+            assert_eq!(dump[0], r#"{"id": 1, "col": "test1"}"#);
+            assert_eq!(dump[3], r#"{"id": 123, "col": null}"#);
+
+            let removed = c
+                .update("select default_fdw_clear('hello')", None, &[])
+                .unwrap()
+                .first()
+                .get::<i64>(1)
+                .unwrap();
+            assert_eq!(removed, Some(4));
+            assert_eq!(get_hello_result(c), vec![]);
+        });
+    }
+
+    #[pg_test(error = "foreign table \"people\" does not use default_fdw_handler")]
+    fn default_fdw_admin_rejects_other_wrappers() {
+        Spi::connect_mut(|c| {
+            c.update(
+                r#"
+                    create foreign data wrapper csv_wrapper handler csv_fdw_handler;
+                    create server csv_server foreign data wrapper csv_wrapper;
+                    create foreign table people (id int, name text) server csv_server options (filepath '/nonexistent.csv');
+                    select default_fdw_clear('people');
+                "#,
+                None,
+                &[],
+            )
+            .unwrap();
+        });
+    }
+
+    #[cfg(not(feature = "pg13"))]
+    #[pg_test]
+    fn default_fdw_batch_insert_and_truncate() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            c.update(
+                r#"
+                    alter foreign table hello options (add batch_size '10');
+                    insert into hello select i, 'batch' || i from generate_series(1000, 1024) i;
+                "#,
+                None,
+                &[],
+            )
+            .unwrap();
+
+            let count = c
+                .select("SELECT count(*) FROM hello", None, &[])
+                .unwrap()
+                .first()
+                .get::<i64>(1)
+                .unwrap();
+            assert_eq!(count, Some(29));
+
+            c.update("truncate hello restart identity cascade", None, &[])
+                .unwrap();
+            assert_eq!(get_hello_result(c), vec![]);
+        });
+    }
+
+    #[cfg(not(feature = "pg13"))]
+    #[pg_test]
+    fn default_fdw_direct_modify() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+
+            let plan = c
+                .select("explain (costs off) delete from hello where id = 2", None, &[])
+                .unwrap()
+                .map(|row| row.get::<String>(1).unwrap().unwrap())
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert!(plan.contains("Foreign Delete"), "{}", plan);
+
+            let deleted = c
+                .update("delete from hello where id = 2", None, &[])
+                .unwrap()
+                .len();
+            assert_eq!(deleted, 1);
+
+            let updated: Vec<(i64, Option<String>)> = c
+                .update(
+                    "update hello set col = 'returned' where id = 21 returning id, col",
+                    None,
+                    &[],
+                )
+                .unwrap()
+                .map(|row| (row.get::<i64>(1).unwrap().unwrap(), row.get::<String>(2).unwrap()))
+                .collect();
+            assert_eq!(updated, vec![(21, Some("returned".to_string()))]);
+
+            let updated = c
+                .update("update hello set col = 'all'", None, &[])
+                .unwrap()
+                .len();
+            assert_eq!(updated, 3);
+            assert_eq!(
+                get_hello_result(c),
+                vec![
+                    (1, Some("all".to_string())),
+                    (21, Some("all".to_string())),
+                    (123, Some("all".to_string())),
+                ]
+            );
+        });
+    }
+
+    #[cfg(not(feature = "pg13"))]
+    #[pg_test]
+    fn default_fdw_prepared_direct_modify_runs_twice() {
+        Spi::connect_mut(|c| {
+            init_fdw_table(c);
+            c.update("prepare update_one as update hello set col = 'again' where id = 21", None, &[]).unwrap();
+            c.update("prepare delete_one as delete from hello where id = 2", None, &[]).unwrap();
+
+            let plan = c
+                .select("explain (costs off) execute delete_one", None, &[])
+                .unwrap()
+                .map(|row| row.get::<String>(1).unwrap().unwrap())
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert!(plan.contains("Foreign Delete"), "{}", plan);
+
+            for _ in 0..2 {
+                assert_eq!(c.update("execute update_one", None, &[]).unwrap().len(), 1);
+            }
+            assert_eq!(c.update("execute delete_one", None, &[]).unwrap().len(), 1);
+            assert_eq!(c.update("execute delete_one", None, &[]).unwrap().len(), 0);
+            c.update("deallocate update_one; deallocate delete_one", None, &[]).unwrap();
+
+            assert_eq!(
+                get_hello_result(c),
+                vec![
+                    (1, Some("test1".to_string())),
+                    (21, Some("again".to_string())),
+                    (123, None),
+                ]
+            );
+        });
+    }
+
+    fn create_validated_server(c: &mut pgrx::spi::SpiClient<'_>) {
+        c.update(
+            r#"create foreign data wrapper checked_wrapper handler default_fdw_handler validator default_fdw_validator;
+               create server checked_server foreign data wrapper checked_wrapper options (updatable 'off', max_rows '10');"#,
+            None,
+            &[],
+        )
+        .unwrap();
+    }
+
+    #[pg_test]
+    fn default_fdw_validator_accepts_known_options() {
+        Spi::connect_mut(|c| {
+            create_validated_server(c);
+            c.update(
+                r#"create foreign table checked (id bigint, col text)
+                    server checked_server options (sort_key 'id', eviction 'LRU', ttl_seconds '60');
+                   alter server checked_server options (set updatable 'true');"#,
+                None,
+                &[],
+            )
+            .unwrap();
+        });
+    }
+
+    #[pg_test(error = "invalid option \"max_row\"")]
+    fn default_fdw_validator_rejects_typo() {
+        Spi::connect_mut(|c| {
+            create_validated_server(c);
+            c.update("alter server checked_server options (add max_row '5')", None, &[]).unwrap();
+        });
+    }
+
+    #[pg_test(error = "invalid value for option \"batch_size\": \"0\"")]
+    fn default_fdw_validator_rejects_bad_value() {
+        Spi::connect_mut(|c| {
+            create_validated_server(c);
+            c.update(
+                "create foreign table checked (id bigint) server checked_server options (batch_size '0')",
+                None,
+                &[],
+            )
+            .unwrap();
+        });
+    }
+
+    #[pg_test(error = "invalid value for option \"max_rows\": \"ten\"")]
+    fn default_fdw_validator_rejects_bad_limit() {
+        Spi::connect_mut(|c| {
+            create_validated_server(c);
+            c.update(
+                "create foreign table checked (id bigint) server checked_server options (max_bytes '1024', ttl_seconds '60')",
+                None,
+                &[],
+            )
+            .unwrap();
+            c.update("alter foreign table checked options (add max_rows 'ten')", None, &[]).unwrap();
+        });
+    }
+
+    #[pg_test(error = "invalid value for option \"ttl_seconds\": \"-1\"")]
+    fn default_fdw_validator_rejects_bad_ttl() {
+        Spi::connect_mut(|c| {
+            create_validated_server(c);
+            c.update(
+                "create foreign table checked (id bigint) server checked_server options (ttl_seconds '-1')",
+                None,
+                &[],
+            )
+            .unwrap();
+        });
+    }
+
+    #[pg_test(error = "invalid value for option \"updatable\": \"maybe\"")]
+    fn default_fdw_validator_rejects_bad_updatable() {
+        Spi::connect_mut(|c| {
+            create_validated_server(c);
+            c.update(
+                "create foreign table checked (id bigint) server checked_server options (updatable 'maybe')",
+                None,
+                &[],
+            )
+            .unwrap();
+        });
+    }
+
+    #[pg_test(error = "option \"sort_key\" cannot be set on a server")]
+    fn default_fdw_validator_rejects_wrong_context() {
+        Spi::connect_mut(|c| {
+            create_validated_server(c);
+            c.update("alter server checked_server options (add sort_key 'id')", None, &[]).unwrap();
+        });
+    }
+
     fn get_hello_result(c: &mut pgrx::spi::SpiClient<'_>) -> Vec<(i64, Option<String>)> {
         let rows = c
             .select("SELECT * FROM hello ORDER BY id", None, &[])
@@ -129,17 +1196,13 @@ mod tests {
     
     fn init_fdw_table(c: &mut pgrx::spi::SpiClient<'_>) {
         c.update(
-            r#"create foreign data wrapper default_wrapper handler default_fdw_handler;"#,
+            r#"create foreign data wrapper default_wrapper handler default_fdw_handler validator default_fdw_validator;"#,
             None,
             &[],
         )
         .unwrap();
         c.update(
-            r#"create server my_default_server
-                    foreign data wrapper default_wrapper
-                    options (
-                    foo 'bar'
-                    );"#,
+            r#"create server my_default_server foreign data wrapper default_wrapper;"#,
             None,
             &[],
         )
@@ -151,9 +1214,7 @@ mod tests {
                 id bigint,
                 col text
                 )
-                server my_default_server options (
-                    foo 'bar'
-                );
+                server my_default_server;
              "#,
             None,
             &[],
@@ -1,9 +1,12 @@
 use pgrx::{
     prelude::*,
-    pg_sys::{Oid, Datum},
-    fcinfo,
+    pg_sys::{self, Oid, Datum},
+    fcinfo, Json, JsonB, Uuid,
 };
-use std::{fmt, ffi::CStr};
+#[cfg(not(feature = "pg16"))]
+use pgrx::{pg_sys::panic::CaughtError, PgTryBuilder};
+use std::{cmp::Ordering, fmt, ffi::{CStr, CString}};
+use crate::fdw::utils_share::utils::string_from_cstr;
 
 #[derive(Debug)]
 pub enum Cell {
@@ -16,6 +19,13 @@ pub enum Cell {
     I64(i64),
     Numeric(AnyNumeric),
     String(String),
+    Varchar(String),
+    Bpchar(String),
+    Name(String),
+    Json(Json),
+    Jsonb(JsonB),
+    Bytea(Vec<u8>),
+    Uuid(Uuid),
     Date(Date),
     Time(Time),
     Timestamp(Timestamp),
@@ -28,6 +38,16 @@ pub enum Cell {
     F32Array(Vec<Option<f32>>),
     F64Array(Vec<Option<f64>>),
     StringArray(Vec<Option<String>>),
+    VarcharArray(Vec<Option<String>>),
+    BpcharArray(Vec<Option<String>>),
+    NameArray(Vec<Option<String>>),
+    JsonArray(Vec<Option<Json>>),
+    JsonbArray(Vec<Option<JsonB>>),
+    ByteaArray(Vec<Option<Vec<u8>>>),
+    UuidArray(Vec<Option<Uuid>>),
+    /// value of a type without a variant of its own, in the text form of its
+    /// output function
+    Other { typoid: Oid, text: String },
 }
 
 impl FromDatum for Cell {
@@ -35,6 +55,9 @@ impl FromDatum for Cell {
     where
         Self: Sized,
     {
+        if is_null || typoid == Oid::INVALID {
+            return None;
+        }
         let oid = PgOid::from(typoid);
         match oid {
             PgOid::BuiltIn(PgBuiltInOids::BOOLOID) => {
@@ -62,6 +85,27 @@ impl FromDatum for Cell {
             PgOid::BuiltIn(PgBuiltInOids::TEXTOID) => {
                 String::from_datum(datum, is_null).map(Cell::String)
             }
+            PgOid::BuiltIn(PgBuiltInOids::VARCHAROID) => {
+                String::from_datum(datum, is_null).map(Cell::Varchar)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::BPCHAROID) => {
+                String::from_datum(datum, is_null).map(Cell::Bpchar)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::NAMEOID) => {
+                name_from_datum(datum, is_null).map(Cell::Name)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::JSONOID) => {
+                Json::from_datum(datum, is_null).map(Cell::Json)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::JSONBOID) => {
+                JsonB::from_datum(datum, is_null).map(Cell::Jsonb)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::BYTEAOID) => {
+                Vec::<u8>::from_datum(datum, is_null).map(Cell::Bytea)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::UUIDOID) => {
+                Uuid::from_datum(datum, is_null).map(Cell::Uuid)
+            }
             PgOid::BuiltIn(PgBuiltInOids::DATEOID) => {
                 Date::from_datum(datum, is_null).map(Cell::Date)
             }
@@ -98,7 +142,66 @@ impl FromDatum for Cell {
             PgOid::BuiltIn(PgBuiltInOids::TEXTARRAYOID) => {
                 Vec::<Option<String>>::from_datum(datum, false).map(Cell::StringArray)
             }
-            _ => None,
+            PgOid::BuiltIn(PgBuiltInOids::VARCHARARRAYOID) => {
+                array_from_datum(datum, is_null, pg_sys::VARCHAROID, |c| match c {
+                    Cell::Varchar(v) => Some(v),
+                    _ => None,
+                })
+                .map(Cell::VarcharArray)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::BPCHARARRAYOID) => {
+                array_from_datum(datum, is_null, pg_sys::BPCHAROID, |c| match c {
+                    Cell::Bpchar(v) => Some(v),
+                    _ => None,
+                })
+                .map(Cell::BpcharArray)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::NAMEARRAYOID) => {
+                array_from_datum(datum, is_null, pg_sys::NAMEOID, |c| match c {
+                    Cell::Name(v) => Some(v),
+                    _ => None,
+                })
+                .map(Cell::NameArray)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::JSONARRAYOID) => {
+                array_from_datum(datum, is_null, pg_sys::JSONOID, |c| match c {
+                    Cell::Json(v) => Some(v),
+                    _ => None,
+                })
+                .map(Cell::JsonArray)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::JSONBARRAYOID) => {
+                array_from_datum(datum, is_null, pg_sys::JSONBOID, |c| match c {
+                    Cell::Jsonb(v) => Some(v),
+                    _ => None,
+                })
+                .map(Cell::JsonbArray)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::BYTEAARRAYOID) => {
+                array_from_datum(datum, is_null, pg_sys::BYTEAOID, |c| match c {
+                    Cell::Bytea(v) => Some(v),
+                    _ => None,
+                })
+                .map(Cell::ByteaArray)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::UUIDARRAYOID) => {
+                array_from_datum(datum, is_null, pg_sys::UUIDOID, |c| match c {
+                    Cell::Uuid(v) => Some(v),
+                    _ => None,
+                })
+                .map(Cell::UuidArray)
+            }
+            _ => {
+                // a domain reads as its base type
+                let base = pg_sys::getBaseType(typoid);
+                if base != typoid {
+                    return Self::from_polymorphic_datum(datum, is_null, base);
+                }
+                Some(Cell::Other {
+                    typoid,
+                    text: output_text(datum, typoid),
+                })
+            }
         }
     }
 }
@@ -115,6 +218,14 @@ impl IntoDatum for Cell {
             Cell::I64(v) => v.into_datum(),
             Cell::Numeric(v) => v.into_datum(),
             Cell::String(v) => v.into_datum(),
+            // varchar and bpchar share the representation of text
+            Cell::Varchar(v) => v.into_datum(),
+            Cell::Bpchar(v) => v.into_datum(),
+            Cell::Name(v) => name_into_datum(&v),
+            Cell::Json(v) => v.into_datum(),
+            Cell::Jsonb(v) => v.into_datum(),
+            Cell::Bytea(v) => v.into_datum(),
+            Cell::Uuid(v) => v.into_datum(),
             Cell::Date(v) => v.into_datum(),
             Cell::Time(v) => v.into_datum(),
             Cell::Timestamp(v) => v.into_datum(),
@@ -127,6 +238,14 @@ impl IntoDatum for Cell {
             Cell::F32Array(v) => v.into_datum(),
             Cell::F64Array(v) => v.into_datum(),
             Cell::StringArray(v) => v.into_datum(),
+            Cell::VarcharArray(v) => array_into_datum(v, pg_sys::VARCHAROID, Cell::Varchar),
+            Cell::BpcharArray(v) => array_into_datum(v, pg_sys::BPCHAROID, Cell::Bpchar),
+            Cell::NameArray(v) => array_into_datum(v, pg_sys::NAMEOID, Cell::Name),
+            Cell::JsonArray(v) => array_into_datum(v, pg_sys::JSONOID, Cell::Json),
+            Cell::JsonbArray(v) => array_into_datum(v, pg_sys::JSONBOID, Cell::Jsonb),
+            Cell::ByteaArray(v) => array_into_datum(v, pg_sys::BYTEAOID, Cell::Bytea),
+            Cell::UuidArray(v) => array_into_datum(v, pg_sys::UUIDOID, Cell::Uuid),
+            Cell::Other { typoid, text } => unsafe { input_datum(&text, typoid) },
         }
     }
 
@@ -134,32 +253,9 @@ impl IntoDatum for Cell {
         Oid::INVALID
     }
 
+    /// Every type converts, to a variant of its own or to `Cell::Other`
     fn is_compatible_with(other: Oid) -> bool {
-        Self::type_oid() == other
-            || other == pgrx::pg_sys::BOOLOID
-            || other == pgrx::pg_sys::CHAROID
-            || other == pgrx::pg_sys::INT2OID
-            || other == pgrx::pg_sys::FLOAT4OID
-            || other == pgrx::pg_sys::INT4OID
-            || other == pgrx::pg_sys::FLOAT8OID
-            || other == pgrx::pg_sys::INT8OID
-            || other == pgrx::pg_sys::NUMERICOID
-            || other == pgrx::pg_sys::TEXTOID
-            || other == pgrx::pg_sys::DATEOID
-            || other == pgrx::pg_sys::TIMEOID
-            || other == pgrx::pg_sys::TIMESTAMPOID
-            || other == pgrx::pg_sys::TIMESTAMPTZOID
-            || other == pgrx::pg_sys::INTERVALOID
-            || other == pgrx::pg_sys::JSONBOID
-            || other == pgrx::pg_sys::BYTEAOID
-            || other == pgrx::pg_sys::UUIDOID
-            || other == pgrx::pg_sys::BOOLARRAYOID
-            || other == pgrx::pg_sys::INT2ARRAYOID
-            || other == pgrx::pg_sys::INT4ARRAYOID
-            || other == pgrx::pg_sys::INT8ARRAYOID
-            || other == pgrx::pg_sys::FLOAT4ARRAYOID
-            || other == pgrx::pg_sys::FLOAT8ARRAYOID
-            || other == pgrx::pg_sys::TEXTARRAYOID
+        other != Oid::INVALID
     }
 }
 
@@ -175,6 +271,13 @@ impl Clone for Cell {
             Cell::I64(v) => Cell::I64(*v),
             Cell::Numeric(v) => Cell::Numeric(v.clone()),
             Cell::String(v) => Cell::String(v.clone()),
+            Cell::Varchar(v) => Cell::Varchar(v.clone()),
+            Cell::Bpchar(v) => Cell::Bpchar(v.clone()),
+            Cell::Name(v) => Cell::Name(v.clone()),
+            Cell::Json(v) => Cell::Json(Json(v.0.clone())),
+            Cell::Jsonb(v) => Cell::Jsonb(JsonB(v.0.clone())),
+            Cell::Bytea(v) => Cell::Bytea(v.clone()),
+            Cell::Uuid(v) => Cell::Uuid(Uuid::from_bytes(*v.as_bytes())),
             Cell::Date(v) => Cell::Date(*v),
             Cell::Time(v) => Cell::Time(*v),
             Cell::Timestamp(v) => Cell::Timestamp(*v),
@@ -187,22 +290,292 @@ impl Clone for Cell {
             Cell::F32Array(v) => Cell::F32Array(v.clone()),
             Cell::F64Array(v) => Cell::F64Array(v.clone()),
             Cell::StringArray(v) => Cell::StringArray(v.clone()),
+            Cell::VarcharArray(v) => Cell::VarcharArray(v.clone()),
+            Cell::BpcharArray(v) => Cell::BpcharArray(v.clone()),
+            Cell::NameArray(v) => Cell::NameArray(v.clone()),
+            Cell::JsonArray(v) => Cell::JsonArray(v.iter().map(|e| e.as_ref().map(|j| Json(j.0.clone()))).collect()),
+            Cell::JsonbArray(v) => Cell::JsonbArray(v.iter().map(|e| e.as_ref().map(|j| JsonB(j.0.clone()))).collect()),
+            Cell::ByteaArray(v) => Cell::ByteaArray(v.clone()),
+            Cell::UuidArray(v) => Cell::UuidArray(v.iter().map(|e| e.map(|u| Uuid::from_bytes(*u.as_bytes()))).collect()),
+            Cell::Other { typoid, text } => Cell::Other {
+                typoid: *typoid,
+                text: text.clone(),
+            },
         }
     }
 }
 
+impl Cell {
+    /// Type of the value, the base type for values read from a domain
+    pub fn typoid(&self) -> Oid {
+        match self {
+            Cell::Bool(_) => pg_sys::BOOLOID,
+            Cell::I8(_) => pg_sys::CHAROID,
+            Cell::I16(_) => pg_sys::INT2OID,
+            Cell::F32(_) => pg_sys::FLOAT4OID,
+            Cell::I32(_) => pg_sys::INT4OID,
+            Cell::F64(_) => pg_sys::FLOAT8OID,
+            Cell::I64(_) => pg_sys::INT8OID,
+            Cell::Numeric(_) => pg_sys::NUMERICOID,
+            Cell::String(_) => pg_sys::TEXTOID,
+            Cell::Varchar(_) => pg_sys::VARCHAROID,
+            Cell::Bpchar(_) => pg_sys::BPCHAROID,
+            Cell::Name(_) => pg_sys::NAMEOID,
+            Cell::Json(_) => pg_sys::JSONOID,
+            Cell::Jsonb(_) => pg_sys::JSONBOID,
+            Cell::Bytea(_) => pg_sys::BYTEAOID,
+            Cell::Uuid(_) => pg_sys::UUIDOID,
+            Cell::Date(_) => pg_sys::DATEOID,
+            Cell::Time(_) => pg_sys::TIMEOID,
+            Cell::Timestamp(_) => pg_sys::TIMESTAMPOID,
+            Cell::Timestamptz(_) => pg_sys::TIMESTAMPTZOID,
+            Cell::Interval(_) => pg_sys::INTERVALOID,
+            Cell::BoolArray(_) => pg_sys::BOOLARRAYOID,
+            Cell::I16Array(_) => pg_sys::INT2ARRAYOID,
+            Cell::I32Array(_) => pg_sys::INT4ARRAYOID,
+            Cell::I64Array(_) => pg_sys::INT8ARRAYOID,
+            Cell::F32Array(_) => pg_sys::FLOAT4ARRAYOID,
+            Cell::F64Array(_) => pg_sys::FLOAT8ARRAYOID,
+            Cell::StringArray(_) => pg_sys::TEXTARRAYOID,
+            Cell::VarcharArray(_) => pg_sys::VARCHARARRAYOID,
+            Cell::BpcharArray(_) => pg_sys::BPCHARARRAYOID,
+            Cell::NameArray(_) => pg_sys::NAMEARRAYOID,
+            Cell::JsonArray(_) => pg_sys::JSONARRAYOID,
+            Cell::JsonbArray(_) => pg_sys::JSONBARRAYOID,
+            Cell::ByteaArray(_) => pg_sys::BYTEAARRAYOID,
+            Cell::UuidArray(_) => pg_sys::UUIDARRAYOID,
+            Cell::Other { typoid, .. } => *typoid,
+        }
+    }
+
+    /// Elements of an array cell, `None` for other cells
+    pub fn array_elements(&self) -> Option<Vec<Option<Cell>>> {
+        fn wrap<T: Clone>(v: &[Option<T>], f: fn(T) -> Cell) -> Vec<Option<Cell>> {
+            v.iter().map(|e| e.clone().map(f)).collect()
+        }
+        match self {
+            Cell::BoolArray(v) => Some(wrap(v, Cell::Bool)),
+            Cell::I16Array(v) => Some(wrap(v, Cell::I16)),
+            Cell::I32Array(v) => Some(wrap(v, Cell::I32)),
+            Cell::I64Array(v) => Some(wrap(v, Cell::I64)),
+            Cell::F32Array(v) => Some(wrap(v, Cell::F32)),
+            Cell::F64Array(v) => Some(wrap(v, Cell::F64)),
+            Cell::StringArray(v) => Some(wrap(v, Cell::String)),
+            Cell::VarcharArray(v) => Some(wrap(v, Cell::Varchar)),
+            Cell::BpcharArray(v) => Some(wrap(v, Cell::Bpchar)),
+            Cell::NameArray(v) => Some(wrap(v, Cell::Name)),
+            Cell::JsonArray(v) => Some(v.iter().map(|e| e.as_ref().map(|j| Cell::Json(Json(j.0.clone())))).collect()),
+            Cell::JsonbArray(v) => Some(v.iter().map(|e| e.as_ref().map(|j| Cell::Jsonb(JsonB(j.0.clone())))).collect()),
+            Cell::ByteaArray(v) => Some(wrap(v, Cell::Bytea)),
+            Cell::UuidArray(v) => Some(v.iter().map(|e| e.map(|u| Cell::Uuid(Uuid::from_bytes(*u.as_bytes())))).collect()),
+            _ => None,
+        }
+    }
+}
+
+impl Cell {
+    /// The value as a SQL literal, e.g. `42`, `'it''s'` or
+    /// `'2024-01-31'::date`. Character values are left without a cast so that
+    /// they take the type of what they are compared with.
+    pub fn to_sql_literal(&self) -> String {
+        match self {
+            Cell::Bool(_) | Cell::I16(_) | Cell::I32(_) | Cell::I64(_) => self.to_string(),
+            Cell::F32(v) if v.is_finite() => self.to_string(),
+            Cell::F64(v) if v.is_finite() => self.to_string(),
+            Cell::Numeric(_) if !matches!(self.to_string().as_str(), "NaN" | "Infinity" | "-Infinity") => self.to_string(),
+            Cell::String(_) | Cell::Varchar(_) | Cell::Bpchar(_) | Cell::Name(_) => quote_literal(&self.to_string()),
+            _ => format!("{}::{}", quote_literal(&self.to_string()), self.sql_type_name()),
+        }
+    }
+
+    /// Name of the type of the value in SQL
+    fn sql_type_name(&self) -> String {
+        let name = match self {
+            Cell::Bool(_) => "boolean",
+            Cell::I8(_) => "\"char\"",
+            Cell::I16(_) => "smallint",
+            Cell::F32(_) => "real",
+            Cell::I32(_) => "integer",
+            Cell::F64(_) => "double precision",
+            Cell::I64(_) => "bigint",
+            Cell::Numeric(_) => "numeric",
+            Cell::String(_) => "text",
+            Cell::Varchar(_) => "character varying",
+            Cell::Bpchar(_) => "bpchar",
+            Cell::Name(_) => "name",
+            Cell::Json(_) => "json",
+            Cell::Jsonb(_) => "jsonb",
+            Cell::Bytea(_) => "bytea",
+            Cell::Uuid(_) => "uuid",
+            Cell::Date(_) => "date",
+            Cell::Time(_) => "time",
+            Cell::Timestamp(_) => "timestamp",
+            Cell::Timestamptz(_) => "timestamptz",
+            Cell::Interval(_) => "interval",
+            Cell::BoolArray(_) => "boolean[]",
+            Cell::I16Array(_) => "smallint[]",
+            Cell::I32Array(_) => "integer[]",
+            Cell::I64Array(_) => "bigint[]",
+            Cell::F32Array(_) => "real[]",
+            Cell::F64Array(_) => "double precision[]",
+            Cell::StringArray(_) => "text[]",
+            Cell::VarcharArray(_) => "character varying[]",
+            Cell::BpcharArray(_) => "bpchar[]",
+            Cell::NameArray(_) => "name[]",
+            Cell::JsonArray(_) => "json[]",
+            Cell::JsonbArray(_) => "jsonb[]",
+            Cell::ByteaArray(_) => "bytea[]",
+            Cell::UuidArray(_) => "uuid[]",
+            Cell::Other { typoid, .. } => return unsafe { string_from_cstr(pg_sys::format_type_be(*typoid)) },
+        };
+        name.to_string()
+    }
+}
+
+/// Equality of the values, see `PartialOrd`
+impl PartialEq for Cell {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+/// Order of two values of comparable types. Integers, floats and numerics
+/// compare with each other, character types with each other by bytes with
+/// the blank padding of bpchar left out, dates and times with values of the
+/// same type. Values of other types are only equal when their text is.
+/// Anything else, NaN included, is not comparable.
+impl PartialOrd for Cell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Cell::Bool(a), Cell::Bool(b)) => a.partial_cmp(b),
+            (Cell::Bpchar(_), _) | (_, Cell::Bpchar(_)) => {
+                let (a, b) = (string_value(self)?, string_value(other)?);
+                Some(a.trim_end_matches(' ').cmp(b.trim_end_matches(' ')))
+            }
+            _ if string_value(self).is_some() => Some(string_value(self)?.cmp(string_value(other)?)),
+            (Cell::Numeric(a), Cell::Numeric(b)) => a.partial_cmp(b),
+            (Cell::Numeric(a), _) => match integer_value(other) {
+                Some(b) => a.partial_cmp(&AnyNumeric::from(b)),
+                None => float_value(self).zip(float_value(other)).and_then(|(a, b)| a.partial_cmp(&b)),
+            },
+            (_, Cell::Numeric(_)) => other.partial_cmp(self).map(Ordering::reverse),
+            (Cell::Date(_), Cell::Date(_))
+            | (Cell::Time(_), Cell::Time(_))
+            | (Cell::Timestamp(_), Cell::Timestamp(_))
+            | (Cell::Timestamptz(_), Cell::Timestamptz(_)) => time_value(self)?.partial_cmp(&time_value(other)?),
+            (Cell::Bytea(a), Cell::Bytea(b)) => a.partial_cmp(b),
+            (Cell::Uuid(a), Cell::Uuid(b)) => a.as_bytes().partial_cmp(b.as_bytes()),
+            _ if self.array_elements().is_some() => {
+                let (a, b) = (self.array_elements()?, other.array_elements()?);
+                compare_arrays(&a, &b)
+            }
+            _ => match (integer_value(self), integer_value(other)) {
+                (Some(a), Some(b)) => Some(a.cmp(&b)),
+                _ => match (float_value(self), float_value(other)) {
+                    (Some(a), Some(b)) => a.partial_cmp(&b),
+                    _ if self.typoid() == other.typoid() && self.to_string() == other.to_string() => Some(Ordering::Equal),
+                    _ => None,
+                },
+            },
+        }
+    }
+}
+
+fn string_value(cell: &Cell) -> Option<&str> {
+    match cell {
+        Cell::String(v) | Cell::Varchar(v) | Cell::Bpchar(v) | Cell::Name(v) => Some(v),
+        _ => None,
+    }
+}
+
+fn integer_value(cell: &Cell) -> Option<i64> {
+    match cell {
+        Cell::I8(v) => Some(*v as i64),
+        Cell::I16(v) => Some(*v as i64),
+        Cell::I32(v) => Some(*v as i64),
+        Cell::I64(v) => Some(*v),
+        _ => None,
+    }
+}
+
+fn float_value(cell: &Cell) -> Option<f64> {
+    match cell {
+        Cell::F32(v) => Some(*v as f64),
+        Cell::F64(v) => Some(*v),
+        Cell::Numeric(v) => f64::try_from(v.clone()).ok(),
+        _ => integer_value(cell).map(|v| v as f64),
+    }
+}
+
+/// Days of a date, microseconds of a time or timestamp
+fn time_value(cell: &Cell) -> Option<i64> {
+    match cell {
+        Cell::Date(v) => (*v).into_datum().map(|d| d.value() as i32 as i64),
+        Cell::Time(v) => (*v).into_datum().map(|d| d.value() as i64),
+        Cell::Timestamp(v) => (*v).into_datum().map(|d| d.value() as i64),
+        Cell::Timestamptz(v) => (*v).into_datum().map(|d| d.value() as i64),
+        _ => None,
+    }
+}
+
+/// Arrays compare element by element, NULL after any value like in Postgres
+fn compare_arrays(a: &[Option<Cell>], b: &[Option<Cell>]) -> Option<Ordering> {
+    for (x, y) in a.iter().zip(b) {
+        let ord = match (x, y) {
+            (Some(x), Some(y)) => x.partial_cmp(y)?,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        if ord != Ordering::Equal {
+            return Some(ord);
+        }
+    }
+    Some(a.len().cmp(&b.len()))
+}
+
+/// Text as a SQL string literal, with an `E` prefix when it holds
+/// backslashes so that it reads the same whatever standard_conforming_strings
+fn quote_literal(text: &str) -> String {
+    let quoted = text.replace('\'', "''");
+    if quoted.contains('\\') {
+        format!("E'{}'", quoted.replace('\\', "\\\\"))
+    } else {
+        format!("'{}'", quoted)
+    }
+}
+
+/// Text of a `"char"` as charout writes it: the character, nothing for a
+/// zero byte and an octal escape for a byte outside ASCII
+fn char_text(v: i8) -> String {
+    match v as u8 {
+        0 => String::new(),
+        b if b.is_ascii() => (b as char).to_string(),
+        b => format!("\\{:03o}", b),
+    }
+}
+
+/// Text form of the value as the output function of its type writes it, so
+/// that it reads back with the input function. Use `Cell::to_sql_literal` to
+/// put a value in a query.
 impl fmt::Display for Cell {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Cell::Bool(v) => write!(f, "{}", v),
-            Cell::I8(v) => write!(f, "{}", v),
+            Cell::I8(v) => write!(f, "{}", char_text(*v)),
             Cell::I16(v) => write!(f, "{}", v),
-            Cell::F32(v) => write!(f, "{}", v),
+            Cell::F32(v) if v.is_finite() => write!(f, "{}", v),
             Cell::I32(v) => write!(f, "{}", v),
-            Cell::F64(v) => write!(f, "{}", v),
+            Cell::F64(v) if v.is_finite() => write!(f, "{}", v),
+            Cell::F32(v) => write_special_float(*v as f64, f),
+            Cell::F64(v) => write_special_float(*v, f),
             Cell::I64(v) => write!(f, "{}", v),
             Cell::Numeric(v) => write!(f, "{}", v),
-            Cell::String(v) => write!(f, "'{}'", v),
+            Cell::String(v) | Cell::Varchar(v) | Cell::Bpchar(v) | Cell::Name(v) => write!(f, "{}", v),
+            Cell::Json(v) => write!(f, "{}", v.0),
+            Cell::Jsonb(v) => write!(f, "{}", v.0),
+            Cell::Bytea(v) => write!(f, "\\x{}", hex::encode(v)),
+            Cell::Uuid(v) => write!(f, "{}", uuid_to_string(v)),
             Cell::Date(v) => unsafe {
                 let dt =
                     fcinfo::direct_function_call_as_datum(pgrx::pg_sys::date_out, &[(*v).into_datum()])
@@ -210,7 +583,7 @@ impl fmt::Display for Cell {
                 let dt_cstr = CStr::from_ptr(dt.cast_mut_ptr());
                 write!(
                     f,
-                    "'{}'",
+                    "{}",
                     dt_cstr.to_str().expect("date should be a valid string")
                 )
             },
@@ -221,7 +594,7 @@ impl fmt::Display for Cell {
                 let ts_cstr = CStr::from_ptr(ts.cast_mut_ptr());
                 write!(
                     f,
-                    "'{}'",
+                    "{}",
                     ts_cstr.to_str().expect("time hould be a valid string")
                 )
             },
@@ -234,7 +607,7 @@ impl fmt::Display for Cell {
                 let ts_cstr = CStr::from_ptr(ts.cast_mut_ptr());
                 write!(
                     f,
-                    "'{}'",
+                    "{}",
                     ts_cstr
                         .to_str()
                         .expect("timestamp should be a valid string")
@@ -249,7 +622,7 @@ impl fmt::Display for Cell {
                 let ts_cstr = CStr::from_ptr(ts.cast_mut_ptr());
                 write!(
                     f,
-                    "'{}'",
+                    "{}",
                     ts_cstr
                         .to_str()
                         .expect("timestamptz should be a valid string")
@@ -263,10 +636,236 @@ impl fmt::Display for Cell {
             Cell::F32Array(v) => write_array(v, f),
             Cell::F64Array(v) => write_array(v, f),
             Cell::StringArray(v) => write_array(v, f),
+            Cell::VarcharArray(v) => write_array(v, f),
+            Cell::BpcharArray(v) => write_array(v, f),
+            Cell::NameArray(v) => write_array(v, f),
+            Cell::JsonArray(v) => write_array(&map_array(v, |j| j.0.to_string()), f),
+            Cell::JsonbArray(v) => write_array(&map_array(v, |j| j.0.to_string()), f),
+            Cell::ByteaArray(v) => write_array(&map_array(v, |b| format!("\\x{}", hex::encode(b))), f),
+            Cell::UuidArray(v) => write_array(&map_array(v, uuid_to_string), f),
+            Cell::Other { text, .. } => write!(f, "{}", text),
         }
     }
 }
 
+/// Text of a datum from the output function of its type
+pub(crate) unsafe fn output_text(datum: Datum, typoid: Oid) -> String {
+    let mut output = Oid::INVALID;
+    let mut is_varlena = false;
+    pg_sys::getTypeOutputInfo(typoid, &mut output, &mut is_varlena);
+    let text = pg_sys::OidOutputFunctionCall(output, datum);
+    let res = CStr::from_ptr(text).to_string_lossy().into_owned();
+    pg_sys::pfree(text as _);
+    res
+}
+
+/// Datum of a text with the input function of its type
+pub(crate) unsafe fn input_datum(text: &str, typoid: Oid) -> Option<Datum> {
+    let text = CString::new(text).ok()?;
+    let mut input = Oid::INVALID;
+    let mut typioparam = Oid::INVALID;
+    pg_sys::getTypeInputInfo(typoid, &mut input, &mut typioparam);
+    Some(pg_sys::OidInputFunctionCall(input, text.as_ptr() as *mut _, typioparam, -1))
+}
+
+/// Error of `Cell::parse`: a value its type does not accept
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// column the value was read for, when the caller knows it
+    pub column: Option<String>,
+    pub value: String,
+    /// SQL name of the expected type, with its modifier
+    pub type_name: String,
+    /// error of the input function
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn in_column(mut self, column: &str) -> Self {
+        self.column = Some(column.to_string());
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {} value \"{}\"", self.type_name, self.value)?;
+        if let Some(column) = &self.column {
+            write!(f, " for column \"{}\"", column)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl Cell {
+    /// Parse the text form of a value of type `typoid` with its input
+    /// function, checking `typmod` (-1 for none) like a cast would. Domains
+    /// check their constraints and give a cell of their base type. A value
+    /// the type does not accept is an error rather than a Postgres ERROR.
+    pub fn parse(text: &str, typoid: Oid, typmod: i32) -> Result<Cell, ParseError> {
+        let error = |message: String| ParseError {
+            column: None,
+            value: text.to_string(),
+            type_name: unsafe { string_from_cstr(pg_sys::format_type_with_typemod(typoid, typmod)) },
+            message,
+        };
+        let c_text = CString::new(text).map_err(|_| error("the value contains a zero byte".to_string()))?;
+        unsafe {
+            let mut input = Oid::INVALID;
+            let mut typioparam = Oid::INVALID;
+            pg_sys::getTypeInputInfo(typoid, &mut input, &mut typioparam);
+            let mut finfo = pg_sys::FmgrInfo::default();
+            pg_sys::fmgr_info(input, &mut finfo);
+            let datum = input_function_call(&mut finfo, &c_text, typioparam, typmod).map_err(error)?;
+            Cell::from_polymorphic_datum(datum, false, typoid)
+                .ok_or_else(|| error("the input function returned NULL".to_string()))
+        }
+    }
+}
+
+/// `InputFunctionCall` returning the error of the input function. Postgres
+/// 16 reports it softly, older versions raise it and it is caught in a
+/// subtransaction, rolled back so that what the input function left behind
+/// (locks, buffer pins, a half-done catalog lookup) is released.
+#[cfg(feature = "pg16")]
+unsafe fn input_function_call(finfo: &mut pg_sys::FmgrInfo, text: &CStr, typioparam: Oid, typmod: i32) -> Result<Datum, String> {
+    let mut escontext = PgBox::<pg_sys::ErrorSaveContext>::alloc_node(pg_sys::NodeTag::T_ErrorSaveContext);
+    escontext.details_wanted = true;
+    let mut datum = Datum::null();
+    let ok = pg_sys::InputFunctionCallSafe(
+        finfo,
+        text.as_ptr() as *mut _,
+        typioparam,
+        typmod,
+        escontext.as_ptr() as *mut pg_sys::Node,
+        &mut datum,
+    );
+    if ok {
+        return Ok(datum);
+    }
+    let error = escontext.error_data;
+    if error.is_null() || (*error).message.is_null() {
+        return Err("invalid input".to_string());
+    }
+    Err(CStr::from_ptr((*error).message).to_string_lossy().into_owned())
+}
+
+#[cfg(not(feature = "pg16"))]
+unsafe fn input_function_call(finfo: &mut pg_sys::FmgrInfo, text: &CStr, typioparam: Oid, typmod: i32) -> Result<Datum, String> {
+    let finfo = finfo as *mut pg_sys::FmgrInfo;
+    let text = text.as_ptr() as *mut std::ffi::c_char;
+    let oldcontext = pg_sys::CurrentMemoryContext;
+    let oldowner = pg_sys::CurrentResourceOwner;
+    pg_sys::BeginInternalSubTransaction(std::ptr::null());
+    // the datum outlives the subtransaction
+    pg_sys::MemoryContextSwitchTo(oldcontext);
+    PgTryBuilder::new(|| {
+        let datum = pg_sys::InputFunctionCall(finfo, text, typioparam, typmod);
+        pg_sys::ReleaseCurrentSubTransaction();
+        pg_sys::MemoryContextSwitchTo(oldcontext);
+        pg_sys::CurrentResourceOwner = oldowner;
+        Ok(datum)
+    })
+    .catch_others(|e| {
+        pg_sys::RollbackAndReleaseCurrentSubTransaction();
+        pg_sys::MemoryContextSwitchTo(oldcontext);
+        pg_sys::CurrentResourceOwner = oldowner;
+        match e {
+            CaughtError::PostgresError(report) | CaughtError::ErrorReport(report) => Err(report.message().to_string()),
+            e => e.rethrow(),
+        }
+    })
+    .execute()
+}
+
+fn map_array<T>(array: &[Option<T>], f: impl Fn(&T) -> String) -> Vec<Option<String>> {
+    array.iter().map(|e| e.as_ref().map(&f)).collect()
+}
+
+/// Hyphenated form of a uuid, as printed by `uuid_out`
+fn uuid_to_string(uuid: &Uuid) -> String {
+    let hex = hex::encode(uuid.as_bytes());
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+unsafe fn name_from_datum(datum: Datum, is_null: bool) -> Option<String> {
+    if is_null {
+        return None;
+    }
+    let name = datum.cast_mut_ptr::<pg_sys::NameData>();
+    Some(pgrx::name_data_to_str(&*name).to_string())
+}
+
+fn name_into_datum(name: &str) -> Option<Datum> {
+    // `namein` truncates to NAMEDATALEN like a cast to name does
+    let name = CString::new(name).ok()?;
+    unsafe { fcinfo::direct_function_call_as_datum(pg_sys::namein, &[name.as_c_str().into_datum()]) }
+}
+
+/// Elements of a one dimensional array of `elemtype`, converted through
+/// `Cell`
+unsafe fn array_from_datum<T>(
+    datum: Datum,
+    is_null: bool,
+    elemtype: Oid,
+    f: impl Fn(Cell) -> Option<T>,
+) -> Option<Vec<Option<T>>> {
+    if is_null || datum.is_null() {
+        return None;
+    }
+    let array = pg_sys::pg_detoast_datum(datum.cast_mut_ptr()) as *mut pg_sys::ArrayType;
+    let (mut typlen, mut typbyval, mut typalign) = (0, false, 0);
+    pg_sys::get_typlenbyvalalign(elemtype, &mut typlen, &mut typbyval, &mut typalign);
+    let mut elems: *mut Datum = std::ptr::null_mut();
+    let mut nulls: *mut bool = std::ptr::null_mut();
+    let mut nelems = 0;
+    pg_sys::deconstruct_array(array, elemtype, typlen as _, typbyval, typalign, &mut elems, &mut nulls, &mut nelems);
+
+    let mut values = Vec::with_capacity(nelems as usize);
+    for i in 0..nelems as usize {
+        let cell = Cell::from_polymorphic_datum(*elems.add(i), *nulls.add(i), elemtype);
+        values.push(cell.and_then(&f));
+    }
+    Some(values)
+}
+
+/// One dimensional array of `elemtype` from values converted through `Cell`
+pub(crate) fn array_into_datum<T>(values: Vec<Option<T>>, elemtype: Oid, f: impl Fn(T) -> Cell) -> Option<Datum> {
+    let mut elems = Vec::with_capacity(values.len());
+    let mut nulls = Vec::with_capacity(values.len());
+    for value in values {
+        match value.and_then(|v| f(v).into_datum()) {
+            Some(datum) => {
+                elems.push(datum);
+                nulls.push(false);
+            }
+            None => {
+                elems.push(Datum::null());
+                nulls.push(true);
+            }
+        }
+    }
+    unsafe {
+        let (mut typlen, mut typbyval, mut typalign) = (0, false, 0);
+        pg_sys::get_typlenbyvalalign(elemtype, &mut typlen, &mut typbyval, &mut typalign);
+        let mut dims = [elems.len() as i32];
+        let mut lbs = [1];
+        let array = pg_sys::construct_md_array(
+            elems.as_mut_ptr(),
+            nulls.as_mut_ptr(),
+            1,
+            dims.as_mut_ptr(),
+            lbs.as_mut_ptr(),
+            elemtype,
+            typlen as _,
+            typbyval,
+            typalign,
+        );
+        Some(Datum::from(array))
+    }
+}
+
+/// Array in the text form of Postgres, `{1,NULL,"a b"}`
 fn write_array<T: std::fmt::Display>(
     array: &[Option<T>],
     f: &mut fmt::Formatter<'_>,
@@ -274,17 +873,185 @@ fn write_array<T: std::fmt::Display>(
     let res = array
         .iter()
         .map(|e| match e {
-            Some(val) => format!("{}", val),
-            None => "null".to_owned(),
+            Some(val) => quote_array_element(&val.to_string()),
+            None => "NULL".to_owned(),
         })
         .collect::<Vec<String>>()
         .join(",");
-    write!(f, "[{}]", res)
+    write!(f, "{{{}}}", res)
 }
 
-pub fn parse_cell(val: &str) -> Cell {
-    match val.parse::<i32>() {
-        Ok(i) => Cell::I32(i),
-        Err(_) => Cell::String(val.to_string()),
+/// Element of an array text, double quoted when array_in would read it
+/// differently otherwise
+fn quote_array_element(text: &str) -> String {
+    let needs_quotes = text.is_empty()
+        || text.eq_ignore_ascii_case("NULL")
+        || text.chars().any(|c| matches!(c, '{' | '}' | ',' | '"' | '\\') || c.is_whitespace());
+    if !needs_quotes {
+        return text.to_owned();
+    }
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn write_special_float(v: f64, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if v.is_nan() {
+        write!(f, "NaN")
+    } else if v > 0.0 {
+        write!(f, "Infinity")
+    } else {
+        write!(f, "-Infinity")
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use pgrx_macros::pg_test;
+    use pgrx::{pg_sys, AnyNumeric, FromDatum, IntoDatum, Spi};
+    use crate::fdw::utils_share::{cell::Cell, utils::{get_datum, string_from_cstr}};
+
+    /// Read a value with the input function of its type into a `Cell` and
+    /// print the datum of the cell with the output function
+    fn round_trip(text: &str, typid: pg_sys::Oid) -> (Cell, String) {
+        unsafe {
+            let cell = Cell::from_polymorphic_datum(get_datum(text, typid), false, typid)
+                .unwrap_or_else(|| panic!("no cell for {} of type {}", text, typid));
+            let datum = cell.clone().into_datum().expect("cell should convert to a datum");
+            let mut output = pg_sys::Oid::INVALID;
+            let mut is_varlena = false;
+            pg_sys::getTypeOutputInfo(typid, &mut output, &mut is_varlena);
+            (cell, string_from_cstr(pg_sys::OidOutputFunctionCall(output, datum)))
+        }
+    }
+
+    #[pg_test]
+    fn test_cell_character_types() {
+        let (cell, text) = round_trip("abc", pg_sys::VARCHAROID);
+        assert!(matches!(cell, Cell::Varchar(ref v) if v == "abc"));
+        assert_eq!(text, "abc");
+        let (cell, text) = round_trip("ab  ", pg_sys::BPCHAROID);
+        assert!(matches!(cell, Cell::Bpchar(_)));
+        assert_eq!(text, "ab  ");
+        let (cell, text) = round_trip("pg_class", pg_sys::NAMEOID);
+        assert_eq!(cell.to_string(), "pg_class");
+        assert_eq!(text, "pg_class");
+        let (_, text) = round_trip("{a,NULL,\"b c\"}", pg_sys::VARCHARARRAYOID);
+        assert_eq!(text, "{a,NULL,\"b c\"}");
+        let (_, text) = round_trip("{pg_class,pg_type}", pg_sys::NAMEARRAYOID);
+        assert_eq!(text, "{pg_class,pg_type}");
+    }
+
+    #[pg_test]
+    fn test_cell_json_bytea_uuid() {
+        let (cell, text) = round_trip("{\"a\": [1, 2]}", pg_sys::JSONBOID);
+        assert_eq!(cell.to_string(), "{\"a\":[1,2]}");
+        assert_eq!(text, "{\"a\": [1, 2]}");
+        let (_, text) = round_trip("{\"a\":1}", pg_sys::JSONOID);
+        assert_eq!(text, "{\"a\":1}");
+        let (cell, text) = round_trip("\\x01ff", pg_sys::BYTEAOID);
+        assert_eq!(cell.to_string(), "\\x01ff");
+        assert_eq!(text, "\\x01ff");
+        let uuid = "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11";
+        let (cell, text) = round_trip(uuid, pg_sys::UUIDOID);
+        assert_eq!(cell.to_string(), uuid);
+        assert_eq!(text, uuid);
+        let (_, text) = round_trip("{\"{}\",NULL}", pg_sys::JSONBARRAYOID);
+        assert_eq!(text, "{\"{}\",NULL}");
+        let (_, text) = round_trip("{\"\\\\x00\"}", pg_sys::BYTEAARRAYOID);
+        assert_eq!(text, "{\"\\\\x00\"}");
+        let (_, text) = round_trip(&format!("{{{}}}", uuid), pg_sys::UUIDARRAYOID);
+        assert_eq!(text, format!("{{{}}}", uuid));
+    }
+
+    #[pg_test]
+    fn test_cell_other_types() {
+        Spi::run("create type mood as enum ('sad', 'happy')").unwrap();
+        Spi::run("create domain positive as int4 check (value > 0)").unwrap();
+        let typid = |name: &str| {
+            Spi::get_one::<pg_sys::Oid>(&format!("select '{}'::regtype::oid", name))
+                .unwrap()
+                .unwrap()
+        };
+
+        let mood = typid("mood");
+        let (cell, text) = round_trip("happy", mood);
+        assert!(matches!(cell, Cell::Other { typoid, .. } if typoid == mood));
+        assert_eq!(cell.to_string(), "happy");
+        assert_eq!(text, "happy");
+        let (cell, text) = round_trip("10.0.0.1/24", pg_sys::INETOID);
+        assert_eq!(cell.to_string(), "10.0.0.1/24");
+        assert_eq!(text, "10.0.0.1/24");
+
+        // domains read as their base type
+        let cell = unsafe { Cell::from_polymorphic_datum(get_datum("7", typid("positive")), false, typid("positive")) };
+        assert!(matches!(cell, Some(Cell::I32(7))));
+        let cell = unsafe { Cell::from_polymorphic_datum(pg_sys::Datum::from(0), true, mood) };
+        assert!(cell.is_none());
+    }
+
+    #[test]
+    fn test_cell_comparison() {
+        assert_eq!(Cell::I32(21), Cell::I64(21));
+        assert!(Cell::I16(2) < Cell::F64(2.5));
+        assert_eq!(Cell::Bpchar("ab  ".to_string()), Cell::String("ab".to_string()));
+        assert!(Cell::String("a".to_string()) < Cell::Varchar("b".to_string()));
+        assert_ne!(Cell::F64(f64::NAN), Cell::F64(f64::NAN));
+        assert_eq!(Cell::String("1".to_string()).partial_cmp(&Cell::I32(1)), None);
+        assert!(Cell::I64Array(vec![Some(1), None]) > Cell::I64Array(vec![Some(1), Some(2)]));
+        assert!(Cell::I64Array(vec![Some(1)]) < Cell::I32Array(vec![Some(1), Some(0)]));
+    }
+
+    #[test]
+    fn test_cell_sql_literal() {
+        assert_eq!(Cell::I64(-3).to_sql_literal(), "-3");
+        assert_eq!(Cell::Bool(true).to_sql_literal(), "true");
+        assert_eq!(Cell::String("it's".to_string()).to_sql_literal(), "'it''s'");
+        assert_eq!(Cell::String("a\\b".to_string()).to_string(), "a\\b");
+        assert_eq!(Cell::String("a\\b".to_string()).to_sql_literal(), "E'a\\\\b'");
+        assert_eq!(Cell::F64(f64::INFINITY).to_sql_literal(), "'Infinity'::double precision");
+        assert_eq!(
+            Cell::StringArray(vec![Some("a b".to_string()), None, Some("NULL".to_string())]).to_sql_literal(),
+            "'{\"a b\",NULL,\"NULL\"}'::text[]"
+        );
+        assert_eq!(Cell::Bytea(vec![0, 255]).to_sql_literal(), "E'\\\\x00ff'::bytea");
+        assert_eq!(Cell::I8(b'a' as i8).to_sql_literal(), "'a'::\"char\"");
+        assert_eq!(Cell::I8(b'\'' as i8).to_sql_literal(), "''''::\"char\"");
+        assert_eq!(Cell::I8(-31).to_sql_literal(), "E'\\\\341'::\"char\"");
+    }
+
+    #[pg_test]
+    fn test_cell_comparison_with_postgres_types() {
+        let cell = |text: &str, typid| unsafe { Cell::from_polymorphic_datum(get_datum(text, typid), false, typid).unwrap() };
+        assert!(cell("2024-01-31", pg_sys::DATEOID) < cell("2024-02-01", pg_sys::DATEOID));
+        assert!(cell("1960-01-01 10:00", pg_sys::TIMESTAMPOID) < cell("2000-01-01", pg_sys::TIMESTAMPOID));
+        assert_eq!(cell("1.50", pg_sys::NUMERICOID), Cell::F64(1.5));
+        assert_eq!(Cell::Numeric(AnyNumeric::from(7)), Cell::I32(7));
+        assert_eq!(cell("2024-01-31", pg_sys::DATEOID).to_string(), "2024-01-31");
+        assert_eq!(cell("2024-01-31", pg_sys::DATEOID).to_sql_literal(), "'2024-01-31'::date");
+        assert_eq!(cell("10.0.0.1", pg_sys::INETOID).to_sql_literal(), "'10.0.0.1'::inet");
+        assert_eq!(cell("10.0.0.1", pg_sys::INETOID), cell("10.0.0.1", pg_sys::INETOID));
+    }
+
+    #[pg_test]
+    fn test_cell_parse() {
+        assert!(matches!(Cell::parse(" 42 ", pg_sys::INT4OID, -1), Ok(Cell::I32(42))));
+        assert!(matches!(Cell::parse("2024-01-31", pg_sys::DATEOID, -1), Ok(Cell::Date(_))));
+        assert!(matches!(Cell::parse("", pg_sys::TEXTOID, -1), Ok(Cell::String(ref v)) if v.is_empty()));
+
+        let err = Cell::parse("abc", pg_sys::INT4OID, -1).unwrap_err().in_column("id");
+        assert_eq!(err.type_name, "integer");
+        assert_eq!(
+            err.to_string(),
+            "invalid integer value \"abc\" for column \"id\": invalid input syntax for type integer: \"abc\""
+        );
+        // the type modifier is checked, varchar(3) holds 3 characters plus VARHDRSZ
+        let err = Cell::parse("abcd", pg_sys::VARCHAROID, 3 + 4).unwrap_err();
+        assert_eq!(err.type_name, "character varying(3)");
+        assert!(Cell::parse("99999999999", pg_sys::INT4OID, -1).is_err());
+
+        Spi::run("create domain small_positive as int4 check (value between 1 and 9)").unwrap();
+        let domain = Spi::get_one::<pg_sys::Oid>("select 'small_positive'::regtype::oid").unwrap().unwrap();
+        assert!(matches!(Cell::parse("5", domain, -1), Ok(Cell::I32(5))));
+        assert!(Cell::parse("10", domain, -1).is_err());
     }
 }
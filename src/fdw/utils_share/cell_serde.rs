@@ -0,0 +1,330 @@
+//! Serde support for `Cell` and `Row`.
+//!
+//! A cell serializes to a plain value: numbers for integers and floats,
+//! strings for numerics (no rounding through f64), character types, uuids,
+//! intervals and ISO-8601 dates and times, base64 strings for bytea, the
+//! document itself for json and jsonb, and sequences for arrays. Types without
+//! a variant of their own serialize to their text form.
+//!
+//! Plain values deserialize to a best guess of the variant. `Tagged` adds the
+//! type of each value, `{"type": "numeric", "value": "1.50"}`, so the exact
+//! variant comes back. A row serializes to a map of its columns in order.
+use std::{ffi::CStr, fmt, ptr};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use pgrx::{fcinfo, pg_sys::{self, Datum, Oid}, IntoDatum, JsonB};
+use serde::{
+    de::{self, Deserializer, MapAccess, Visitor},
+    ser::{SerializeMap, SerializeSeq, Serializer},
+    Deserialize, Serialize,
+};
+use serde_json::Value;
+use crate::fdw::utils_share::{
+    cell::{array_into_datum, Cell},
+    row::Row,
+    utils::string_from_cstr,
+};
+
+/// Cell or row serialized with the type of each value
+#[derive(Debug, Clone)]
+pub struct Tagged<T>(pub T);
+
+impl Serialize for Cell {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Cell::Bool(v) => serializer.serialize_bool(*v),
+            Cell::I8(v) => serializer.serialize_i8(*v),
+            Cell::I16(v) => serializer.serialize_i16(*v),
+            Cell::I32(v) => serializer.serialize_i32(*v),
+            Cell::I64(v) => serializer.serialize_i64(*v),
+            Cell::F32(v) if v.is_finite() => serializer.serialize_f32(*v),
+            Cell::F64(v) if v.is_finite() => serializer.serialize_f64(*v),
+            // NaN and infinities have no JSON number
+            Cell::F32(v) => serializer.serialize_str(&special_float(*v as f64)),
+            Cell::F64(v) => serializer.serialize_str(&special_float(*v)),
+            Cell::Numeric(v) => serializer.serialize_str(&v.to_string()),
+            Cell::String(v) | Cell::Varchar(v) | Cell::Bpchar(v) | Cell::Name(v) => serializer.serialize_str(v),
+            Cell::Json(v) => v.0.serialize(serializer),
+            Cell::Jsonb(v) => v.0.serialize(serializer),
+            Cell::Bytea(v) => serializer.serialize_str(&BASE64.encode(v)),
+            Cell::Date(v) => serializer.serialize_str(&iso_text((*v).into_datum(), pg_sys::DATEOID)),
+            Cell::Time(v) => serializer.serialize_str(&iso_text((*v).into_datum(), pg_sys::TIMEOID)),
+            Cell::Timestamp(v) => serializer.serialize_str(&iso_text((*v).into_datum(), pg_sys::TIMESTAMPOID)),
+            Cell::Timestamptz(v) => serializer.serialize_str(&iso_text((*v).into_datum(), pg_sys::TIMESTAMPTZOID)),
+            Cell::Uuid(_) | Cell::Interval(_) | Cell::Other { .. } => serializer.serialize_str(&self.to_string()),
+            _ => {
+                let elements = self.array_elements().unwrap_or_default();
+                let mut seq = serializer.serialize_seq(Some(elements.len()))?;
+                for element in &elements {
+                    seq.serialize_element(element)?;
+                }
+                seq.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Cell {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        guess_cell(&value).ok_or_else(|| de::Error::custom("a cell cannot be null"))
+    }
+}
+
+impl Serialize for Tagged<&Cell> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("type", &type_name(self.0.typoid()))?;
+        map.serialize_entry("value", self.0)?;
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Tagged<Cell> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Repr {
+            #[serde(rename = "type")]
+            typ: String,
+            value: Value,
+        }
+
+        let repr = Repr::deserialize(deserializer)?;
+        let typoid = type_from_name(&repr.typ).ok_or_else(|| de::Error::custom(format!("unknown type \"{}\"", repr.typ)))?;
+        Cell::from_json(&repr.value, typoid)
+            .map_err(de::Error::custom)?
+            .map(Tagged)
+            .ok_or_else(|| de::Error::custom("a cell cannot be null"))
+    }
+}
+
+impl Serialize for Row {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.cols.len()))?;
+        for (col, cell) in self.iter() {
+            map.serialize_entry(col, cell)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Row {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(RowVisitor(|cell: Option<Cell>| cell))
+    }
+}
+
+impl Serialize for Tagged<&Row> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.cols.len()))?;
+        for (col, cell) in self.0.iter() {
+            map.serialize_entry(col, &cell.as_ref().map(Tagged))?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Tagged<Row> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer
+            .deserialize_map(RowVisitor(|cell: Option<Tagged<Cell>>| cell.map(|c| c.0)))
+            .map(Tagged)
+    }
+}
+
+/// Reads a map of columns into a row, keeping their order
+struct RowVisitor<F>(F);
+
+impl<'de, T: Deserialize<'de>, F: Fn(Option<T>) -> Option<Cell>> Visitor<'de> for RowVisitor<F> {
+    type Value = Row;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a map of column names to cells")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Row, A::Error> {
+        let mut row = Row::new();
+        while let Some((col, cell)) = access.next_entry::<String, Option<T>>()? {
+            row.push(&col, (self.0)(cell));
+        }
+        Ok(row)
+    }
+}
+
+impl Cell {
+    /// Read a serialized value as a cell of type `typoid`, `None` for null.
+    /// Strings go through the input function of the type with `Cell::parse`,
+    /// so any text form of the type is accepted and a bad one is an error.
+    pub fn from_json(value: &Value, typoid: Oid) -> Result<Option<Cell>, String> {
+        if value.is_null() {
+            return Ok(None);
+        }
+        let basetype = unsafe { pg_sys::getBaseType(typoid) };
+        let text = match (basetype, value) {
+            // the text of the document reads as json or jsonb
+            (pg_sys::JSONOID | pg_sys::JSONBOID, _) => value.to_string(),
+            // a "char" serializes as its byte, the text would read as its first digit
+            (pg_sys::CHAROID, Value::Number(n)) => {
+                return n
+                    .as_i64()
+                    .and_then(|v| i8::try_from(v).ok())
+                    .map(|v| Some(Cell::I8(v)))
+                    .ok_or_else(|| format!("invalid value for type {}: {}", type_name(typoid), value));
+            }
+            (pg_sys::BYTEAOID, Value::String(s)) => {
+                let bytes = BASE64
+                    .decode(s)
+                    .map_err(|e| format!("invalid base64 value for bytea: {}", e))?;
+                let datum = bytes.into_datum().unwrap_or_default();
+                return Ok(unsafe { Cell::from_polymorphic_datum(datum, false, basetype) });
+            }
+            (_, Value::Array(elements)) => {
+                let elemtype = unsafe { pg_sys::get_element_type(basetype) };
+                if elemtype == Oid::INVALID {
+                    return Err(format!("array given for type {}", type_name(typoid)));
+                }
+                let cells = elements
+                    .iter()
+                    .map(|element| Cell::from_json(element, elemtype))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let datum = unsafe { array_into_datum(cells, elemtype, |cell| cell) }
+                    .ok_or_else(|| format!("invalid value for type {}: {}", type_name(typoid), value))?;
+                return Ok(unsafe { Cell::from_polymorphic_datum(datum, false, basetype) });
+            }
+            (_, Value::String(s)) => s.clone(),
+            (_, Value::Bool(_) | Value::Number(_)) => value.to_string(),
+            (_, _) => return Err(format!("object given for type {}", type_name(typoid))),
+        };
+        Cell::parse(&text, typoid, -1).map(Some).map_err(|e| e.to_string())
+    }
+}
+
+/// Variant for a value serialized without its type: integers are bigints,
+/// other numbers doubles, strings text and objects jsonb. Arrays of a single
+/// kind of scalar become arrays of that kind, other arrays jsonb.
+fn guess_cell(value: &Value) -> Option<Cell> {
+    match value {
+        Value::Null => None,
+        Value::Bool(v) => Some(Cell::Bool(*v)),
+        Value::Number(n) => match n.as_i64() {
+            Some(v) => Some(Cell::I64(v)),
+            None => n.as_f64().map(Cell::F64),
+        },
+        Value::String(v) => Some(Cell::String(v.clone())),
+        Value::Object(_) => Some(Cell::Jsonb(JsonB(value.clone()))),
+        Value::Array(elements) => {
+            let scalars = elements.iter().filter(|e| !e.is_null());
+            let cell = if scalars.clone().all(Value::is_boolean) {
+                Cell::BoolArray(elements.iter().map(Value::as_bool).collect())
+            } else if scalars.clone().all(Value::is_i64) {
+                Cell::I64Array(elements.iter().map(Value::as_i64).collect())
+            } else if scalars.clone().all(Value::is_number) {
+                Cell::F64Array(elements.iter().map(Value::as_f64).collect())
+            } else if scalars.clone().all(Value::is_string) {
+                Cell::StringArray(elements.iter().map(|e| e.as_str().map(str::to_string)).collect())
+            } else {
+                Cell::Jsonb(JsonB(value.clone()))
+            };
+            Some(cell)
+        }
+    }
+}
+
+fn special_float(v: f64) -> String {
+    if v.is_nan() {
+        "NaN".to_string()
+    } else if v > 0.0 {
+        "Infinity".to_string()
+    } else {
+        "-Infinity".to_string()
+    }
+}
+
+/// ISO-8601 text of a date or time, as used by Postgres for json
+fn iso_text(datum: Option<Datum>, typoid: Oid) -> String {
+    let Some(datum) = datum else {
+        return String::new();
+    };
+    unsafe {
+        let text = pg_sys::JsonEncodeDateTime(ptr::null_mut(), datum, typoid, ptr::null());
+        let res = CStr::from_ptr(text).to_string_lossy().into_owned();
+        pg_sys::pfree(text as _);
+        res
+    }
+}
+
+/// SQL name of a type, e.g. `bigint[]` or `character varying`
+fn type_name(typoid: Oid) -> String {
+    unsafe { string_from_cstr(pg_sys::format_type_be(typoid)) }
+}
+
+/// Type of a name written by `type_name`
+fn type_from_name(name: &str) -> Option<Oid> {
+    unsafe { fcinfo::direct_function_call::<Oid>(pg_sys::to_regtype, &[name.into_datum()]) }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use pgrx_macros::pg_test;
+    use pgrx::{pg_sys, FromDatum};
+    use crate::fdw::utils_share::{cell::Cell, cell_serde::Tagged, row::Row, utils::get_datum};
+
+    fn cell(text: &str, typid: pg_sys::Oid) -> Cell {
+        unsafe { Cell::from_polymorphic_datum(get_datum(text, typid), false, typid).unwrap() }
+    }
+
+    #[pg_test]
+    fn test_cell_json_representation() {
+        let json = |c: &Cell| serde_json::to_string(c).unwrap();
+        assert_eq!(json(&cell("42", pg_sys::INT8OID)), "42");
+        assert_eq!(json(&cell("1.50", pg_sys::NUMERICOID)), "\"1.50\"");
+        assert_eq!(json(&cell("NaN", pg_sys::FLOAT8OID)), "\"NaN\"");
+        assert_eq!(json(&cell("2024-02-29", pg_sys::DATEOID)), "\"2024-02-29\"");
+        assert_eq!(json(&cell("2024-02-29 10:30:00.5", pg_sys::TIMESTAMPOID)), "\"2024-02-29T10:30:00.5\"");
+        assert_eq!(json(&cell("\\x0102ff", pg_sys::BYTEAOID)), "\"AQL/\"");
+        assert_eq!(json(&cell("{\"a\": 1}", pg_sys::JSONBOID)), "{\"a\":1}");
+        assert_eq!(json(&cell("{1,NULL,3}", pg_sys::INT4ARRAYOID)), "[1,null,3]");
+        assert_eq!(
+            serde_json::to_string(&Tagged(&cell("1.50", pg_sys::NUMERICOID))).unwrap(),
+            "{\"type\":\"numeric\",\"value\":\"1.50\"}"
+        );
+    }
+
+    #[pg_test]
+    fn test_row_round_trip() {
+        let mut row = Row::new();
+        row.push("id", Some(cell("7", pg_sys::INT8OID)));
+        row.push("price", Some(cell("19.99", pg_sys::NUMERICOID)));
+        row.push("seen", Some(cell("2024-02-29 10:30:00+00", pg_sys::TIMESTAMPTZOID)));
+        row.push("data", Some(cell("\\xdeadbeef", pg_sys::BYTEAOID)));
+        row.push("tags", Some(cell("{a,b}", pg_sys::VARCHARARRAYOID)));
+        row.push("note", None);
+
+        // with tags every variant comes back
+        let json = serde_json::to_string(&Tagged(&row)).unwrap();
+        let back: Tagged<Row> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.0.cols, row.cols);
+        for (a, b) in back.0.cells.iter().zip(&row.cells) {
+            assert_eq!(format!("{:?}", a), format!("{:?}", b));
+        }
+
+        // without them values come back as the closest plain variant
+        let json = serde_json::to_string(&row).unwrap();
+        let back: Row = serde_json::from_str(&json).unwrap();
+        assert!(matches!(back.cells[0], Some(Cell::I64(7))));
+        assert!(matches!(&back.cells[1], Some(Cell::String(v)) if v == "19.99"));
+        assert!(matches!(&back.cells[4], Some(Cell::StringArray(v)) if v.len() == 2));
+        assert!(back.cells[5].is_none());
+    }
+
+    #[pg_test]
+    fn test_tagged_cell_errors() {
+        let json = serde_json::to_string(&Tagged(&Cell::I8(b'a' as i8))).unwrap();
+        let back: Tagged<Cell> = serde_json::from_str(&json).unwrap();
+        assert!(matches!(back.0, Cell::I8(v) if v == b'a' as i8));
+
+        // a bad value is a serde error, not a Postgres one
+        let err = serde_json::from_str::<Tagged<Cell>>(r#"{"type":"integer","value":"two"}"#).unwrap_err();
+        assert!(err.to_string().contains("invalid input syntax for type integer"), "{}", err);
+    }
+}
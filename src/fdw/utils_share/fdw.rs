@@ -0,0 +1,1461 @@
+//! Safe interface for writing a foreign data wrapper.
+//!
+//! A wrapper implements [`ForeignDataWrapper`] and declares its handler and
+//! validator functions with [`foreign_data_wrapper!`]. The generic callbacks
+//! of this module own the wrapper instance, run it in a memory context of its
+//! own, convert rows from and to tuple slots and raise its errors.
+//!
+//! ```rust,ignore
+//! struct MyFdw { rows: std::vec::IntoIter<Row> }
+//!
+//! impl ForeignDataWrapper for MyFdw {
+//!     fn new(_relid: Oid, _options: &HashMap<String, String>) -> FdwResult<Self> {
+//!         Ok(MyFdw { rows: Vec::new().into_iter() })
+//!     }
+//!     fn begin_scan(
+//!         &mut self,
+//!         _quals: &[Qual],
+//!         _columns: &[Column],
+//!         _sorts: &[Sort],
+//!         _limit: &Option<Limit>,
+//!     ) -> FdwResult<()> {
+//!         Ok(())
+//!     }
+//!     fn iter_scan(&mut self) -> FdwResult<Option<Row>> { Ok(self.rows.next()) }
+//! }
+//!
+//! foreign_data_wrapper!(my_fdw_handler, my_fdw_validator, MyFdw);
+//! ```
+use std::{collections::{HashMap, HashSet}, ffi::c_int, ptr};
+use pgrx::{
+    memcx,
+    pg_sys::{self, panic::ErrorReport, AttrNumber, CmdType, MemoryContext, Oid},
+    prelude::*,
+    AllocatedByRust, FromDatum, IntoDatum, PgBox, PgMemoryContexts, PgRelation, PgTupleDesc,
+};
+use crate::fdw::utils_share::{
+    cell::{Cell, ParseError},
+    join::{extract_join, Join, JoinPlan},
+    limit::{extract_limit, limit_from_list, limit_to_list, Limit},
+    memory::create_wrappers_memctx,
+    qual::{extract_plan_quals, extract_quals, resolve_params, Qual},
+    row::Row,
+    sort::{extract_sorts, merge_join_sorts, pathkeys_to_sorts, query_pathkeys_head, sorts_from_list, sorts_to_list, Sort},
+    utils::{
+        build_attr_name_to_index_map, delete_wrappers_memctx, exec_clear_tuple, fill_slot_from_row,
+        get_foreign_table_options, list_items, pg_list_to_rust_list, slot_getattr, string_from_cstr, string_to_cstr,
+        tuple_table_slot_to_row,
+    },
+};
+#[cfg(not(feature = "pg13"))]
+use crate::fdw::utils_share::utils::{const_value, make_const};
+
+pub type FdwRoutine<A = AllocatedByRust> = PgBox<pg_sys::FdwRoutine, A>;
+
+pub type FdwResult<T> = Result<T, FdwError>;
+
+/// Error returned by a wrapper, raised as a Postgres error by the callbacks
+#[derive(Debug)]
+pub struct FdwError {
+    pub code: PgSqlErrorCode,
+    pub message: String,
+    pub detail: Option<String>,
+    pub hint: Option<String>,
+}
+
+impl FdwError {
+    pub fn new(code: PgSqlErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            detail: None,
+            hint: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// Error of an operation the wrapper does not implement
+    pub fn unsupported(operation: &str) -> Self {
+        Self::new(
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            format!("{} is not supported by this foreign data wrapper", operation),
+        )
+    }
+
+    /// Raise the error. Callers must not hold locks, the error unwinds.
+    pub fn report(self) -> ! {
+        let mut report = ErrorReport::new(self.code, self.message, "FdwError::report");
+        if let Some(detail) = self.detail {
+            report = report.set_detail(detail);
+        }
+        if let Some(hint) = self.hint {
+            report = report.set_hint(hint);
+        }
+        report.report(PgLogLevel::ERROR);
+        unreachable!("ERROR does not return")
+    }
+}
+
+impl From<String> for FdwError {
+    fn from(message: String) -> Self {
+        Self::new(PgSqlErrorCode::ERRCODE_FDW_ERROR, message)
+    }
+}
+
+impl From<ParseError> for FdwError {
+    fn from(error: ParseError) -> Self {
+        Self::new(PgSqlErrorCode::ERRCODE_INVALID_TEXT_REPRESENTATION, error.to_string())
+    }
+}
+
+/// Column of a foreign table
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    /// position in the tuple descriptor, starting at 0
+    pub colno: usize,
+    pub typid: Oid,
+    /// type modifier, e.g. the length of a varchar, -1 for none
+    pub typmod: i32,
+}
+
+/// `IMPORT FOREIGN SCHEMA` statement
+#[derive(Debug, Clone)]
+pub struct ImportSchema {
+    pub server_name: String,
+    pub server_options: HashMap<String, String>,
+    pub remote_schema: String,
+    pub local_schema: String,
+    pub list_type: pg_sys::ImportForeignSchemaType::Type,
+    /// tables listed in `LIMIT TO` or `EXCEPT`
+    pub table_list: HashSet<String>,
+}
+
+impl ImportSchema {
+    /// Whether `LIMIT TO` and `EXCEPT` let a remote table through
+    pub fn includes(&self, table: &str) -> bool {
+        match self.list_type {
+            pg_sys::ImportForeignSchemaType::FDW_IMPORT_SCHEMA_LIMIT_TO => self.table_list.contains(table),
+            pg_sys::ImportForeignSchemaType::FDW_IMPORT_SCHEMA_EXCEPT => !self.table_list.contains(table),
+            _ => true,
+        }
+    }
+}
+
+/// `UPDATE` or `DELETE` run as a whole by the wrapper instead of row by row
+#[derive(Debug, Clone)]
+pub struct DirectModify {
+    pub operation: CmdType::Type,
+    /// every restriction of the statement, with their parameters resolved
+    pub quals: Vec<Qual>,
+    /// column and new value of each SET target of an UPDATE
+    pub set: Vec<(String, Option<Cell>)>,
+}
+
+/// A foreign data wrapper. One instance is created per scanned or modified
+/// table of a statement, from the merged wrapper, server and table options.
+///
+/// Methods run in a temporary memory context that is reset before each call,
+/// so rows only have to stay valid until the next call. Errors are raised as
+/// Postgres errors after the method returns.
+pub trait ForeignDataWrapper: Sized {
+    fn new(relid: Oid, options: &HashMap<String, String>) -> FdwResult<Self>;
+
+    /// Estimated number of rows a scan with the restrictions `quals` reads,
+    /// for planning. The planner applies the selectivity of the restrictions
+    /// for the rows it returns.
+    fn estimate_rows(&mut self, _quals: &[Qual]) -> FdwResult<f64> {
+        Ok(1000.0)
+    }
+
+    /// Number of leading `sorts` of the query the scan can return its rows
+    /// in, planning then counts on that order
+    fn can_sort(&self, _sorts: &[Sort]) -> usize {
+        0
+    }
+
+    /// Whether the scan makes use of the limit of the query
+    fn can_limit(&self, _limit: &Limit) -> bool {
+        false
+    }
+
+    /// Start a scan. `quals` are the restrictions of the scan the wrapper may
+    /// check itself, with their parameters resolved. Rows must come in the
+    /// order of `sorts`, the sorts accepted by `can_sort` the plan chose. With
+    /// a `limit` the scan may stop after `Limit::rows` rows.
+    fn begin_scan(&mut self, quals: &[Qual], columns: &[Column], sorts: &[Sort], limit: &Option<Limit>) -> FdwResult<()>;
+
+    /// Next row of the scan, `None` at the end. Columns missing from the row
+    /// are NULL.
+    fn iter_scan(&mut self) -> FdwResult<Option<Row>>;
+
+    /// Restart the scan from the first row, parameters of the quals may have
+    /// new values
+    fn re_scan(&mut self, _quals: &[Qual]) -> FdwResult<()> {
+        Ok(())
+    }
+
+    fn end_scan(&mut self) -> FdwResult<()> {
+        Ok(())
+    }
+
+    /// Whether the wrapper can run `join` itself, replacing the scans of both
+    /// tables. Asked of an instance of the outer table.
+    fn can_join(&self, _join: &Join) -> bool {
+        false
+    }
+
+    /// Start a join accepted by `can_join`, on an instance of the outer table.
+    /// The joined rows come from `iter_scan`, their cells named after
+    /// `JoinColumn::label`, and `re_scan` and `end_scan` apply as for a scan.
+    fn begin_join(&mut self, _join: &Join) -> FdwResult<()> {
+        Err(FdwError::unsupported("join pushdown"))
+    }
+
+    /// Properties shown by EXPLAIN
+    fn explain(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    /// Whether the table accepts INSERT, UPDATE and DELETE
+    fn is_updatable(_options: &HashMap<String, String>) -> bool {
+        true
+    }
+
+    /// Column identifying the rows of UPDATE and DELETE
+    fn rowid_column(_options: &HashMap<String, String>) -> Option<String> {
+        None
+    }
+
+    /// Start an INSERT, UPDATE or DELETE. `returning` tells whether rows
+    /// returned by the modify methods are shown by a RETURNING list.
+    fn begin_modify(&mut self, _columns: &[Column], _returning: bool) -> FdwResult<()> {
+        Ok(())
+    }
+
+    /// Number of rows inserted at once by `insert_batch`
+    fn batch_size(&self) -> usize {
+        1
+    }
+
+    /// Insert a row and return the stored row for RETURNING, `None` returns
+    /// the row as inserted
+    fn insert(&mut self, _row: &Row) -> FdwResult<Option<Row>> {
+        Err(FdwError::unsupported("INSERT"))
+    }
+
+    fn insert_batch(&mut self, rows: &[Row]) -> FdwResult<()> {
+        rows.iter().try_for_each(|row| self.insert(row).map(|_| ()))
+    }
+
+    /// Replace the row identified by `rowid` and return the stored row,
+    /// `None` when there is no such row
+    fn update(&mut self, _rowid: &Cell, _new_row: &Row) -> FdwResult<Option<Row>> {
+        Err(FdwError::unsupported("UPDATE"))
+    }
+
+    /// Delete the row identified by `rowid` and return it, `None` when there
+    /// is no such row
+    fn delete(&mut self, _rowid: &Cell) -> FdwResult<Option<Row>> {
+        Err(FdwError::unsupported("DELETE"))
+    }
+
+    fn end_modify(&mut self) -> FdwResult<()> {
+        Ok(())
+    }
+
+    /// Whether `modify` can run as a whole in `modify_directly`. The wrapper
+    /// must then check every qual exactly, Postgres does not see the rows.
+    fn can_modify_directly(&self, _modify: &DirectModify) -> bool {
+        false
+    }
+
+    /// Run an UPDATE or DELETE accepted by `can_modify_directly`, between
+    /// `begin_modify` and `end_modify`. Returns the updated or deleted rows,
+    /// their number is the row count of the statement.
+    fn modify_directly(&mut self, _modify: &DirectModify) -> FdwResult<Vec<Row>> {
+        Err(FdwError::unsupported("direct modification"))
+    }
+
+    /// Remove every row of the table, for TRUNCATE
+    fn truncate(&mut self) -> FdwResult<()> {
+        Err(FdwError::unsupported("TRUNCATE"))
+    }
+
+    /// Check the options of a wrapper, server, user mapping, foreign table or
+    /// column, `catalog` is the oid of the catalog holding them. See
+    /// `options::check_options`.
+    fn validator(_options: &[(String, String)], _catalog: Oid) -> FdwResult<()> {
+        Ok(())
+    }
+
+    /// `CREATE FOREIGN TABLE` statements of `IMPORT FOREIGN SCHEMA`
+    fn import_schema(_stmt: &ImportSchema) -> FdwResult<Vec<String>> {
+        Err(FdwError::unsupported("IMPORT FOREIGN SCHEMA"))
+    }
+}
+
+/// Wrapper instance with the memory context its methods run in.
+///
+/// Planning keeps an instance in planner memory, dropped with it. Scans and
+/// modifications build their own in the Begin callbacks from the plan data in
+/// `fdw_private`, and drop it in the End callbacks, so that a plan can be run
+/// any number of times.
+pub struct FdwState<W: ForeignDataWrapper> {
+    pub instance: W,
+    pub tmp_ctx: MemoryContext,
+    pub columns: Vec<Column>,
+    pub header_name_to_colno: HashMap<String, usize>,
+    pub quals: Vec<Qual>,
+    pub sorts: Vec<Sort>,
+    pub limit: Option<Limit>,
+    rowid_attno: AttrNumber,
+    rowid_typid: Oid,
+    returning: bool,
+    /// whether the scan or modification was started, not the case for
+    /// EXPLAIN without ANALYZE
+    pub started: bool,
+    /// join the scan runs instead of a scan of the table
+    pub join: Option<Join>,
+}
+
+impl<W: ForeignDataWrapper> FdwState<W> {
+    pub unsafe fn new(instance: W, ctx_name: &str) -> Self {
+        Self {
+            instance,
+            tmp_ctx: create_wrappers_memctx(ctx_name),
+            columns: Vec::new(),
+            header_name_to_colno: HashMap::new(),
+            quals: Vec::new(),
+            sorts: Vec::new(),
+            limit: None,
+            rowid_attno: 0,
+            rowid_typid: Oid::INVALID,
+            returning: false,
+            started: false,
+            join: None,
+        }
+    }
+
+    /// Run a method in the reset temporary context, raising its error
+    pub unsafe fn call<T>(&mut self, f: impl FnOnce(&mut W) -> FdwResult<T>) -> T {
+        pg_sys::MemoryContextReset(self.tmp_ctx);
+        let instance = &mut self.instance;
+        let res = PgMemoryContexts::For(self.tmp_ctx).switch_to(|_| f(instance));
+        res.unwrap_or_else(|e| e.report())
+    }
+
+    /// Read the columns of the scanned relation, resolve the parameters of
+    /// the quals and start the scan. EXPLAIN without ANALYZE does not start
+    /// it.
+    pub unsafe fn begin_scan(&mut self, node: *mut pg_sys::ForeignScanState, eflags: c_int) {
+        let relation = (*node).ss.ss_currentRelation;
+        self.columns = relation_columns(relation);
+        self.header_name_to_colno = build_attr_name_to_index_map(relation);
+        if eflags & pg_sys::EXEC_FLAG_EXPLAIN_ONLY as c_int != 0 {
+            return;
+        }
+        resolve_params(&mut self.quals, node);
+        let (quals, columns, sorts, limit) = (self.quals.clone(), self.columns.clone(), self.sorts.clone(), self.limit);
+        self.call(|w| w.begin_scan(&quals, &columns, &sorts, &limit));
+        self.started = true;
+    }
+
+    /// Start a join, its rows fill the scan tuple in the order of
+    /// `Join::columns`
+    pub unsafe fn begin_join(&mut self, join: Join, eflags: c_int) {
+        self.header_name_to_colno = join.columns.iter().enumerate().map(|(colno, c)| (c.label(), colno)).collect();
+        if eflags & pg_sys::EXEC_FLAG_EXPLAIN_ONLY as c_int == 0 {
+            self.call(|w| w.begin_join(&join));
+            self.started = true;
+        }
+        self.join = Some(join);
+    }
+
+    /// Restart the scan with the current values of the parameters
+    pub unsafe fn re_scan(&mut self, node: *mut pg_sys::ForeignScanState) {
+        resolve_params(&mut self.quals, node);
+        let quals = self.quals.clone();
+        self.call(|w| w.re_scan(&quals));
+    }
+
+    /// Store the next row of the scan into `slot`, which is left empty at the
+    /// end. Returns whether there was a row.
+    pub unsafe fn next_row(&mut self, slot: *mut pg_sys::TupleTableSlot) -> bool {
+        exec_clear_tuple(slot);
+        let Some(row) = self.call(|w| w.iter_scan()) else {
+            return false;
+        };
+        self.store_row(slot, row);
+        true
+    }
+
+    /// Store a row into the slot, in the temporary context so that the datums
+    /// live until the next call
+    unsafe fn store_row(&self, slot: *mut pg_sys::TupleTableSlot, row: Row) {
+        PgMemoryContexts::For(self.tmp_ctx).switch_to(|_| fill_slot_from_row(slot, &row, &self.header_name_to_colno));
+    }
+
+    /// Show the row returned by a modify method when the statement has a
+    /// RETURNING list
+    unsafe fn return_row(&self, slot: *mut pg_sys::TupleTableSlot, row: Option<Row>) {
+        if let Some(row) = row.filter(|_| self.returning) {
+            self.store_row(slot, row);
+        }
+    }
+
+    unsafe fn rowid(&self, plan_slot: *mut pg_sys::TupleTableSlot) -> Option<Cell> {
+        let mut is_null = true;
+        let datum = slot_getattr(plan_slot, self.rowid_attno.into(), &mut is_null);
+        Cell::from_polymorphic_datum(datum, is_null, self.rowid_typid)
+    }
+}
+
+impl<W: ForeignDataWrapper> Drop for FdwState<W> {
+    fn drop(&mut self) {
+        unsafe { delete_wrappers_memctx(self.tmp_ctx) };
+    }
+}
+
+/// Columns of a relation, skipping dropped ones
+pub unsafe fn relation_columns(relation: pg_sys::Relation) -> Vec<Column> {
+    let tup_desc = PgTupleDesc::from_pg_copy((*relation).rd_att);
+    tup_desc
+        .iter()
+        .enumerate()
+        .filter(|(_, attr)| !attr.is_dropped())
+        .map(|(colno, attr)| Column {
+            name: pgrx::name_data_to_str(&attr.attname).to_string(),
+            colno,
+            typid: attr.atttypid,
+            typmod: attr.atttypmod,
+        })
+        .collect()
+}
+
+unsafe fn find_attribute(relation: pg_sys::Relation, name: &str) -> Option<pg_sys::FormData_pg_attribute> {
+    let tup_desc = PgTupleDesc::from_pg_copy((*relation).rd_att);
+    tup_desc
+        .iter()
+        .find(|attr| !attr.is_dropped() && pgrx::name_data_to_str(&attr.attname) == name)
+        .copied()
+}
+
+unsafe fn create_instance<W: ForeignDataWrapper>(relid: Oid, kind: &str) -> FdwState<W> {
+    let options = get_foreign_table_options(relid);
+    let instance = W::new(relid, &options).unwrap_or_else(|e| e.report());
+    FdwState::new(instance, &format!("Wrappers_{}_{}", kind, relid.to_u32()))
+}
+
+/// Whether the table has row level triggers that must fire for `operation`
+#[cfg(not(feature = "pg13"))]
+unsafe fn has_row_triggers(relation: pg_sys::Relation, operation: CmdType::Type) -> bool {
+    let trigdesc = (*relation).trigdesc;
+    if trigdesc.is_null() {
+        return false;
+    }
+    match operation {
+        CmdType::CMD_UPDATE => (*trigdesc).trig_update_before_row || (*trigdesc).trig_update_after_row,
+        CmdType::CMD_DELETE => (*trigdesc).trig_delete_before_row || (*trigdesc).trig_delete_after_row,
+        _ => false,
+    }
+}
+
+/// Column identifying the rows of UPDATE and DELETE
+unsafe fn rowid_attribute<W: ForeignDataWrapper>(relation: pg_sys::Relation) -> pg_sys::FormData_pg_attribute {
+    let options = get_foreign_table_options((*relation).rd_id);
+    let attr = W::rowid_column(&options).and_then(|name| find_attribute(relation, &name));
+    attr.unwrap_or_else(|| {
+        FdwError::new(
+            PgSqlErrorCode::ERRCODE_FDW_COLUMN_NAME_NOT_FOUND,
+            format!(
+                "foreign table \"{}\" has no rowid column",
+                string_from_cstr(pg_sys::get_rel_name((*relation).rd_id))
+            ),
+        )
+        .report()
+    })
+}
+
+/// Build the `FdwRoutine` of a wrapper from the generic callbacks
+pub fn fdw_routine<W: ForeignDataWrapper>() -> FdwRoutine {
+    unsafe {
+        let mut fdw_routine = PgBox::<pg_sys::FdwRoutine, AllocatedByRust>::alloc_node(pg_sys::NodeTag::T_FdwRoutine);
+
+        fdw_routine.ImportForeignSchema = Some(import_foreign_schema::<W>);
+
+        // planning phase
+        fdw_routine.GetForeignRelSize = Some(get_foreign_rel_size::<W>);
+        fdw_routine.GetForeignPaths = Some(get_foreign_paths::<W>);
+        fdw_routine.GetForeignPlan = Some(get_foreign_plan::<W>);
+        fdw_routine.GetForeignJoinPaths = Some(get_foreign_join_paths::<W>);
+        fdw_routine.ExplainForeignScan = Some(explain_foreign_scan::<W>);
+
+        // scan phase
+        fdw_routine.BeginForeignScan = Some(begin_foreign_scan::<W>);
+        fdw_routine.IterateForeignScan = Some(iterate_foreign_scan::<W>);
+        fdw_routine.ReScanForeignScan = Some(re_scan_foreign_scan::<W>);
+        fdw_routine.EndForeignScan = Some(end_foreign_scan::<W>);
+
+        // modify phase
+        fdw_routine.AddForeignUpdateTargets = Some(add_foreign_update_targets::<W>);
+        fdw_routine.PlanForeignModify = Some(plan_foreign_modify::<W>);
+        fdw_routine.BeginForeignModify = Some(begin_foreign_modify::<W>);
+        fdw_routine.ExecForeignInsert = Some(exec_foreign_insert::<W>);
+        fdw_routine.ExecForeignUpdate = Some(exec_foreign_update::<W>);
+        fdw_routine.ExecForeignDelete = Some(exec_foreign_delete::<W>);
+        fdw_routine.EndForeignModify = Some(end_foreign_modify::<W>);
+        #[cfg(not(feature = "pg13"))]
+        {
+            fdw_routine.GetForeignModifyBatchSize = Some(get_foreign_modify_batch_size::<W>);
+            fdw_routine.ExecForeignBatchInsert = Some(exec_foreign_batch_insert::<W>);
+            fdw_routine.ExecForeignTruncate = Some(exec_foreign_truncate::<W>);
+
+            fdw_routine.PlanDirectModify = Some(plan_direct_modify::<W>);
+            fdw_routine.BeginDirectModify = Some(begin_direct_modify::<W>);
+            fdw_routine.IterateDirectModify = Some(iterate_direct_modify::<W>);
+            fdw_routine.EndDirectModify = Some(end_direct_modify::<W>);
+            fdw_routine.ExplainDirectModify = Some(explain_direct_modify::<W>);
+        }
+        fdw_routine.IsForeignRelUpdatable = Some(is_foreign_rel_updatable::<W>);
+
+        fdw_routine
+    }
+}
+
+/// Check the options of a `CREATE` or `ALTER` with the wrapper's validator.
+/// Options come as `name=value` strings.
+pub fn validate_options<W: ForeignDataWrapper>(options: Vec<Option<String>>, catalog: Oid) {
+    let options: Vec<(String, String)> = options
+        .into_iter()
+        .flatten()
+        .map(|option| match option.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => (option, String::new()),
+        })
+        .collect();
+    if let Err(e) = W::validator(&options, catalog) {
+        e.report();
+    }
+}
+
+/// Declare the handler and validator functions of a wrapper. An optional
+/// block can adjust the generated routine, e.g. to leave out a callback:
+///
+/// ```rust,ignore
+/// foreign_data_wrapper!(csv_fdw_handler, csv_fdw_validator, CsvFdw);
+/// foreign_data_wrapper!(my_fdw_handler, my_fdw_validator, MyFdw, |routine| {
+///     routine.ExecForeignTruncate = None;
+/// });
+/// ```
+macro_rules! foreign_data_wrapper {
+    ($handler:ident, $validator:ident, $wrapper:ty) => {
+        $crate::fdw::utils_share::fdw::foreign_data_wrapper!($handler, $validator, $wrapper, |_routine| {});
+    };
+    ($handler:ident, $validator:ident, $wrapper:ty, |$routine:ident| $body:block) => {
+        #[pgrx::pg_extern(create_or_replace)]
+        pub extern "C" fn $handler() -> $crate::fdw::utils_share::fdw::FdwRoutine {
+            pgrx::log!(concat!("---> ", stringify!($handler)));
+            #[allow(unused_mut)]
+            let mut $routine = $crate::fdw::utils_share::fdw::fdw_routine::<$wrapper>();
+            #[allow(unused_unsafe)]
+            unsafe { $body }
+            $routine
+        }
+
+        #[pgrx::pg_extern(create_or_replace)]
+        pub fn $validator(options: Vec<Option<String>>, catalog: pgrx::pg_sys::Oid) {
+            $crate::fdw::utils_share::fdw::validate_options::<$wrapper>(options, catalog)
+        }
+    };
+}
+pub(crate) use foreign_data_wrapper;
+
+#[pg_guard]
+extern "C-unwind" fn import_foreign_schema<W: ForeignDataWrapper>(
+    stmt: *mut pg_sys::ImportForeignSchemaStmt,
+    server_oid: Oid,
+) -> *mut pg_sys::List {
+    log!("---> import_foreign_schema");
+    unsafe {
+        let mut table_list = HashSet::new();
+        memcx::current_context(|mcx| {
+            let tables = pg_list_to_rust_list::<*mut std::os::raw::c_void>((*stmt).table_list, mcx);
+            for rv in tables.iter() {
+                let rv = rv.cast::<pg_sys::RangeVar>();
+                table_list.insert(string_from_cstr((*rv).relname));
+            }
+        });
+
+        let mut server_options = HashMap::new();
+        let server = pg_sys::GetForeignServer(server_oid);
+        memcx::current_context(|mcx| {
+            let options = pg_list_to_rust_list::<*mut std::os::raw::c_void>((*server).options, mcx);
+            for option in options.iter() {
+                let def_elem = option.cast::<pg_sys::DefElem>();
+                server_options.insert(
+                    string_from_cstr((*def_elem).defname),
+                    string_from_cstr(pg_sys::defGetString(def_elem)),
+                );
+            }
+        });
+
+        let import = ImportSchema {
+            server_name: string_from_cstr((*stmt).server_name),
+            server_options,
+            remote_schema: string_from_cstr((*stmt).remote_schema),
+            local_schema: string_from_cstr((*stmt).local_schema),
+            list_type: (*stmt).list_type,
+            table_list,
+        };
+
+        let mut ret: *mut pg_sys::List = ptr::null_mut();
+        for sql in W::import_schema(&import).unwrap_or_else(|e| e.report()) {
+            log!("Importing foreign table: {}", sql);
+            ret = pg_sys::lappend(ret, PgMemoryContexts::CurrentMemoryContext.pstrdup(&sql) as _);
+        }
+        ret
+    }
+}
+
+#[pg_guard]
+extern "C-unwind" fn get_foreign_rel_size<W: ForeignDataWrapper>(
+    root: *mut pg_sys::PlannerInfo,
+    baserel: *mut pg_sys::RelOptInfo,
+    foreigntableid: Oid,
+) {
+    log!("---> get_foreign_rel_size");
+    unsafe {
+        let mut state = create_instance::<W>(foreigntableid, "plan");
+        state.quals = extract_quals(root, baserel, foreigntableid);
+        state.sorts = extract_sorts(root, baserel, foreigntableid);
+        state.limit = extract_limit(root, baserel);
+        let quals = state.quals.clone();
+        (*baserel).tuples = state.call(|w| w.estimate_rows(&quals));
+        let selectivity = pg_sys::clauselist_selectivity(
+            root,
+            (*baserel).baserestrictinfo,
+            0,
+            pg_sys::JoinType::JOIN_INNER,
+            ptr::null_mut(),
+        );
+        (*baserel).rows = pg_sys::clamp_row_est((*baserel).tuples * selectivity);
+        // the planning instance goes with the planner's memory, whether the
+        // relation ends up in a plan or not
+        (*baserel).fdw_private = PgMemoryContexts::CurrentMemoryContext.leak_and_drop_on_delete(state) as _;
+    }
+}
+
+#[pg_guard]
+extern "C-unwind" fn get_foreign_paths<W: ForeignDataWrapper>(
+    root: *mut pg_sys::PlannerInfo,
+    baserel: *mut pg_sys::RelOptInfo,
+    foreigntableid: Oid,
+) {
+    log!("---> get_foreign_paths");
+    unsafe {
+        let startup_cost = 10.0;
+        let total_cost = startup_cost + (*baserel).rows;
+        // create a ForeignPath node and add it as the only possible path
+        let path = pg_sys::create_foreignscan_path(
+            root,
+            baserel,
+            ptr::null_mut(), // default pathtarget
+            (*baserel).rows,
+            startup_cost,
+            total_cost,
+            ptr::null_mut(), // no pathkeys
+            ptr::null_mut(), // no outer rel either
+            ptr::null_mut(), // no extra plan
+            ptr::null_mut(), // no fdw_private data
+        );
+        pg_sys::add_path(baserel, &mut ((*path).path));
+
+        // the same scan returning rows in the order of the query, as far as
+        // the wrapper can sort them
+        let state = &*((*baserel).fdw_private as *mut FdwState<W>);
+        let sorted = state.instance.can_sort(&state.sorts).min(state.sorts.len());
+        if sorted > 0 {
+            let path = pg_sys::create_foreignscan_path(
+                root,
+                baserel,
+                ptr::null_mut(),
+                (*baserel).rows,
+                startup_cost,
+                total_cost,
+                query_pathkeys_head(root, sorted),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            pg_sys::add_path(baserel, &mut ((*path).path));
+        }
+
+        // and in the order of a merge join key
+        for (pathkey, sort) in merge_join_sorts(root, baserel, foreigntableid) {
+            if state.instance.can_sort(std::slice::from_ref(&sort)) == 0 {
+                continue;
+            }
+            let path = pg_sys::create_foreignscan_path(
+                root,
+                baserel,
+                ptr::null_mut(),
+                (*baserel).rows,
+                startup_cost,
+                total_cost,
+                pg_sys::lappend(ptr::null_mut(), pathkey as _),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            pg_sys::add_path(baserel, &mut ((*path).path));
+        }
+    }
+}
+
+#[pg_guard]
+unsafe extern "C-unwind" fn get_foreign_join_paths<W: ForeignDataWrapper>(
+    root: *mut pg_sys::PlannerInfo,
+    joinrel: *mut pg_sys::RelOptInfo,
+    outerrel: *mut pg_sys::RelOptInfo,
+    innerrel: *mut pg_sys::RelOptInfo,
+    jointype: pg_sys::JoinType::Type,
+    extra: *mut pg_sys::JoinPathExtraData,
+) {
+    log!("---> get_foreign_join_paths");
+    // the same join is offered again with the sides swapped
+    if !(*joinrel).fdw_private.is_null() {
+        return;
+    }
+    let Some(plan) = extract_join(root, joinrel, outerrel, innerrel, jointype, extra) else {
+        return;
+    };
+    // both tables belong to the same server, the outer one was planned by
+    // get_foreign_rel_size
+    let outer = (*outerrel).fdw_private as *mut FdwState<W>;
+    if outer.is_null() || !(*outer).instance.can_join(&plan.join) {
+        return;
+    }
+
+    // reading the inner table comes first, then the outer one is read and
+    // joined
+    let startup_cost = 10.0 + (*innerrel).tuples * pg_sys::cpu_tuple_cost;
+    let total_cost = startup_cost + ((*outerrel).tuples + (*joinrel).rows) * pg_sys::cpu_tuple_cost;
+    (*joinrel).fdw_private = PgMemoryContexts::CurrentMemoryContext.leak_and_drop_on_delete(plan) as _;
+
+    let path = pg_sys::create_foreign_join_path(
+        root,
+        joinrel,
+        ptr::null_mut(),
+        (*joinrel).rows,
+        startup_cost,
+        total_cost,
+        ptr::null_mut(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+    );
+    pg_sys::add_path(joinrel, path as *mut pg_sys::Path);
+}
+
+#[pg_guard]
+unsafe extern "C-unwind" fn get_foreign_plan<W: ForeignDataWrapper>(
+    root: *mut pg_sys::PlannerInfo,
+    baserel: *mut pg_sys::RelOptInfo,
+    foreigntableid: Oid,
+    best_path: *mut pg_sys::ForeignPath,
+    tlist: *mut pg_sys::List,
+    scan_clauses: *mut pg_sys::List,
+    outer_plan: *mut pg_sys::Plan,
+) -> *mut pg_sys::ForeignScan {
+    log!("---> get_foreign_plan");
+    // the plan may be copied and run many times, it only holds nodes: the
+    // sorts, the limit and the join here, the quals come from the plan's qual
+    if (*baserel).reloptkind == pg_sys::RelOptKind::RELOPT_JOINREL {
+        // Postgres checks the clauses that are not join keys on the joined
+        // rows, the scan tuple holds the columns they need
+        let plan = &*((*baserel).fdw_private as *mut JoinPlan);
+        let mut fdw_private = pg_sys::lappend(ptr::null_mut(), ptr::null_mut());
+        fdw_private = pg_sys::lappend(fdw_private, ptr::null_mut());
+        fdw_private = pg_sys::lappend(fdw_private, plan.join.to_list() as _);
+        return pg_sys::make_foreignscan(
+            tlist,
+            pg_sys::extract_actual_clauses(plan.local_conds, false),
+            0,
+            ptr::null_mut(),
+            fdw_private,
+            plan.scan_tlist,
+            ptr::null_mut(),
+            outer_plan,
+        );
+    }
+
+    let state = &*((*baserel).fdw_private as *mut FdwState<W>);
+    // keep the sorts the chosen path promised, the limit only holds when the
+    // rows come in the whole order of the query
+    let pathkeys = (*best_path).path.pathkeys;
+    let sorts = pathkeys_to_sorts(pathkeys, baserel, foreigntableid);
+    let ordered = pg_sys::pathkeys_contained_in((*root).query_pathkeys, pathkeys);
+    let limit = state.limit.filter(|limit| ordered && state.instance.can_limit(limit));
+
+    let mut fdw_private = pg_sys::lappend(ptr::null_mut(), sorts_to_list(&sorts) as _);
+    fdw_private = pg_sys::lappend(fdw_private, limit_to_list(&limit) as _);
+    pg_sys::make_foreignscan(
+        tlist,
+        pg_sys::extract_actual_clauses(scan_clauses, false),
+        (*baserel).relid,
+        ptr::null_mut(),
+        fdw_private,
+        ptr::null_mut(),
+        ptr::null_mut(),
+        outer_plan,
+    )
+}
+
+#[pg_guard]
+unsafe extern "C-unwind" fn explain_foreign_scan<W: ForeignDataWrapper>(
+    node: *mut pg_sys::ForeignScanState,
+    es: *mut pg_sys::ExplainState,
+) {
+    log!("---> explain_foreign_scan");
+    let state = (*node).fdw_state as *mut FdwState<W>;
+    if state.is_null() {
+        return;
+    }
+    if let Some(join) = &(*state).join {
+        pg_sys::ExplainPropertyText(
+            string_to_cstr("Pushed Join").as_ptr(),
+            string_to_cstr(&join.deparse()).as_ptr(),
+            es,
+        );
+    }
+    if !(*state).sorts.is_empty() {
+        let sorts: Vec<String> = (*state).sorts.iter().map(|sort| sort.deparse()).collect();
+        pg_sys::ExplainPropertyText(
+            string_to_cstr("Pushed Sort").as_ptr(),
+            string_to_cstr(&sorts.join(", ")).as_ptr(),
+            es,
+        );
+    }
+    if let Some(limit) = &(*state).limit {
+        pg_sys::ExplainPropertyInteger(string_to_cstr("Pushed Limit").as_ptr(), ptr::null(), limit.rows(), es);
+    }
+    for (label, value) in (*state).instance.explain() {
+        pg_sys::ExplainPropertyText(string_to_cstr(&label).as_ptr(), string_to_cstr(&value).as_ptr(), es);
+    }
+}
+
+#[pg_guard]
+extern "C-unwind" fn begin_foreign_scan<W: ForeignDataWrapper>(
+    node: *mut pg_sys::ForeignScanState,
+    eflags: c_int,
+) {
+    log!("---> begin_foreign_scan");
+    unsafe {
+        let plan = (*node).ss.ps.plan as *mut pg_sys::ForeignScan;
+        let fdw_private = list_items((*plan).fdw_private);
+        // a join has no scan relation
+        if (*plan).scan.scanrelid == 0 {
+            let Some(join) = Join::from_list(fdw_private[2] as _) else {
+                error!("foreign join plan has no join");
+            };
+            let mut state = create_instance::<W>(join.outer, "join");
+            state.begin_join(join, eflags);
+            (*node).fdw_state = Box::into_raw(Box::new(state)) as _;
+            return;
+        }
+
+        let relid = (*(*node).ss.ss_currentRelation).rd_id;
+        let mut state = create_instance::<W>(relid, "scan");
+        state.sorts = sorts_from_list(fdw_private[0] as _);
+        state.limit = limit_from_list(fdw_private[1] as _);
+        state.quals = extract_plan_quals((*plan).scan.plan.qual, (*plan).scan.scanrelid, relid);
+        state.begin_scan(node, eflags);
+        (*node).fdw_state = Box::into_raw(Box::new(state)) as _;
+    }
+}
+
+#[pg_guard]
+extern "C-unwind" fn iterate_foreign_scan<W: ForeignDataWrapper>(
+    node: *mut pg_sys::ForeignScanState,
+) -> *mut pg_sys::TupleTableSlot {
+    log!("---> iterate_foreign_scan");
+    unsafe {
+        let state = (*node).fdw_state as *mut FdwState<W>;
+        let slot = (*node).ss.ss_ScanTupleSlot;
+        (*state).next_row(slot);
+        slot
+    }
+}
+
+#[pg_guard]
+extern "C-unwind" fn re_scan_foreign_scan<W: ForeignDataWrapper>(node: *mut pg_sys::ForeignScanState) {
+    log!("---> re_scan_foreign_scan");
+    unsafe {
+        let state = (*node).fdw_state as *mut FdwState<W>;
+        if !state.is_null() {
+            (*state).re_scan(node);
+        }
+    }
+}
+
+#[pg_guard]
+extern "C-unwind" fn end_foreign_scan<W: ForeignDataWrapper>(node: *mut pg_sys::ForeignScanState) {
+    log!("---> end_foreign_scan");
+    unsafe {
+        let state = (*node).fdw_state as *mut FdwState<W>;
+        if state.is_null() {
+            return;
+        }
+        if (*state).started {
+            (*state).call(|w| w.end_scan());
+        }
+        let _ = Box::from_raw(state);
+        (*node).fdw_state = ptr::null_mut();
+    }
+}
+
+#[cfg(feature = "pg13")]
+#[pg_guard]
+unsafe extern "C-unwind" fn add_foreign_update_targets<W: ForeignDataWrapper>(
+    parsetree: *mut pg_sys::Query,
+    _target_rte: *mut pg_sys::RangeTblEntry,
+    target_relation: pg_sys::Relation,
+) {
+    log!("---> add_foreign_update_targets");
+    let options = get_foreign_table_options((*target_relation).rd_id);
+    let Some(attr) = W::rowid_column(&options).and_then(|name| find_attribute(target_relation, &name)) else {
+        return;
+    };
+
+    // make a Var representing the desired value
+    let var = pg_sys::makeVar(
+        (*parsetree).resultRelation as _,
+        attr.attnum,
+        attr.atttypid,
+        attr.atttypmod,
+        attr.attcollation,
+        0,
+    );
+
+    // wrap it in a resjunk TLE with the right name
+    let target_list = (*parsetree).targetList;
+    let resno = if target_list.is_null() { 1 } else { (*target_list).length + 1 };
+    let attname = PgMemoryContexts::CurrentMemoryContext.pstrdup(pgrx::name_data_to_str(&attr.attname));
+    let tle = pg_sys::makeTargetEntry(var as _, resno as _, attname, true);
+
+    // and add it to the query's targetlist
+    (*parsetree).targetList = pg_sys::lappend(target_list, tle as _);
+}
+
+#[cfg(not(feature = "pg13"))]
+#[pg_guard]
+unsafe extern "C-unwind" fn add_foreign_update_targets<W: ForeignDataWrapper>(
+    root: *mut pg_sys::PlannerInfo,
+    rtindex: pg_sys::Index,
+    _target_rte: *mut pg_sys::RangeTblEntry,
+    target_relation: pg_sys::Relation,
+) {
+    log!("---> add_foreign_update_targets");
+    let options = get_foreign_table_options((*target_relation).rd_id);
+    let Some(attr) = W::rowid_column(&options).and_then(|name| find_attribute(target_relation, &name)) else {
+        return;
+    };
+
+    // make a Var representing the desired value
+    let var = pg_sys::makeVar(rtindex as _, attr.attnum, attr.atttypid, attr.atttypmod, attr.attcollation, 0);
+
+    // register it as a row-identity column needed by this target rel
+    pg_sys::add_row_identity_var(root, var, rtindex, &attr.attname.data as _);
+}
+
+#[pg_guard]
+extern "C-unwind" fn is_foreign_rel_updatable<W: ForeignDataWrapper>(rel: pg_sys::Relation) -> c_int {
+    log!("---> is_foreign_rel_updatable");
+    unsafe {
+        if W::is_updatable(&get_foreign_table_options((*rel).rd_id)) {
+            (1 << CmdType::CMD_INSERT) | (1 << CmdType::CMD_UPDATE) | (1 << CmdType::CMD_DELETE)
+        } else {
+            0
+        }
+    }
+}
+
+/// Reject writes to read-only tables while planning, before any row is read,
+/// and before the bulk writes of a wrapper's own functions
+pub unsafe fn check_updatable<W: ForeignDataWrapper>(rel: &PgRelation, operation: CmdType::Type) {
+    if W::is_updatable(&get_foreign_table_options(rel.oid())) {
+        return;
+    }
+    let action = match operation {
+        CmdType::CMD_INSERT => "insert into",
+        CmdType::CMD_UPDATE => "update",
+        CmdType::CMD_DELETE => "delete from",
+        _ => "modify",
+    };
+    FdwError::new(
+        PgSqlErrorCode::ERRCODE_WRONG_OBJECT_TYPE,
+        format!("cannot {} foreign table \"{}\"", action, rel.name()),
+    )
+    .with_detail("The foreign table does not accept writes.")
+    .report();
+}
+
+#[pg_guard]
+unsafe extern "C-unwind" fn plan_foreign_modify<W: ForeignDataWrapper>(
+    root: *mut pg_sys::PlannerInfo,
+    plan: *mut pg_sys::ModifyTable,
+    result_relation: pg_sys::Index,
+    _subplan_index: c_int,
+) -> *mut pg_sys::List {
+    log!("---> plan_foreign_modify");
+    let rte = pg_sys::planner_rt_fetch(result_relation, root);
+    let rel = PgRelation::with_lock((*rte).relid, pg_sys::NoLock as _);
+    check_updatable::<W>(&rel, (*plan).operation);
+    if (*plan).operation != CmdType::CMD_INSERT {
+        rowid_attribute::<W>(rel.as_ptr());
+    }
+    // the modification is set up by begin_foreign_modify, the plan holds
+    // nothing of its own
+    ptr::null_mut()
+}
+
+#[pg_guard]
+extern "C-unwind" fn begin_foreign_modify<W: ForeignDataWrapper>(
+    mtstate: *mut pg_sys::ModifyTableState,
+    rinfo: *mut pg_sys::ResultRelInfo,
+    _fdw_private: *mut pg_sys::List,
+    subplan_index: c_int,
+    eflags: c_int,
+) {
+    log!("---> begin_foreign_modify");
+    unsafe {
+        let relation = (*rinfo).ri_RelationDesc;
+        let mut state = create_instance::<W>((*relation).rd_id, "modify");
+
+        // search for the rowid junk attribute in the subplan's target list
+        if (*mtstate).operation != CmdType::CMD_INSERT {
+            #[cfg(feature = "pg13")]
+            let subplan = (**(*mtstate).mt_plans.add(subplan_index as usize)).plan;
+            #[cfg(not(feature = "pg13"))]
+            let subplan = (*(*mtstate).ps.lefttree).plan;
+            #[cfg(not(feature = "pg13"))]
+            let _ = subplan_index;
+            let attr = rowid_attribute::<W>(relation);
+            state.rowid_typid = attr.atttypid;
+            state.rowid_attno = pg_sys::ExecFindJunkAttributeInTlist((*subplan).targetlist, attr.attname.data.as_ptr());
+        }
+
+        state.columns = relation_columns(relation);
+        state.header_name_to_colno = build_attr_name_to_index_map(relation);
+        state.returning = !(*rinfo).ri_projectReturning.is_null();
+        // EXPLAIN without ANALYZE does not modify anything
+        if eflags & pg_sys::EXEC_FLAG_EXPLAIN_ONLY as c_int == 0 {
+            let columns = state.columns.clone();
+            let returning = state.returning;
+            state.call(|w| w.begin_modify(&columns, returning));
+            state.started = true;
+        }
+        (*rinfo).ri_FdwState = Box::into_raw(Box::new(state)) as _;
+    }
+}
+
+#[pg_guard]
+extern "C-unwind" fn exec_foreign_insert<W: ForeignDataWrapper>(
+    _estate: *mut pg_sys::EState,
+    rinfo: *mut pg_sys::ResultRelInfo,
+    slot: *mut pg_sys::TupleTableSlot,
+    _plan_slot: *mut pg_sys::TupleTableSlot,
+) -> *mut pg_sys::TupleTableSlot {
+    log!("---> exec_foreign_insert");
+    unsafe {
+        let state = &mut *((*rinfo).ri_FdwState as *mut FdwState<W>);
+        let stored = state.call(|w| w.insert(&tuple_table_slot_to_row(slot)));
+        state.return_row(slot, stored);
+        (*slot).tts_tableOid = pg_sys::InvalidOid;
+        slot
+    }
+}
+
+#[cfg(not(feature = "pg13"))]
+#[pg_guard]
+extern "C-unwind" fn get_foreign_modify_batch_size<W: ForeignDataWrapper>(rinfo: *mut pg_sys::ResultRelInfo) -> c_int {
+    log!("---> get_foreign_modify_batch_size");
+    unsafe {
+        // RETURNING, WITH CHECK OPTION and AFTER ROW triggers need the rows
+        // one at a time
+        if !(*rinfo).ri_projectReturning.is_null() || !(*rinfo).ri_WithCheckOptions.is_null() {
+            return 1;
+        }
+        let trigdesc = (*rinfo).ri_TrigDesc;
+        if !trigdesc.is_null() && (*trigdesc).trig_insert_after_row {
+            return 1;
+        }
+
+        let state = (*rinfo).ri_FdwState as *mut FdwState<W>;
+        if state.is_null() {
+            return 1;
+        }
+        (*state).instance.batch_size().max(1) as c_int
+    }
+}
+
+#[cfg(not(feature = "pg13"))]
+#[pg_guard]
+extern "C-unwind" fn exec_foreign_batch_insert<W: ForeignDataWrapper>(
+    _estate: *mut pg_sys::EState,
+    rinfo: *mut pg_sys::ResultRelInfo,
+    slots: *mut *mut pg_sys::TupleTableSlot,
+    _plan_slots: *mut *mut pg_sys::TupleTableSlot,
+    num_slots: *mut c_int,
+) -> *mut *mut pg_sys::TupleTableSlot {
+    log!("---> exec_foreign_batch_insert");
+    unsafe {
+        let state = &mut *((*rinfo).ri_FdwState as *mut FdwState<W>);
+        let slots_arr = std::slice::from_raw_parts(slots, *num_slots as usize);
+        state.call(|w| {
+            let rows: Vec<Row> = slots_arr.iter().map(|slot| tuple_table_slot_to_row(*slot)).collect();
+            w.insert_batch(&rows)
+        });
+        for slot in slots_arr {
+            (**slot).tts_tableOid = pg_sys::InvalidOid;
+        }
+        slots
+    }
+}
+
+#[pg_guard]
+extern "C-unwind" fn exec_foreign_update<W: ForeignDataWrapper>(
+    _estate: *mut pg_sys::EState,
+    rinfo: *mut pg_sys::ResultRelInfo,
+    slot: *mut pg_sys::TupleTableSlot,
+    plan_slot: *mut pg_sys::TupleTableSlot,
+) -> *mut pg_sys::TupleTableSlot {
+    log!("---> exec_foreign_update");
+    unsafe {
+        let state = &mut *((*rinfo).ri_FdwState as *mut FdwState<W>);
+        // the old rowid comes from the plan, `slot` holds every column of the
+        // new row, including columns that were NULL before
+        let Some(rowid) = state.rowid(plan_slot) else {
+            return ptr::null_mut();
+        };
+        let stored = state.call(|w| w.update(&rowid, &tuple_table_slot_to_row(slot)));
+        // a row deleted in the meantime does not count as updated
+        if stored.is_none() {
+            return ptr::null_mut();
+        }
+        state.return_row(slot, stored);
+        slot
+    }
+}
+
+#[pg_guard]
+extern "C-unwind" fn exec_foreign_delete<W: ForeignDataWrapper>(
+    _estate: *mut pg_sys::EState,
+    rinfo: *mut pg_sys::ResultRelInfo,
+    slot: *mut pg_sys::TupleTableSlot,
+    plan_slot: *mut pg_sys::TupleTableSlot,
+) -> *mut pg_sys::TupleTableSlot {
+    log!("---> exec_foreign_delete");
+    unsafe {
+        let state = &mut *((*rinfo).ri_FdwState as *mut FdwState<W>);
+        let Some(rowid) = state.rowid(plan_slot) else {
+            return ptr::null_mut();
+        };
+        let deleted = state.call(|w| w.delete(&rowid));
+        // RETURNING shows the deleted row, a missing row does not count
+        if deleted.is_none() {
+            return ptr::null_mut();
+        }
+        state.return_row(slot, deleted);
+        slot
+    }
+}
+
+#[pg_guard]
+extern "C-unwind" fn end_foreign_modify<W: ForeignDataWrapper>(
+    _estate: *mut pg_sys::EState,
+    rinfo: *mut pg_sys::ResultRelInfo,
+) {
+    log!("---> end_foreign_modify");
+    unsafe {
+        let state = (*rinfo).ri_FdwState as *mut FdwState<W>;
+        if state.is_null() {
+            return;
+        }
+        if (*state).started {
+            (*state).call(|w| w.end_modify());
+        }
+        let _ = Box::from_raw(state);
+        (*rinfo).ri_FdwState = ptr::null_mut();
+    }
+}
+
+#[cfg(not(feature = "pg13"))]
+#[pg_guard]
+extern "C-unwind" fn exec_foreign_truncate<W: ForeignDataWrapper>(
+    rels: *mut pg_sys::List,
+    _behavior: pg_sys::DropBehavior::Type,
+    _restart_seqs: bool,
+) {
+    log!("---> exec_foreign_truncate");
+    unsafe {
+        for rel in list_items(rels) {
+            let relid = (*(rel as pg_sys::Relation)).rd_id;
+            let mut state = create_instance::<W>(relid, "truncate");
+            state.call(|w| w.truncate());
+        }
+    }
+}
+
+/// UPDATE or DELETE run by the wrapper, with the rows it returned
+#[cfg(not(feature = "pg13"))]
+struct DirectModifyState<W: ForeignDataWrapper> {
+    fdw: FdwState<W>,
+    modify: DirectModify,
+    /// whether the modified rows count for the statement
+    set_processed: bool,
+    /// modified rows not returned yet, set on the first fetch
+    rows: Option<std::vec::IntoIter<Row>>,
+}
+
+/// Run an UPDATE or DELETE in the wrapper when every restriction is a qual
+/// it accepts and every SET value is a constant
+#[cfg(not(feature = "pg13"))]
+#[pg_guard]
+unsafe extern "C-unwind" fn plan_direct_modify<W: ForeignDataWrapper>(
+    root: *mut pg_sys::PlannerInfo,
+    plan: *mut pg_sys::ModifyTable,
+    result_relation: pg_sys::Index,
+    _subplan_index: c_int,
+) -> bool {
+    log!("---> plan_direct_modify");
+    let operation = (*plan).operation;
+    if operation != CmdType::CMD_UPDATE && operation != CmdType::CMD_DELETE {
+        return false;
+    }
+
+    // the subplan must be a plain scan of the target table
+    let subplan = (*plan).plan.lefttree;
+    if subplan.is_null() || (*subplan).type_ != pg_sys::NodeTag::T_ForeignScan {
+        return false;
+    }
+    let fscan = subplan as *mut pg_sys::ForeignScan;
+    if (*fscan).scan.scanrelid != result_relation {
+        return false;
+    }
+
+    // triggers and check options need the rows one by one, read-only tables
+    // are rejected by plan_foreign_modify
+    let rte = pg_sys::planner_rt_fetch(result_relation, root);
+    let rel = PgRelation::with_lock((*rte).relid, pg_sys::NoLock as _);
+    if has_row_triggers(rel.as_ptr(), operation)
+        || !(*plan).withCheckOptionLists.is_null()
+        || !W::is_updatable(&get_foreign_table_options(rel.oid()))
+    {
+        return false;
+    }
+
+    // every restriction must be a qual
+    let clauses = (*fscan).scan.plan.qual;
+    let mut quals = Vec::new();
+    for clause in list_items(clauses) {
+        let qual = extract_plan_quals(pg_sys::lappend(ptr::null_mut(), clause), result_relation, rel.oid());
+        if qual.len() != 1 {
+            return false;
+        }
+        quals.extend(qual);
+    }
+
+    // SET values are constants
+    let mut set_names = ptr::null_mut();
+    let mut set_values = ptr::null_mut();
+    let mut set = Vec::new();
+    if operation == CmdType::CMD_UPDATE {
+        let tup_desc = PgTupleDesc::from_relation(&rel);
+        let tlist = list_items((*root).processed_tlist);
+        let colnos = memcx::current_context(|mcx| {
+            pg_list_to_rust_list::<c_int>((*root).update_colnos, mcx).iter().copied().collect::<Vec<_>>()
+        });
+        let mut colnos = colnos.into_iter();
+        for te in tlist {
+            let te = te as *mut pg_sys::TargetEntry;
+            if (*te).resjunk {
+                continue;
+            }
+            let Some(attr) = colnos.next().and_then(|attno| tup_desc.get((attno - 1) as usize)) else {
+                return false;
+            };
+            let Some(value) = const_cell((*te).expr as _) else {
+                return false;
+            };
+            let name = pgrx::name_data_to_str(&attr.attname).to_string();
+            set_names = pg_sys::lappend(set_names, make_const(Some(name.clone())));
+            set_values = pg_sys::lappend(set_values, (*te).expr as _);
+            set.push((name, value));
+        }
+    }
+
+    let modify = DirectModify { operation, quals, set };
+    let state = create_instance::<W>(rel.oid(), "plan");
+    if !state.instance.can_modify_directly(&modify) {
+        return false;
+    }
+    log!("Direct modify pushed down: {:?}", modify);
+
+    // the restrictions move to fdw_exprs, where setrefs still prepares
+    // their parameters
+    (*fscan).operation = operation;
+    (*fscan).resultRelation = result_relation;
+    (*fscan).fdw_exprs = clauses;
+    (*fscan).scan.plan.qual = ptr::null_mut();
+    let mut fdw_private = pg_sys::lappend(ptr::null_mut(), make_const(Some(operation as i32)));
+    fdw_private = pg_sys::lappend(fdw_private, make_const(Some((*plan).canSetTag)));
+    fdw_private = pg_sys::lappend(fdw_private, set_names as _);
+    (*fscan).fdw_private = pg_sys::lappend(fdw_private, set_values as _);
+    true
+}
+
+/// Value of a `Const`, `Some(None)` for NULL and `None` for anything else or
+/// an unsupported type
+#[cfg(not(feature = "pg13"))]
+unsafe fn const_cell(node: *mut pg_sys::Node) -> Option<Option<Cell>> {
+    if node.is_null() || (*node).type_ != pg_sys::NodeTag::T_Const {
+        return None;
+    }
+    let cst = node as *mut pg_sys::Const;
+    if (*cst).constisnull {
+        return Some(None);
+    }
+    Cell::from_polymorphic_datum((*cst).constvalue, false, (*cst).consttype).map(Some)
+}
+
+#[cfg(not(feature = "pg13"))]
+#[pg_guard]
+extern "C-unwind" fn begin_direct_modify<W: ForeignDataWrapper>(node: *mut pg_sys::ForeignScanState, eflags: c_int) {
+    log!("---> begin_direct_modify");
+    unsafe {
+        let plan = (*node).ss.ps.plan as *mut pg_sys::ForeignScan;
+        let relation = (*node).ss.ss_currentRelation;
+        let relid = (*relation).rd_id;
+        let fdw_private = list_items((*plan).fdw_private);
+        let set_names = list_items(fdw_private[2] as _);
+        let set_values = list_items(fdw_private[3] as _);
+        let set = set_names
+            .into_iter()
+            .zip(set_values)
+            .map(|(name, value)| (const_value(name).unwrap_or_default(), const_cell(value as _).flatten()))
+            .collect();
+        let modify = DirectModify {
+            operation: const_value::<i32>(fdw_private[0]).unwrap_or_default() as CmdType::Type,
+            quals: extract_plan_quals((*plan).fdw_exprs, (*plan).scan.scanrelid, relid),
+            set,
+        };
+
+        let mut fdw = create_instance::<W>(relid, "modify");
+        fdw.columns = relation_columns(relation);
+        fdw.header_name_to_colno = build_attr_name_to_index_map(relation);
+        fdw.returning = !(*(*node).resultRelInfo).ri_projectReturning.is_null();
+        // EXPLAIN without ANALYZE does not modify anything
+        if eflags & pg_sys::EXEC_FLAG_EXPLAIN_ONLY as c_int == 0 {
+            let columns = fdw.columns.clone();
+            let returning = fdw.returning;
+            fdw.call(|w| w.begin_modify(&columns, returning));
+            fdw.started = true;
+        }
+        let state = DirectModifyState {
+            fdw,
+            modify,
+            set_processed: const_value(fdw_private[1]).unwrap_or_default(),
+            rows: None,
+        };
+        (*node).fdw_state = Box::into_raw(Box::new(state)) as _;
+    }
+}
+
+#[cfg(not(feature = "pg13"))]
+#[pg_guard]
+extern "C-unwind" fn iterate_direct_modify<W: ForeignDataWrapper>(
+    node: *mut pg_sys::ForeignScanState,
+) -> *mut pg_sys::TupleTableSlot {
+    log!("---> iterate_direct_modify");
+    unsafe {
+        let state = &mut *((*node).fdw_state as *mut DirectModifyState<W>);
+        let slot = (*node).ss.ss_ScanTupleSlot;
+        let estate = (*node).ss.ps.state;
+        let rinfo = (*node).resultRelInfo;
+
+        if state.rows.is_none() {
+            resolve_params(&mut state.modify.quals, node);
+            let modify = state.modify.clone();
+            let mut rows = state.fdw.call(|w| w.modify_directly(&modify));
+            // without RETURNING the rows are only counted
+            if !state.fdw.returning {
+                if state.set_processed {
+                    (*estate).es_processed += rows.len() as u64;
+                }
+                rows.clear();
+            }
+            state.rows = Some(rows.into_iter());
+        }
+
+        exec_clear_tuple(slot);
+        let Some(row) = state.rows.as_mut().and_then(|rows| rows.next()) else {
+            return slot;
+        };
+        state.fdw.store_row(slot, row);
+        if state.set_processed {
+            (*estate).es_processed += 1;
+        }
+
+        // make the modified row available to ExecProcessReturning
+        (*(*(*rinfo).ri_projectReturning).pi_exprContext).ecxt_scantuple = slot;
+        slot
+    }
+}
+
+#[cfg(not(feature = "pg13"))]
+#[pg_guard]
+unsafe extern "C-unwind" fn explain_direct_modify<W: ForeignDataWrapper>(
+    node: *mut pg_sys::ForeignScanState,
+    es: *mut pg_sys::ExplainState,
+) {
+    log!("---> explain_direct_modify");
+    let state = (*node).fdw_state as *mut DirectModifyState<W>;
+    if state.is_null() {
+        return;
+    }
+    // the wrapper checks every restriction
+    let quals: Vec<String> = (*state).modify.quals.iter().map(|qual| qual.to_string()).collect();
+    let quals = if quals.is_empty() { "none".to_string() } else { quals.join(" AND ") };
+    pg_sys::ExplainPropertyText(string_to_cstr("Pushed Quals").as_ptr(), string_to_cstr(&quals).as_ptr(), es);
+    for (label, value) in (*state).fdw.instance.explain() {
+        pg_sys::ExplainPropertyText(string_to_cstr(&label).as_ptr(), string_to_cstr(&value).as_ptr(), es);
+    }
+}
+
+#[cfg(not(feature = "pg13"))]
+#[pg_guard]
+extern "C-unwind" fn end_direct_modify<W: ForeignDataWrapper>(node: *mut pg_sys::ForeignScanState) {
+    log!("---> end_direct_modify");
+    unsafe {
+        let state = (*node).fdw_state as *mut DirectModifyState<W>;
+        if state.is_null() {
+            return;
+        }
+        if (*state).fdw.started {
+            (*state).fdw.call(|w| w.end_modify());
+        }
+        let _ = Box::from_raw(state);
+        (*node).fdw_state = ptr::null_mut();
+    }
+}
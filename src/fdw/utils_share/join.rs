@@ -0,0 +1,313 @@
+//! Join of two foreign tables a wrapper can run itself.
+//!
+//! `extract_join` checks a join of two base relations while planning: an
+//! inner or left join with at least one equality between plain columns of
+//! both tables. Clauses that are not join keys are checked by Postgres on the
+//! joined rows. The scan returns one row per joined pair, its cells named
+//! after `JoinColumn::label`.
+use std::{ffi::{c_int, c_void}, ptr};
+use pgrx::{memcx, pg_sys::{self, Oid}};
+use crate::fdw::utils_share::utils::{const_value, list_items, make_const, pg_list_to_rust_list, string_from_cstr};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    Inner,
+    /// rows of the outer table without a match are joined with NULLs
+    Left,
+}
+
+/// `outer.column = inner.column` with the default equality of the type
+#[derive(Debug, Clone, PartialEq)]
+pub struct JoinKey {
+    pub outer: String,
+    pub inner: String,
+    pub typid: Oid,
+    pub collation: Oid,
+}
+
+/// Column of the joined rows
+#[derive(Debug, Clone, PartialEq)]
+pub struct JoinColumn {
+    /// whether the column comes from the outer table
+    pub outer: bool,
+    pub name: String,
+    pub typid: Oid,
+    pub typmod: i32,
+}
+
+impl JoinColumn {
+    /// Name of the cell of the column in a joined row, e.g. `inner.id`
+    pub fn label(&self) -> String {
+        format!("{}.{}", if self.outer { "outer" } else { "inner" }, self.name)
+    }
+}
+
+/// Join of the `outer` and `inner` foreign tables on all of `keys`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub outer: Oid,
+    pub inner: Oid,
+    pub keys: Vec<JoinKey>,
+    /// columns of the joined rows, in the order of the scan tuple
+    pub columns: Vec<JoinColumn>,
+}
+
+impl Join {
+    /// Join as SQL, e.g. `hello LEFT JOIN tags ON (id = hello_id)`
+    pub unsafe fn deparse(&self) -> String {
+        let keys: Vec<String> = self.keys.iter().map(|key| format!("{} = {}", key.outer, key.inner)).collect();
+        format!(
+            "{} {} JOIN {} ON ({})",
+            string_from_cstr(pg_sys::get_rel_name(self.outer)),
+            if self.kind == JoinKind::Left { "LEFT" } else { "INNER" },
+            string_from_cstr(pg_sys::get_rel_name(self.inner)),
+            keys.join(" AND ")
+        )
+    }
+
+    /// Join as plan data, see `make_const`
+    pub unsafe fn to_list(&self) -> *mut pg_sys::List {
+        let mut list = ptr::null_mut();
+        list = pg_sys::lappend(list, make_const(Some((self.kind == JoinKind::Left) as i32)));
+        list = pg_sys::lappend(list, make_const(Some(self.outer)));
+        list = pg_sys::lappend(list, make_const(Some(self.inner)));
+        let mut keys = ptr::null_mut();
+        for key in &self.keys {
+            let mut item = ptr::null_mut();
+            item = pg_sys::lappend(item, make_const(Some(key.outer.clone())));
+            item = pg_sys::lappend(item, make_const(Some(key.inner.clone())));
+            item = pg_sys::lappend(item, make_const(Some(key.typid)));
+            item = pg_sys::lappend(item, make_const(Some(key.collation)));
+            keys = pg_sys::lappend(keys, item as _);
+        }
+        let mut columns = ptr::null_mut();
+        for column in &self.columns {
+            let mut item = ptr::null_mut();
+            item = pg_sys::lappend(item, make_const(Some(column.outer)));
+            item = pg_sys::lappend(item, make_const(Some(column.name.clone())));
+            item = pg_sys::lappend(item, make_const(Some(column.typid)));
+            item = pg_sys::lappend(item, make_const(Some(column.typmod)));
+            columns = pg_sys::lappend(columns, item as _);
+        }
+        list = pg_sys::lappend(list, keys as _);
+        pg_sys::lappend(list, columns as _)
+    }
+
+    /// Join of a list made by `to_list`
+    pub unsafe fn from_list(list: *mut pg_sys::List) -> Option<Join> {
+        let items = list_items(list);
+        let [kind, outer, inner, keys, columns] = items[..] else {
+            return None;
+        };
+        Some(Join {
+            kind: if const_value::<i32>(kind)? == 1 { JoinKind::Left } else { JoinKind::Inner },
+            outer: const_value(outer)?,
+            inner: const_value(inner)?,
+            keys: list_items(keys as _)
+                .into_iter()
+                .map(|item| {
+                    let fields = list_items(item as _);
+                    Some(JoinKey {
+                        outer: const_value(fields[0])?,
+                        inner: const_value(fields[1])?,
+                        typid: const_value(fields[2])?,
+                        collation: const_value(fields[3])?,
+                    })
+                })
+                .collect::<Option<_>>()?,
+            columns: list_items(columns as _)
+                .into_iter()
+                .map(|item| {
+                    let fields = list_items(item as _);
+                    Some(JoinColumn {
+                        outer: const_value(fields[0])?,
+                        name: const_value(fields[1])?,
+                        typid: const_value(fields[2])?,
+                        typmod: const_value(fields[3])?,
+                    })
+                })
+                .collect::<Option<_>>()?,
+        })
+    }
+}
+
+/// Join found by `extract_join`, with the planner data of its plan
+pub struct JoinPlan {
+    pub join: Join,
+    /// clauses Postgres checks on the joined rows, as `RestrictInfo`s
+    pub local_conds: *mut pg_sys::List,
+    /// target list of the scan tuple, the columns of `Join::columns`
+    pub scan_tlist: *mut pg_sys::List,
+}
+
+/// Check whether a join of two base relations can be run by the wrapper:
+/// an inner or left join with at least one equi-join clause between plain
+/// columns of the same type
+pub unsafe fn extract_join(
+    root: *mut pg_sys::PlannerInfo,
+    joinrel: *mut pg_sys::RelOptInfo,
+    outerrel: *mut pg_sys::RelOptInfo,
+    innerrel: *mut pg_sys::RelOptInfo,
+    jointype: pg_sys::JoinType::Type,
+    extra: *mut pg_sys::JoinPathExtraData,
+) -> Option<JoinPlan> {
+    let kind = match jointype {
+        pg_sys::JoinType::JOIN_INNER => JoinKind::Inner,
+        pg_sys::JoinType::JOIN_LEFT => JoinKind::Left,
+        _ => return None,
+    };
+    if (*outerrel).reloptkind != pg_sys::RelOptKind::RELOPT_BASEREL
+        || (*innerrel).reloptkind != pg_sys::RelOptKind::RELOPT_BASEREL
+    {
+        return None;
+    }
+    // locked rows need EvalPlanQual rechecks and placeholders need to be
+    // computed below the join, neither is supported
+    if (*(*root).parse).commandType != pg_sys::CmdType::CMD_SELECT
+        || !(*root).rowMarks.is_null()
+        || !(*root).placeholder_list.is_null()
+    {
+        return None;
+    }
+
+    let outer_varno = (*outerrel).relid;
+    let inner_varno = (*innerrel).relid;
+    let outer = (*pg_sys::planner_rt_fetch(outer_varno, root)).relid;
+    let inner = (*pg_sys::planner_rt_fetch(inner_varno, root)).relid;
+
+    memcx::current_context(|mcx| {
+        let mut keys = Vec::new();
+        let mut local_conds: *mut pg_sys::List = ptr::null_mut();
+        for ri in pg_list_to_rust_list::<*mut c_void>((*extra).restrictlist, mcx).iter() {
+            let ri = *ri as *mut pg_sys::RestrictInfo;
+            if (*ri).pseudoconstant {
+                return None;
+            }
+            let pushed_down =
+                (*ri).is_pushed_down || !pg_sys::bms_is_subset((*ri).required_relids, (*joinrel).relids);
+            let key = equi_join_key((*ri).clause as _, (outer_varno, outer), (inner_varno, inner));
+            match key {
+                // WHERE quals above a left join filter the joined tuples
+                _ if kind == JoinKind::Left && pushed_down => {
+                    local_conds = pg_sys::lappend(local_conds, ri as _);
+                }
+                Some(key) => keys.push(key),
+                None if kind == JoinKind::Inner => {
+                    local_conds = pg_sys::lappend(local_conds, ri as _);
+                }
+                // other join quals decide which rows are null extended
+                None => return None,
+            }
+        }
+        if keys.is_empty() {
+            return None;
+        }
+
+        // quals of the tables are checked after the join, which is only
+        // correct for the side that is not null extended
+        for (rel, nullable) in [(outerrel, false), (innerrel, kind == JoinKind::Left)] {
+            for ri in pg_list_to_rust_list::<*mut c_void>((*rel).baserestrictinfo, mcx).iter() {
+                if nullable || (*(*ri as *mut pg_sys::RestrictInfo)).pseudoconstant {
+                    return None;
+                }
+                local_conds = pg_sys::lappend(local_conds, *ri);
+            }
+        }
+
+        // the scan tuple holds the columns of the join target and local quals
+        let flags = pg_sys::PVC_RECURSE_PLACEHOLDERS as c_int;
+        let mut scan_tlist = pg_sys::add_to_flat_tlist(
+            ptr::null_mut(),
+            pg_sys::pull_var_clause((*(*joinrel).reltarget).exprs as _, flags),
+        );
+        for ri in pg_list_to_rust_list::<*mut c_void>(local_conds, mcx).iter() {
+            let clause = (*(*ri as *mut pg_sys::RestrictInfo)).clause;
+            scan_tlist = pg_sys::add_to_flat_tlist(scan_tlist, pg_sys::pull_var_clause(clause as _, flags));
+        }
+
+        let mut columns = Vec::new();
+        for te in pg_list_to_rust_list::<*mut c_void>(scan_tlist, mcx).iter() {
+            let expr = (*(*te as *mut pg_sys::TargetEntry)).expr as *mut pg_sys::Node;
+            if (*expr).type_ != pg_sys::NodeTag::T_Var {
+                return None;
+            }
+            // whole-row references and system columns are not supported
+            let var = expr as *mut pg_sys::Var;
+            if (*var).varattno <= 0 {
+                return None;
+            }
+            let is_outer = (*var).varno as pg_sys::Index == outer_varno;
+            columns.push(JoinColumn {
+                outer: is_outer,
+                name: attribute_name(if is_outer { outer } else { inner }, (*var).varattno),
+                typid: (*var).vartype,
+                typmod: (*var).vartypmod,
+            });
+        }
+
+        Some(JoinPlan {
+            join: Join {
+                kind,
+                outer,
+                inner,
+                keys,
+                columns,
+            },
+            local_conds,
+            scan_tlist,
+        })
+    })
+}
+
+/// Match `outer.col = inner.col` with the default equality operator of the
+/// column type
+unsafe fn equi_join_key(
+    expr: *mut pg_sys::Node,
+    (outer_varno, outer_relid): (pg_sys::Index, Oid),
+    (inner_varno, inner_relid): (pg_sys::Index, Oid),
+) -> Option<JoinKey> {
+    if expr.is_null() || (*expr).type_ != pg_sys::NodeTag::T_OpExpr {
+        return None;
+    }
+    let op = expr as *mut pg_sys::OpExpr;
+
+    memcx::current_context(|mcx| {
+        let args = pg_list_to_rust_list::<*mut c_void>((*op).args, mcx);
+        if args.len() != 2 {
+            return None;
+        }
+        let (left, right) = (*args.get(0)? as *mut pg_sys::Node, *args.get(1)? as *mut pg_sys::Node);
+        if (*left).type_ != pg_sys::NodeTag::T_Var || (*right).type_ != pg_sys::NodeTag::T_Var {
+            return None;
+        }
+        let (left, right) = (left as *mut pg_sys::Var, right as *mut pg_sys::Var);
+        let varno = |var: *mut pg_sys::Var| (*var).varno as pg_sys::Index;
+        let (outer, inner) = if varno(left) == outer_varno && varno(right) == inner_varno {
+            (left, right)
+        } else if varno(left) == inner_varno && varno(right) == outer_varno {
+            (right, left)
+        } else {
+            return None;
+        };
+        if (*outer).varattno <= 0 || (*inner).varattno <= 0 || (*outer).vartype != (*inner).vartype {
+            return None;
+        }
+
+        let typid = (*outer).vartype;
+        let typentry = pg_sys::lookup_type_cache(typid, pg_sys::TYPECACHE_EQ_OPR as _);
+        if (*typentry).eq_opr != (*op).opno {
+            return None;
+        }
+        Some(JoinKey {
+            outer: attribute_name(outer_relid, (*outer).varattno),
+            inner: attribute_name(inner_relid, (*inner).varattno),
+            typid,
+            collation: (*op).inputcollid,
+        })
+    })
+}
+
+unsafe fn attribute_name(relid: Oid, attno: pg_sys::AttrNumber) -> String {
+    string_from_cstr(pg_sys::get_attname(relid, attno, false))
+}
@@ -0,0 +1,103 @@
+//! Constant `LIMIT` and `OFFSET` of a query a scan can stop early with.
+//!
+//! The Limit node stays in the plan, a scan told about a limit only has to
+//! return `Limit::rows` rows instead of all of them.
+use pgrx::{pg_sys, FromDatum};
+use crate::fdw::utils_share::utils::{const_value, list_items, make_const};
+
+/// `LIMIT count OFFSET offset` of a query on a single foreign table
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limit {
+    pub count: i64,
+    pub offset: i64,
+}
+
+impl Limit {
+    /// Rows the scan has to return for the Limit node above it
+    pub fn rows(&self) -> i64 {
+        self.count.saturating_add(self.offset)
+    }
+}
+
+/// Constant `LIMIT` and `OFFSET` of the query when they apply to the rows of
+/// `baserel` as scanned. That is the case when the table is the only one of
+/// the query, no grouping, aggregate, window function, `DISTINCT`, set
+/// operation or set returning function sits between the scan and the limit,
+/// and Postgres does not filter the scanned rows. A limit under an `ORDER BY`
+/// only holds when the scan returns rows in that order.
+pub unsafe fn extract_limit(root: *mut pg_sys::PlannerInfo, baserel: *mut pg_sys::RelOptInfo) -> Option<Limit> {
+    let parse = (*root).parse;
+    if (*parse).commandType != pg_sys::CmdType::CMD_SELECT
+        || pg_sys::bms_membership((*root).all_baserels) != pg_sys::BMS_Membership::BMS_SINGLETON
+        || !(*parse).groupClause.is_null()
+        || !(*parse).groupingSets.is_null()
+        || !(*parse).havingQual.is_null()
+        || (*parse).hasAggs
+        || (*parse).hasWindowFuncs
+        || (*parse).hasTargetSRFs
+        || !(*parse).distinctClause.is_null()
+        || !(*parse).setOperations.is_null()
+        || (*parse).limitOption != pg_sys::LimitOption::LIMIT_OPTION_COUNT
+        || !(*baserel).baserestrictinfo.is_null()
+    {
+        return None;
+    }
+
+    let count = const_bigint((*parse).limitCount)??;
+    let offset = if (*parse).limitOffset.is_null() {
+        0
+    } else {
+        // OFFSET NULL skips no rows
+        const_bigint((*parse).limitOffset)?.unwrap_or(0)
+    };
+    Some(Limit {
+        count: count.max(0),
+        offset: offset.max(0),
+    })
+}
+
+/// Limit as plan data, a list of the count and offset `Const`s, see
+/// `make_const`
+pub unsafe fn limit_to_list(limit: &Option<Limit>) -> *mut pg_sys::List {
+    let Some(limit) = limit else {
+        return std::ptr::null_mut();
+    };
+    let list = pg_sys::lappend(std::ptr::null_mut(), make_const(Some(limit.count)));
+    pg_sys::lappend(list, make_const(Some(limit.offset)))
+}
+
+/// Limit of a list made by `limit_to_list`
+pub unsafe fn limit_from_list(list: *mut pg_sys::List) -> Option<Limit> {
+    match list_items(list)[..] {
+        [count, offset] => Some(Limit {
+            count: const_value(count)?,
+            offset: const_value(offset)?,
+        }),
+        _ => None,
+    }
+}
+
+/// Value of a bigint constant, `Some(None)` for NULL and `None` when the node
+/// is not a constant
+unsafe fn const_bigint(node: *mut pg_sys::Node) -> Option<Option<i64>> {
+    if node.is_null() || (*node).type_ != pg_sys::NodeTag::T_Const {
+        return None;
+    }
+    let cst = node as *mut pg_sys::Const;
+    if (*cst).consttype != pg_sys::INT8OID {
+        return None;
+    }
+    Some(i64::from_datum((*cst).constvalue, (*cst).constisnull))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_rows() {
+        assert_eq!(Limit { count: 10, offset: 5 }.rows(), 15);
+        assert_eq!(Limit { count: i64::MAX, offset: 5 }.rows(), i64::MAX);
+    }
+}
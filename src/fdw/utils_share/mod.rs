@@ -1,6 +1,13 @@
 pub mod cell;
+pub mod cell_serde;
+pub mod fdw;
+pub mod join;
+pub mod limit;
 pub mod row;
 pub mod memory;
+pub mod options;
+pub mod qual;
+pub mod sort;
 pub mod utils;
 
 
@@ -45,5 +52,36 @@ mod tests {
         let result = build_header_index_map(&headers, &attr_map);
         assert_eq!(result, vec![2, 0, 1]);
     }
-}
 
+    #[pgrx_macros::pg_test]
+    fn test_fill_slot_from_row() {
+        use pgrx::{pg_sys, FromDatum};
+        use crate::fdw::utils_share::{cell::Cell, row::Row, utils::fill_slot_from_row};
+
+        unsafe {
+            let tupdesc = pg_sys::CreateTemplateTupleDesc(2);
+            pg_sys::TupleDescInitEntry(tupdesc, 1, c"id".as_ptr(), pg_sys::INT8OID, -1, 0);
+            pg_sys::TupleDescInitEntry(tupdesc, 2, c"col".as_ptr(), pg_sys::TEXTOID, -1, 0);
+            let slot = pg_sys::MakeSingleTupleTableSlot(tupdesc, &pg_sys::TTSOpsVirtual);
+            let colmap = HashMap::from([("id".to_string(), 0), ("col".to_string(), 1)]);
+
+            let mut row = Row::new();
+            row.push("id", Some(Cell::I64(1)));
+            row.push("col", Some(Cell::String("a".to_string())));
+            fill_slot_from_row(slot, &row, &colmap);
+            assert_eq!(i64::from_datum(*(*slot).tts_values, *(*slot).tts_isnull), Some(1));
+            assert!(!*(*slot).tts_isnull.add(1));
+
+            // a shorter row leaves no value of the previous one behind
+            let mut row = Row::new();
+            row.push("id", Some(Cell::I64(2)));
+            row.push("unknown", Some(Cell::I64(3)));
+            fill_slot_from_row(slot, &row, &colmap);
+            assert_eq!(i64::from_datum(*(*slot).tts_values, *(*slot).tts_isnull), Some(2));
+            assert!(*(*slot).tts_isnull.add(1));
+            assert_eq!((*slot).tts_nvalid, 2);
+
+            pg_sys::ExecDropSingleTupleTableSlot(slot);
+        }
+    }
+}
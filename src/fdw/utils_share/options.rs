@@ -0,0 +1,203 @@
+//! Options a wrapper accepts, for its validator.
+//!
+//! A wrapper lists its options with the catalogs they may be set in and the
+//! kind of value they take, and checks the options of a `CREATE` or `ALTER`
+//! with `check_options` from `ForeignDataWrapper::validator`. Unknown names
+//! are reported with the closest valid option of the context.
+use std::path::Path;
+use pgrx::{pg_sys::{self, Oid}, PgSqlErrorCode};
+use crate::fdw::utils_share::fdw::{FdwError, FdwResult};
+
+/// `CREATE FOREIGN DATA WRAPPER`
+pub const WRAPPER: Oid = pg_sys::ForeignDataWrapperRelationId;
+/// `CREATE SERVER`
+pub const SERVER: Oid = pg_sys::ForeignServerRelationId;
+/// `CREATE USER MAPPING`
+pub const USER_MAPPING: Oid = pg_sys::UserMappingRelationId;
+/// `CREATE FOREIGN TABLE`
+pub const TABLE: Oid = pg_sys::ForeignTableRelationId;
+/// options of the columns of a foreign table
+pub const COLUMN: Oid = pg_sys::AttributeRelationId;
+
+/// Value an option takes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptionKind {
+    Text,
+    /// `true`/`false`, `on`/`off`, `yes`/`no` or `1`/`0`
+    Bool,
+    /// integer above 0
+    PositiveInt,
+    /// one of the listed words, in any case
+    Choice(&'static [&'static str]),
+    /// path of an existing file
+    ExistingFile,
+    /// existing directory, or file in an existing directory; empty for none
+    OutputPath,
+}
+
+/// Option of a wrapper and the catalogs it may be set in
+#[derive(Debug, Clone, Copy)]
+pub struct FdwOption {
+    pub name: &'static str,
+    pub catalogs: &'static [Oid],
+    pub kind: OptionKind,
+}
+
+/// Check `options`, set in `catalog`, against the options of a wrapper
+pub fn check_options(options: &[(String, String)], catalog: Oid, known: &[FdwOption]) -> FdwResult<()> {
+    let valid: Vec<&FdwOption> = known.iter().filter(|o| o.catalogs.contains(&catalog)).collect();
+    for (name, value) in options {
+        let Some(option) = valid.iter().find(|o| o.name == name) else {
+            return Err(unknown_option(name, catalog, known, &valid));
+        };
+        check_value(option, value)?;
+    }
+    Ok(())
+}
+
+/// Boolean option value, see `OptionKind::Bool`
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "on" | "yes" | "1" => Some(true),
+        "false" | "off" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Integer option value above 0
+pub fn parse_positive_int(value: &str) -> Option<usize> {
+    value.parse::<usize>().ok().filter(|n| *n > 0)
+}
+
+fn check_value(option: &FdwOption, value: &str) -> FdwResult<()> {
+    let invalid = |expected: &str| {
+        Err(FdwError::new(
+            PgSqlErrorCode::ERRCODE_FDW_INVALID_ATTRIBUTE_VALUE,
+            format!("invalid value for option \"{}\": \"{}\"", option.name, value),
+        )
+        .with_hint(format!("{} requires {}.", option.name, expected)))
+    };
+    match option.kind {
+        OptionKind::Text => Ok(()),
+        OptionKind::Bool if parse_bool(value).is_none() => invalid("a Boolean value"),
+        OptionKind::PositiveInt if parse_positive_int(value).is_none() => invalid("a positive integer value"),
+        OptionKind::Choice(choices) if !choices.iter().any(|c| c.eq_ignore_ascii_case(value)) => {
+            invalid(&format!("one of {}", choices.join(", ")))
+        }
+        OptionKind::ExistingFile if !Path::new(value).is_file() => invalid("the path of an existing file"),
+        OptionKind::OutputPath if !value.is_empty() && !is_output_path(Path::new(value)) => {
+            invalid("an existing directory or a file in one")
+        }
+        _ => Ok(()),
+    }
+}
+
+fn is_output_path(path: &Path) -> bool {
+    if path.is_dir() {
+        return true;
+    }
+    match path.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => true,
+        Some(parent) => parent.is_dir(),
+        None => false,
+    }
+}
+
+fn unknown_option(name: &str, catalog: Oid, known: &[FdwOption], valid: &[&FdwOption]) -> FdwError {
+    let names: Vec<&str> = valid.iter().map(|o| o.name).collect();
+    if known.iter().any(|o| o.name == name) {
+        let error = FdwError::new(
+            PgSqlErrorCode::ERRCODE_FDW_INVALID_OPTION_NAME,
+            format!("option \"{}\" cannot be set on a {}", name, catalog_name(catalog)),
+        );
+        if names.is_empty() {
+            return error;
+        }
+        return error.with_hint(format!("Valid options in this context are: {}", names.join(", ")));
+    }
+
+    let error = FdwError::new(PgSqlErrorCode::ERRCODE_FDW_INVALID_OPTION_NAME, format!("invalid option \"{}\"", name));
+    match closest_name(name, &names) {
+        Some(closest) => error.with_hint(format!("Perhaps you meant the option \"{}\".", closest)),
+        None if names.is_empty() => error.with_hint("There are no valid options in this context."),
+        None => error.with_hint(format!("Valid options in this context are: {}", names.join(", "))),
+    }
+}
+
+fn catalog_name(catalog: Oid) -> &'static str {
+    match catalog {
+        WRAPPER => "foreign-data wrapper",
+        SERVER => "server",
+        USER_MAPPING => "user mapping",
+        TABLE => "foreign table",
+        COLUMN => "column",
+        _ => "object",
+    }
+}
+
+/// Name closest to `name` by edit distance, when it is close enough to be a
+/// typo of it
+fn closest_name<'a>(name: &str, names: &[&'a str]) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    names
+        .iter()
+        .map(|candidate| (levenshtein(&name.to_lowercase(), candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb { diagonal } else { 1 + diagonal.min(above).min(row[j]) };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use super::*;
+
+    const OPTIONS: &[FdwOption] = &[
+        FdwOption { name: "filepath", catalogs: &[TABLE], kind: OptionKind::ExistingFile },
+        FdwOption { name: "updatable", catalogs: &[SERVER, TABLE], kind: OptionKind::Bool },
+        FdwOption { name: "eviction", catalogs: &[TABLE], kind: OptionKind::Choice(&["error", "lru"]) },
+    ];
+
+    fn options(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_closest_name() {
+        assert_eq!(levenshtein("file_path", "filepath"), 1);
+        assert_eq!(closest_name("file_path", &["filepath", "updatable"]), Some("filepath"));
+        assert_eq!(closest_name("Updateable", &["filepath", "updatable"]), Some("updatable"));
+        assert_eq!(closest_name("foo", &["filepath", "updatable"]), None);
+    }
+
+    #[test]
+    fn test_check_options() {
+        assert!(check_options(&options(&[("updatable", "off"), ("eviction", "LRU")]), TABLE, OPTIONS).is_ok());
+        assert!(check_options(&options(&[("updatable", "maybe")]), SERVER, OPTIONS).is_err());
+        assert!(check_options(&options(&[("eviction", "random")]), TABLE, OPTIONS).is_err());
+        assert!(check_options(&options(&[("filepath", "/nonexistent/file.csv")]), TABLE, OPTIONS).is_err());
+
+        let err = check_options(&options(&[("file_path", "/tmp/a.csv")]), TABLE, OPTIONS).unwrap_err();
+        assert_eq!(err.message, "invalid option \"file_path\"");
+        assert_eq!(err.hint.as_deref(), Some("Perhaps you meant the option \"filepath\"."));
+        let err = check_options(&options(&[("filepath", "/tmp/a.csv")]), SERVER, OPTIONS).unwrap_err();
+        assert_eq!(err.message, "option \"filepath\" cannot be set on a server");
+        let err = check_options(&options(&[("foo", "bar")]), USER_MAPPING, OPTIONS).unwrap_err();
+        assert_eq!(err.hint.as_deref(), Some("There are no valid options in this context."));
+    }
+}
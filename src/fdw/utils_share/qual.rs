@@ -0,0 +1,471 @@
+//! Restrictions of a scan turned into `Qual`s a wrapper can check on its rows.
+//!
+//! `extract_quals` walks the `baserestrictinfo` of a foreign relation while
+//! planning. Quals comparing a column to a `Param` get their value when the
+//! scan starts, see `resolve_params`. Postgres still checks every restriction
+//! on the returned rows, so a wrapper may use any subset of the quals.
+use std::{cmp::Ordering, ffi::c_void, fmt, ptr};
+use pgrx::{memcx, pg_sys::{self, Oid}, FromDatum, PgBox};
+use crate::fdw::utils_share::{cell::Cell, row::Row, utils::{pg_list_to_rust_list, string_from_cstr}};
+
+/// Operators `Qual::eval` knows how to check
+const SUPPORTED_OPERATORS: &[&str] = &["=", "<>", "<", "<=", ">", ">=", "~~", "!~~"];
+
+/// Parameter of a qual, evaluated at the start of each scan
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub kind: pg_sys::ParamKind::Type,
+    pub id: i32,
+    pub typid: Oid,
+    pub typmod: i32,
+    pub collid: Oid,
+    /// executor state of the parameter, built when the scan starts
+    pub expr_state: *mut pg_sys::ExprState,
+    /// whether `Qual::value` holds the current value of the parameter
+    pub resolved: bool,
+}
+
+/// `field operator value` restriction of a scan. `IS [NOT] NULL` uses the
+/// operators `is` and `is not`. For `field op ANY/ALL (array)` the value is
+/// the array, `is_scalar_array_op` is set and `use_or` tells ANY from ALL.
+#[derive(Debug, Clone)]
+pub struct Qual {
+    pub field: String,
+    pub operator: String,
+    pub value: Cell,
+    pub use_or: bool,
+    /// whether the qual is a `ScalarArrayOpExpr`, comparing the field to
+    /// each element of the value rather than to the value
+    pub is_scalar_array_op: bool,
+    pub param: Option<Param>,
+    /// input collation of the operator, `InvalidOid` when it has none
+    pub collation: Oid,
+}
+
+impl Qual {
+    fn new(field: String, operator: &str, value: Cell) -> Self {
+        Self {
+            field,
+            operator: operator.to_string(),
+            value,
+            use_or: false,
+            is_scalar_array_op: false,
+            param: None,
+            collation: pg_sys::InvalidOid,
+        }
+    }
+
+    /// Whether values equal under the collation of the qual are equal
+    /// strings. A nondeterministic collation, like a case insensitive one,
+    /// matches strings that differ.
+    pub fn is_deterministic(&self) -> bool {
+        self.collation == pg_sys::InvalidOid
+            || self.collation == pg_sys::DEFAULT_COLLATION_OID
+            || self.collation == pg_sys::C_COLLATION_OID
+            || unsafe { pg_sys::get_collation_isdeterministic(self.collation) }
+    }
+
+    /// Check the qual on a row. Quals the row cannot be checked against, like
+    /// unresolved parameters, a missing column, text ordering, which depends
+    /// on the collation, or text comparisons under a nondeterministic
+    /// collation, let the row through.
+    pub fn eval(&self, row: &Row) -> bool {
+        if self.param.as_ref().is_some_and(|p| !p.resolved) {
+            return true;
+        }
+        let Some((_, cell)) = row.iter().find(|(col, _)| **col == self.field) else {
+            return true;
+        };
+
+        match self.operator.as_str() {
+            "is" => return cell.is_none(),
+            "is not" => return cell.is_some(),
+            _ => {}
+        }
+        // comparisons with NULL are never true
+        let Some(cell) = cell else {
+            return false;
+        };
+        if string_value(cell).is_some() && !self.is_deterministic() {
+            return true;
+        }
+        // NULL elements never match, unknown results keep the row
+        let apply = |e: &Option<Cell>| e.as_ref().map_or(Some(false), |e| apply_operator(&self.operator, cell, e));
+        match self.value.array_elements().filter(|_| self.is_scalar_array_op) {
+            Some(elements) if self.use_or => elements.iter().map(apply).any(|res| res != Some(false)),
+            Some(elements) => elements.iter().map(apply).all(|res| res != Some(false)),
+            None => apply_operator(&self.operator, cell, &self.value).unwrap_or(true),
+        }
+    }
+}
+
+impl fmt::Display for Qual {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match &self.param {
+            Some(param) if param.kind == pg_sys::ParamKind::PARAM_EXTERN => format!("${}", param.id),
+            Some(_) => "(subplan)".to_string(),
+            None if self.operator == "is" || self.operator == "is not" => "NULL".to_string(),
+            None => self.value.to_sql_literal(),
+        };
+        if self.is_scalar_array_op {
+            write!(f, "{} {} {}({})", self.field, self.operator, if self.use_or { "ANY" } else { "ALL" }, value)
+        } else {
+            write!(f, "{} {} {}", self.field, self.operator, value)
+        }
+    }
+}
+
+/// Check all quals on a row
+pub fn eval_quals(quals: &[Qual], row: &Row) -> bool {
+    quals.iter().all(|qual| qual.eval(row))
+}
+
+/// Result of `cell operator value`, `None` when it cannot be told
+fn apply_operator(operator: &str, cell: &Cell, value: &Cell) -> Option<bool> {
+    match (operator, string_value(cell), string_value(value)) {
+        ("~~" | "!~~", Some(s), Some(pattern)) => Some(like_match(s, pattern) == (operator == "~~")),
+        ("~~" | "!~~", _, _) => None,
+        // text ordering follows the collation
+        ("<" | "<=" | ">" | ">=", Some(_), _) => None,
+        // the operators of types without a variant are unknown
+        _ if matches!(cell, Cell::Other { .. }) => None,
+        _ => {
+            let ord = cell.partial_cmp(value)?;
+            match operator {
+                "=" => Some(ord == Ordering::Equal),
+                "<>" => Some(ord != Ordering::Equal),
+                "<" => Some(ord == Ordering::Less),
+                "<=" => Some(ord != Ordering::Greater),
+                ">" => Some(ord == Ordering::Greater),
+                ">=" => Some(ord != Ordering::Less),
+                _ => None,
+            }
+        }
+    }
+}
+
+fn string_value(cell: &Cell) -> Option<&str> {
+    match cell {
+        Cell::String(v) | Cell::Varchar(v) | Cell::Bpchar(v) | Cell::Name(v) => Some(v),
+        _ => None,
+    }
+}
+
+/// SQL `LIKE` with `%`, `_` and `\` as escape character
+fn like_match(s: &str, pattern: &str) -> bool {
+    let s: Vec<char> = s.chars().collect();
+    let p: Vec<char> = pattern.chars().collect();
+    // position in the pattern after the last `%` and the text position it
+    // matched from, to backtrack to
+    let (mut si, mut pi) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while si < s.len() {
+        match p.get(pi) {
+            Some('%') => {
+                pi += 1;
+                star = Some((pi, si));
+                continue;
+            }
+            Some('_') => {
+                si += 1;
+                pi += 1;
+                continue;
+            }
+            Some('\\') if pi + 1 < p.len() && p[pi + 1] == s[si] => {
+                si += 1;
+                pi += 2;
+                continue;
+            }
+            Some(c) if *c != '\\' && *c == s[si] => {
+                si += 1;
+                pi += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match star {
+            Some((star_pi, star_si)) => {
+                pi = star_pi;
+                si = star_si + 1;
+                star = Some((star_pi, star_si + 1));
+            }
+            None => return false,
+        }
+    }
+    p[pi.min(p.len())..].iter().all(|c| *c == '%')
+}
+
+/// Turn the restrictions of a foreign relation into quals. Restrictions that
+/// are not a supported comparison of a column with a constant or parameter
+/// are skipped.
+pub unsafe fn extract_quals(
+    _root: *mut pg_sys::PlannerInfo,
+    baserel: *mut pg_sys::RelOptInfo,
+    relid: Oid,
+) -> Vec<Qual> {
+    let mut quals = Vec::new();
+    memcx::current_context(|mcx| {
+        let restrictions = pg_list_to_rust_list::<*mut c_void>((*baserel).baserestrictinfo, mcx);
+        for ri in restrictions.iter() {
+            let ri = *ri as *mut pg_sys::RestrictInfo;
+            extract_clause((*ri).clause as _, (*baserel).relid, relid, &mut quals);
+        }
+    });
+    quals
+}
+
+/// Turn the qual of a plan into quals, for a scan starting from a plan that
+/// may be run more than once. `varno` is the range table index of the scan.
+pub unsafe fn extract_plan_quals(clauses: *mut pg_sys::List, varno: pg_sys::Index, relid: Oid) -> Vec<Qual> {
+    let mut quals = Vec::new();
+    memcx::current_context(|mcx| {
+        for clause in pg_list_to_rust_list::<*mut c_void>(clauses, mcx).iter() {
+            extract_clause(*clause as _, varno, relid, &mut quals);
+        }
+    });
+    quals
+}
+
+unsafe fn extract_clause(node: *mut pg_sys::Node, varno: pg_sys::Index, relid: Oid, quals: &mut Vec<Qual>) {
+    if node.is_null() {
+        return;
+    }
+    let qual = match (*node).type_ {
+        pg_sys::NodeTag::T_OpExpr => extract_op_expr(node as _, varno, relid),
+        pg_sys::NodeTag::T_ScalarArrayOpExpr => extract_array_op_expr(node as _, varno, relid),
+        pg_sys::NodeTag::T_NullTest => extract_null_test(node as _, varno, relid),
+        pg_sys::NodeTag::T_BoolExpr => {
+            let expr = node as *mut pg_sys::BoolExpr;
+            match (*expr).boolop {
+                // restrictions are implicitly ANDed already
+                pg_sys::BoolExprType::AND_EXPR => {
+                    memcx::current_context(|mcx| {
+                        for arg in pg_list_to_rust_list::<*mut c_void>((*expr).args, mcx).iter() {
+                            extract_clause(*arg as _, varno, relid, quals);
+                        }
+                    });
+                    None
+                }
+                // NOT bool_column
+                pg_sys::BoolExprType::NOT_EXPR => memcx::current_context(|mcx| {
+                    let args = pg_list_to_rust_list::<*mut c_void>((*expr).args, mcx);
+                    args.get(0)
+                        .and_then(|arg| column_name(*arg as _, varno, relid))
+                        .map(|field| Qual::new(field, "=", Cell::Bool(false)))
+                }),
+                _ => None,
+            }
+        }
+        // a plain bool_column
+        pg_sys::NodeTag::T_Var => column_name(node, varno, relid)
+            .filter(|_| (*(node as *mut pg_sys::Var)).vartype == pg_sys::BOOLOID)
+            .map(|field| Qual::new(field, "=", Cell::Bool(true))),
+        _ => None,
+    };
+    quals.extend(qual);
+}
+
+/// Name of the column when `node` is a column of the scanned relation,
+/// looking through binary compatible casts
+unsafe fn column_name(mut node: *mut pg_sys::Node, varno: pg_sys::Index, relid: Oid) -> Option<String> {
+    while !node.is_null() && (*node).type_ == pg_sys::NodeTag::T_RelabelType {
+        node = (*(node as *mut pg_sys::RelabelType)).arg as _;
+    }
+    if node.is_null() || (*node).type_ != pg_sys::NodeTag::T_Var {
+        return None;
+    }
+    let var = node as *mut pg_sys::Var;
+    if (*var).varno as pg_sys::Index != varno || (*var).varattno <= 0 || (*var).varlevelsup != 0 {
+        return None;
+    }
+    Some(string_from_cstr(pg_sys::get_attname(relid, (*var).varattno, false)))
+}
+
+/// Value of a constant or a parameter placeholder
+unsafe fn operand(node: *mut pg_sys::Node) -> Option<(Cell, Option<Param>)> {
+    match (*node).type_ {
+        pg_sys::NodeTag::T_Const => {
+            let cst = node as *mut pg_sys::Const;
+            Cell::from_polymorphic_datum((*cst).constvalue, (*cst).constisnull, (*cst).consttype).map(|c| (c, None))
+        }
+        pg_sys::NodeTag::T_Param => {
+            let param = node as *mut pg_sys::Param;
+            let placeholder = Cell::String(String::new());
+            Some((
+                placeholder,
+                Some(Param {
+                    kind: (*param).paramkind,
+                    id: (*param).paramid,
+                    typid: (*param).paramtype,
+                    typmod: (*param).paramtypmod,
+                    collid: (*param).paramcollid,
+                    expr_state: ptr::null_mut(),
+                    resolved: false,
+                }),
+            ))
+        }
+        _ => None,
+    }
+}
+
+unsafe fn operator_name(opno: Oid) -> Option<String> {
+    let name = string_from_cstr(pg_sys::get_opname(opno));
+    SUPPORTED_OPERATORS.contains(&name.as_str()).then_some(name)
+}
+
+unsafe fn extract_op_expr(expr: *mut pg_sys::OpExpr, varno: pg_sys::Index, relid: Oid) -> Option<Qual> {
+    let (left, right) = memcx::current_context(|mcx| {
+        let args = pg_list_to_rust_list::<*mut c_void>((*expr).args, mcx);
+        if args.len() != 2 {
+            return None;
+        }
+        Some((*args.get(0)? as *mut pg_sys::Node, *args.get(1)? as *mut pg_sys::Node))
+    })?;
+
+    // `const op column` is turned around with the commutator
+    let (field, value, opno) = match column_name(left, varno, relid) {
+        Some(field) => (field, right, (*expr).opno),
+        None => (column_name(right, varno, relid)?, left, pg_sys::get_commutator((*expr).opno)),
+    };
+    if opno == pg_sys::InvalidOid {
+        return None;
+    }
+    let operator = operator_name(opno)?;
+    let (value, param) = operand(value)?;
+    Some(Qual {
+        param,
+        collation: (*expr).inputcollid,
+        ..Qual::new(field, &operator, value)
+    })
+}
+
+unsafe fn extract_array_op_expr(
+    expr: *mut pg_sys::ScalarArrayOpExpr,
+    varno: pg_sys::Index,
+    relid: Oid,
+) -> Option<Qual> {
+    let (left, right) = memcx::current_context(|mcx| {
+        let args = pg_list_to_rust_list::<*mut c_void>((*expr).args, mcx);
+        if args.len() != 2 {
+            return None;
+        }
+        Some((*args.get(0)? as *mut pg_sys::Node, *args.get(1)? as *mut pg_sys::Node))
+    })?;
+
+    let field = column_name(left, varno, relid)?;
+    let operator = operator_name((*expr).opno)?;
+    let (value, param) = operand(right)?;
+    if param.is_none() && value.array_elements().is_none() {
+        return None;
+    }
+    Some(Qual {
+        use_or: (*expr).useOr,
+        is_scalar_array_op: true,
+        param,
+        collation: (*expr).inputcollid,
+        ..Qual::new(field, &operator, value)
+    })
+}
+
+unsafe fn extract_null_test(expr: *mut pg_sys::NullTest, varno: pg_sys::Index, relid: Oid) -> Option<Qual> {
+    if (*expr).argisrow {
+        return None;
+    }
+    let field = column_name((*expr).arg as _, varno, relid)?;
+    let operator = if (*expr).nulltesttype == pg_sys::NullTestType::IS_NULL { "is" } else { "is not" };
+    Some(Qual::new(field, operator, Cell::String("null".to_string())))
+}
+
+/// Evaluate the parameters of the quals for the current execution of a
+/// scan. Called when the scan starts and again on rescans, when the values of
+/// executor parameters may have changed. Must run in the per-query context of
+/// the executor.
+pub unsafe fn resolve_params(quals: &mut [Qual], node: *mut pg_sys::ForeignScanState) {
+    let econtext = (*node).ss.ps.ps_ExprContext;
+    for qual in quals.iter_mut() {
+        let Some(param) = qual.param.as_mut() else {
+            continue;
+        };
+        if param.expr_state.is_null() {
+            // the planner's node may be gone with a cached plan, build a new one
+            let mut expr = PgBox::<pg_sys::Param>::alloc_node(pg_sys::NodeTag::T_Param);
+            expr.paramkind = param.kind;
+            expr.paramid = param.id;
+            expr.paramtype = param.typid;
+            expr.paramtypmod = param.typmod;
+            expr.paramcollid = param.collid;
+            expr.location = -1;
+            param.expr_state = pg_sys::ExecInitExpr(expr.into_pg() as _, node as *mut pg_sys::PlanState);
+        }
+        let Some(eval) = (*param.expr_state).evalfunc else {
+            param.resolved = false;
+            continue;
+        };
+        let mut is_null = false;
+        let datum = eval(param.expr_state, econtext, &mut is_null);
+        // a NULL or unsupported value leaves the qual to Postgres
+        match Cell::from_polymorphic_datum(datum, is_null, param.typid) {
+            Some(value) => {
+                qual.value = value;
+                param.resolved = true;
+            }
+            None => param.resolved = false,
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use super::*;
+
+    fn row() -> Row {
+        let mut row = Row::new();
+        row.push("id", Some(Cell::I64(21)));
+        row.push("col", Some(Cell::String("test21".to_string())));
+        row.push("flag", None);
+        row
+    }
+
+    #[test]
+    fn test_qual_comparisons() {
+        let row = row();
+        assert!(Qual::new("id".to_string(), "=", Cell::I32(21)).eval(&row));
+        assert!(Qual::new("id".to_string(), ">", Cell::I64(2)).eval(&row));
+        assert!(!Qual::new("id".to_string(), "<=", Cell::F64(20.5)).eval(&row));
+        assert!(Qual::new("col".to_string(), "<>", Cell::String("test2".to_string())).eval(&row));
+        // text ordering is left to Postgres
+        assert!(Qual::new("col".to_string(), ">", Cell::String("z".to_string())).eval(&row));
+        // comparisons with NULL never hold
+        assert!(!Qual::new("flag".to_string(), "=", Cell::Bool(true)).eval(&row));
+        assert!(Qual::new("flag".to_string(), "is", Cell::String("null".to_string())).eval(&row));
+        assert!(!Qual::new("id".to_string(), "is", Cell::String("null".to_string())).eval(&row));
+    }
+
+    #[test]
+    fn test_qual_array_and_like() {
+        let row = row();
+        let mut any = Qual::new("id".to_string(), "=", Cell::I64Array(vec![Some(1), None, Some(21)]));
+        any.use_or = true;
+        any.is_scalar_array_op = true;
+        assert!(any.eval(&row));
+        assert_eq!(any.to_string(), "id = ANY('{1,NULL,21}'::bigint[])");
+        let mut all = Qual::new("id".to_string(), "<>", Cell::I64Array(vec![Some(1), Some(2)]));
+        all.is_scalar_array_op = true;
+        assert!(all.eval(&row));
+
+        // `array = array` compares whole arrays, NULL elements included
+        let mut row = Row::new();
+        row.push("ids", Some(Cell::I32Array(vec![Some(1), None])));
+        let eq = Qual::new("ids".to_string(), "=", Cell::I32Array(vec![Some(1), None]));
+        assert!(eq.eval(&row));
+        assert_eq!(eq.to_string(), "ids = '{1,NULL}'::integer[]");
+        assert!(!Qual::new("ids".to_string(), "=", Cell::I32Array(vec![Some(1)])).eval(&row));
+
+        assert!(Qual::new("col".to_string(), "~~", Cell::String("test%".to_string())).eval(&row));
+        assert!(Qual::new("col".to_string(), "~~", Cell::String("_est_1".to_string())).eval(&row));
+        assert!(Qual::new("col".to_string(), "!~~", Cell::String("%3".to_string())).eval(&row));
+        assert!(like_match("100%", "100\\%"));
+        assert!(!like_match("1000", "100\\%"));
+    }
+}
@@ -0,0 +1,199 @@
+//! `ORDER BY` of a query turned into `Sort`s a wrapper can return its rows in.
+//!
+//! `extract_sorts` reads the pathkeys the query wants while planning a scan.
+//! A wrapper that returns rows in that order gets a path with those pathkeys,
+//! so Postgres can leave out its own Sort node.
+use std::ffi::c_void;
+use pgrx::{memcx, pg_sys::{self, Oid}};
+use crate::fdw::utils_share::utils::{const_value, list_items, make_const, pg_list_to_rust_list, string_from_cstr};
+
+/// Sort key of a scan: a column, its direction, where NULLs go and the
+/// collation when it is not the default one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sort {
+    pub field: String,
+    /// attribute number of the column, from 1
+    pub field_no: usize,
+    pub reversed: bool,
+    pub nulls_first: bool,
+    pub collate: Option<String>,
+    /// collation of the comparison, `InvalidOid` for types without one
+    pub collation: Oid,
+}
+
+impl Sort {
+    /// `ORDER BY` item as SQL, e.g. `name COLLATE "C" DESC NULLS LAST`
+    pub fn deparse(&self) -> String {
+        let mut sql = self.field.clone();
+        if let Some(collate) = &self.collate {
+            sql.push_str(&format!(" COLLATE \"{}\"", collate));
+        }
+        sql.push_str(if self.reversed { " DESC" } else { " ASC" });
+        // only mention NULLS when it differs from the default of the direction
+        if self.nulls_first != self.reversed {
+            sql.push_str(if self.nulls_first { " NULLS FIRST" } else { " NULLS LAST" });
+        }
+        sql
+    }
+}
+
+/// Sort keys of the `ORDER BY` of the query that are columns of `baserel`.
+/// Keys after the first one that is not a plain column are left out, rows
+/// sorted on the returned keys are still useful for an incremental sort.
+pub unsafe fn extract_sorts(root: *mut pg_sys::PlannerInfo, baserel: *mut pg_sys::RelOptInfo, relid: Oid) -> Vec<Sort> {
+    pathkeys_to_sorts((*root).query_pathkeys, baserel, relid)
+}
+
+/// Sort keys of a pathkey list, up to the first pathkey that is not a column
+/// of `baserel`
+pub unsafe fn pathkeys_to_sorts(pathkeys: *mut pg_sys::List, baserel: *mut pg_sys::RelOptInfo, relid: Oid) -> Vec<Sort> {
+    memcx::current_context(|mcx| {
+        pg_list_to_rust_list::<*mut c_void>(pathkeys, mcx)
+            .iter()
+            .map_while(|pk| pathkey_to_sort(*pk as *mut pg_sys::PathKey, baserel, relid))
+            .collect()
+    })
+}
+
+unsafe fn pathkey_to_sort(pk: *mut pg_sys::PathKey, baserel: *mut pg_sys::RelOptInfo, relid: Oid) -> Option<Sort> {
+    let ec = (*pk).pk_eclass;
+    if (*ec).ec_has_volatile {
+        return None;
+    }
+    let attno = memcx::current_context(|mcx| {
+        pg_list_to_rust_list::<*mut c_void>((*ec).ec_members, mcx).iter().find_map(|em| {
+            let mut expr = (*(*em as *mut pg_sys::EquivalenceMember)).em_expr as *mut pg_sys::Node;
+            while (*expr).type_ == pg_sys::NodeTag::T_RelabelType {
+                expr = (*(expr as *mut pg_sys::RelabelType)).arg as _;
+            }
+            if (*expr).type_ != pg_sys::NodeTag::T_Var {
+                return None;
+            }
+            let var = expr as *mut pg_sys::Var;
+            ((*var).varno as pg_sys::Index == (*baserel).relid && (*var).varattno > 0 && (*var).varlevelsup == 0)
+                .then_some((*var).varattno)
+        })
+    })?;
+
+    // rows are sorted with the default operators of the column type
+    let typentry = pg_sys::lookup_type_cache(pg_sys::get_atttype(relid, attno), pg_sys::TYPECACHE_BTREE_OPFAMILY as _);
+    if (*typentry).btree_opf != (*pk).pk_opfamily {
+        return None;
+    }
+
+    let collation = (*ec).ec_collation;
+    let collate = (collation != pg_sys::InvalidOid && collation != pg_sys::DEFAULT_COLLATION_OID)
+        .then(|| string_from_cstr(pg_sys::get_collation_name(collation)));
+    Some(Sort {
+        field: string_from_cstr(pg_sys::get_attname(relid, attno, false)),
+        field_no: attno as usize,
+        reversed: (*pk).pk_strategy == pg_sys::BTGreaterStrategyNumber as i32,
+        nulls_first: (*pk).pk_nulls_first,
+        collate,
+        collation,
+    })
+}
+
+/// Ascending sorts on the columns of `baserel` that are merge join keys, with
+/// their pathkeys. Rows in one of these orders save a merge join its Sort.
+pub unsafe fn merge_join_sorts(
+    root: *mut pg_sys::PlannerInfo,
+    baserel: *mut pg_sys::RelOptInfo,
+    relid: Oid,
+) -> Vec<(*mut pg_sys::PathKey, Sort)> {
+    let mut sorts = Vec::new();
+    memcx::current_context(|mcx| {
+        for ec in pg_list_to_rust_list::<*mut c_void>((*root).eq_classes, mcx).iter() {
+            let ec = *ec as *mut pg_sys::EquivalenceClass;
+            // a constant member makes the order useless, a single member
+            // joins nothing
+            if (*ec).ec_has_const
+                || (*ec).ec_has_volatile
+                || pg_sys::list_length((*ec).ec_members) < 2
+                || !pg_sys::bms_is_subset((*baserel).relids, (*ec).ec_relids)
+            {
+                continue;
+            }
+            for opfamily in pg_list_to_rust_list::<pg_sys::Oid>((*ec).ec_opfamilies, mcx).iter() {
+                let pathkey =
+                    pg_sys::make_canonical_pathkey(root, ec, *opfamily, pg_sys::BTLessStrategyNumber as _, false);
+                if let Some(sort) = pathkey_to_sort(pathkey, baserel, relid) {
+                    sorts.push((pathkey, sort));
+                    break;
+                }
+            }
+        }
+    });
+    sorts
+}
+
+/// Leading `count` pathkeys of the query, for a path returning rows in the
+/// order of the first `count` sorts of `extract_sorts`
+pub unsafe fn query_pathkeys_head(root: *mut pg_sys::PlannerInfo, count: usize) -> *mut pg_sys::List {
+    if count == 0 {
+        return std::ptr::null_mut();
+    }
+    pg_sys::list_truncate(pg_sys::list_copy((*root).query_pathkeys), count as i32)
+}
+
+/// Sorts as plan data, a list of `Const` lists, see `make_const`
+pub unsafe fn sorts_to_list(sorts: &[Sort]) -> *mut pg_sys::List {
+    let mut list = std::ptr::null_mut();
+    for sort in sorts {
+        let mut item = std::ptr::null_mut();
+        item = pg_sys::lappend(item, make_const(Some(sort.field.clone())));
+        item = pg_sys::lappend(item, make_const(Some(sort.field_no as i32)));
+        item = pg_sys::lappend(item, make_const(Some(sort.reversed)));
+        item = pg_sys::lappend(item, make_const(Some(sort.nulls_first)));
+        item = pg_sys::lappend(item, make_const(sort.collate.clone()));
+        item = pg_sys::lappend(item, make_const(Some(sort.collation)));
+        list = pg_sys::lappend(list, item as _);
+    }
+    list
+}
+
+/// Sorts of a list made by `sorts_to_list`
+pub unsafe fn sorts_from_list(list: *mut pg_sys::List) -> Vec<Sort> {
+    list_items(list)
+        .into_iter()
+        .map(|item| {
+            let fields = list_items(item as _);
+            Sort {
+                field: const_value(fields[0]).unwrap_or_default(),
+                field_no: const_value::<i32>(fields[1]).unwrap_or_default() as usize,
+                reversed: const_value(fields[2]).unwrap_or_default(),
+                nulls_first: const_value(fields[3]).unwrap_or_default(),
+                collate: const_value(fields[4]),
+                collation: const_value(fields[5]).unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sort_deparse() {
+        let sort = Sort {
+            field: "id".to_string(),
+            field_no: 1,
+            ..Default::default()
+        };
+        assert_eq!(sort.deparse(), "id ASC");
+        let sort = Sort {
+            reversed: true,
+            ..sort
+        };
+        assert_eq!(sort.deparse(), "id DESC NULLS LAST");
+        let sort = Sort {
+            field: "name".to_string(),
+            nulls_first: true,
+            collate: Some("C".to_string()),
+            ..sort
+        };
+        assert_eq!(sort.deparse(), "name COLLATE \"C\" DESC");
+    }
+}
@@ -1,11 +1,14 @@
 use std::{collections::HashMap, ffi::{c_int, c_void, CStr, CString}, num::NonZeroUsize, slice};
-use pgrx::{list::{self, List}, memcx::{self, MemCx}, pg_sys::{self, defGetString, fmgr_info, getTypeInputInfo, list_concat, Datum, FmgrInfo, InputFunctionCall, MemoryContext, Oid}, FromDatum, IntoDatum, PgBox, PgRelation, PgTupleDesc};
+use pgrx::{list, memcx::{self, MemCx}, pg_sys::{self, defGetString, fmgr_info, getTypeInputInfo, list_concat, Datum, FmgrInfo, InputFunctionCall, MemoryContext, Oid}, FromDatum, IntoDatum, PgBox, PgRelation, PgTupleDesc};
 use crate::fdw::utils_share::row::Row;
 use crate::fdw::utils_share::cell::Cell;
 #[cfg(any(feature = "pg13", feature = "pg14"))]
 use pgrx::pg_sys::Value;
 
-pub static ROWID : &str = "id"; //todo
+/// Rowid column of a table without a `rowid_column` option
+pub static ROWID : &str = "id";
+/// Table option naming the column that identifies the rows of a table
+pub static ROWID_OPTION: &str = "rowid_column";
 
 #[cfg(any(feature = "pg15", feature = "pg16"))] 
 #[repr(C)]
@@ -163,6 +166,32 @@ pub unsafe fn exec_clear_tuple(slot: *mut pgrx::pg_sys::TupleTableSlot) {
     }
 }
 
+/// Store `row` into `slot` as a virtual tuple.
+///
+/// The slot is cleared first and every column the row does not set, or sets
+/// to a cell without a datum, is NULL, so nothing of the previous tuple shows
+/// through. `colmap` maps column names to their index in the tuple
+/// descriptor; columns it does not know are skipped. Cells are converted in
+/// the current memory context, which should be a per-tuple one such as the
+/// context iterate callbacks run in.
+pub unsafe fn fill_slot_from_row(slot: *mut pg_sys::TupleTableSlot, row: &Row, colmap: &HashMap<String, usize>) {
+    exec_clear_tuple(slot);
+    let natts = (*(*slot).tts_tupleDescriptor).natts as usize;
+    let values = slice::from_raw_parts_mut((*slot).tts_values, natts);
+    let nulls = slice::from_raw_parts_mut((*slot).tts_isnull, natts);
+    nulls.fill(true);
+    for (col, cell) in row.iter() {
+        let Some(&colno) = colmap.get(col).filter(|&&colno| colno < natts) else {
+            continue;
+        };
+        if let Some(datum) = cell.clone().and_then(|c| c.into_datum()) {
+            values[colno] = datum;
+            nulls[colno] = false;
+        }
+    }
+    pg_sys::ExecStoreVirtualTuple(slot);
+}
+
 /// Convert a `TupleTableSlot` to a `Row`
 /// This function is unsafe because it dereferences raw pointers and assumes that the `TupleTableSlot` is valid and properly initialized.
 /// # Arguments
@@ -231,22 +260,18 @@ pub fn string_to_cstr(s: &str) -> CString {
 /// * `typid`: The Oid of the data type to which the string should be
 /// converted.
 /// # Returns
-/// A `Datum` representing the converted value. If the input string is empty, a null
-/// `Datum` is returned.
+/// A `Datum` representing the converted value. An empty string goes through the
+/// input function like any other value.
 /// # Note
 /// This function uses the PostgreSQL type input function to convert the string to a `Datum`.
 /// It is intended for use with PostgreSQL data types that have a defined input function.
 /// The function retrieves the type input information for the specified Oid and calls the input function to
-/// perform the conversion. If the input string is empty, it returns a null `Datum`.
+/// perform the conversion.
 /// This function is unsafe because it relies on the PostgreSQL C API and assumes that the input
 /// function for the specified Oid is correctly defined and available in the PostgreSQL environment.
 /// It is the caller's responsibility to ensure that the Oid corresponds to a valid data type
 /// and that the input function is properly registered in the PostgreSQL system.
-pub unsafe fn get_datum(value_str: &str, typid: Oid) -> Datum {    
-    if value_str.is_empty() {
-        return Datum::null();
-    }
-
+pub unsafe fn get_datum(value_str: &str, typid: Oid) -> Datum {
     let c_value = CString::new(value_str).unwrap();
     let mut typeinput = Oid::default();
     let mut typeioparam = Oid::default();
@@ -257,6 +282,13 @@ pub unsafe fn get_datum(value_str: &str, typid: Oid) -> Datum {
     res
 }
 
+/// Parse a text value with the input function of `typid` into a `Cell`. An
+/// empty value is an empty string, not NULL. Types without a variant of their
+/// own come as `Cell::Other`.
+pub unsafe fn text_to_cell(value_str: &str, typid: Oid) -> Option<Cell> {
+    Cell::from_polymorphic_datum(get_datum(value_str, typid), false, typid)
+}
+
 pub unsafe fn pg_list_to_rust_list<'a, T: list::Enlist>(
     list: *mut pg_sys::List,
     mcx: &'a MemCx<'_>,
@@ -265,43 +297,38 @@ pub unsafe fn pg_list_to_rust_list<'a, T: list::Enlist>(
 }
 
 
-pub unsafe fn serialize_to_list<T>(state: PgBox<T>) -> *mut pg_sys::List
-where
-    T: Sized,
-{
-    let ret = memcx::current_context(|mcx| {
-        let mut ret = List::<*mut c_void>::Nil;
-        let val = state.into_pg() as i64;
-        let cst: *mut pg_sys::Const = pg_sys::makeConst(
-            pg_sys::INT8OID,
-            -1,
-            pg_sys::InvalidOid,
-            8,
-            val.into_datum().unwrap(),
-            false,
-            true,
-        );
-        ret.unstable_push_in_context(cst as _, mcx);
-        ret.into_ptr()
-    });
+/// `Const` node holding `value`, NULL for `None`. Plan data kept in
+/// `fdw_private` must be made of nodes like these, a plan may be copied and
+/// run any number of times.
+pub unsafe fn make_const<T: IntoDatum>(value: Option<T>) -> *mut c_void {
+    let typid = T::type_oid();
+    let mut typlen = 0;
+    let mut typbyval = false;
+    pg_sys::get_typlenbyval(typid, &mut typlen, &mut typbyval);
+    let datum = value.and_then(|v| v.into_datum());
+    pg_sys::makeConst(
+        typid,
+        -1,
+        pg_sys::get_typcollation(typid),
+        typlen as c_int,
+        datum.unwrap_or(Datum::null()),
+        datum.is_none(),
+        typbyval,
+    ) as _
+}
 
-    ret
+/// Value of a `Const` node made by `make_const`
+pub unsafe fn const_value<T: FromDatum>(node: *mut c_void) -> Option<T> {
+    let cst = node as *mut pg_sys::Const;
+    if cst.is_null() {
+        return None;
+    }
+    T::from_datum((*cst).constvalue, (*cst).constisnull)
 }
 
-pub unsafe fn deserialize_from_list<T>(list: *mut pg_sys::List) -> PgBox<T>
-where
-    T: Sized,
-{
-    memcx::current_context(|mcx| {
-        if let Some(list) = List::<*mut c_void>::downcast_ptr_in_memcx(list, mcx) {
-            if let Some(cst) = list.get(0) {
-                let cst = *(*cst as *mut pg_sys::Const);
-                let ptr = i64::from_datum(cst.constvalue, cst.constisnull).unwrap();
-                return PgBox::<T>::from_pg(ptr as _);
-            }
-        }
-        PgBox::<T>::null()
-    })
+/// Items of a list of plan data, see `make_const`
+pub unsafe fn list_items(list: *mut pg_sys::List) -> Vec<*mut c_void> {
+    memcx::current_context(|mcx| pg_list_to_rust_list::<*mut c_void>(list, mcx).iter().copied().collect())
 }
 
 
@@ -313,12 +340,15 @@ pub unsafe fn delete_wrappers_memctx(ctx: MemoryContext) {
 }
 
 
+/// Name of the rowid column, from the `rowid_column` option
+pub fn rowid_column_name(options: &HashMap<String, String>) -> String {
+    options.get(ROWID_OPTION).map_or(ROWID, String::as_str).to_string()
+}
+
 pub unsafe fn find_rowid_column(
     target_relation: pg_sys::Relation,
 ) -> Option<pg_sys::FormData_pg_attribute> {
-    // get rowid column name from table options
-    //todo
-    let rowid_name = ROWID;
+    let rowid_name = rowid_column_name(&get_foreign_table_options((*target_relation).rd_id));
 
     // find rowid attribute
     let tup_desc = PgTupleDesc::from_pg_copy((*target_relation).rd_att);
@@ -330,3 +360,24 @@ pub unsafe fn find_rowid_column(
 
     None
 }
+
+/// Read attribute `attnum` (starting at 1) of a slot, deforming the tuple up
+/// to it if needed
+pub unsafe fn slot_getattr(
+    slot: *mut pg_sys::TupleTableSlot,
+    attnum: c_int,
+    isnull: *mut bool,
+) -> Datum {
+    assert!(attnum > 0);
+
+    if attnum > (*slot).tts_nvalid.into() {
+        pg_sys::slot_getsomeattrs_int(slot, attnum);
+    }
+
+    let attnum = attnum as usize;
+    let values = slice::from_raw_parts((*slot).tts_values, attnum);
+    let nulls = slice::from_raw_parts((*slot).tts_isnull, attnum);
+
+    *isnull = nulls[attnum - 1];
+    values[attnum - 1]
+}
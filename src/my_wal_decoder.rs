@@ -1,5 +1,6 @@
 #![deny(unsafe_op_in_unsafe_fn)]
 use pgrx::prelude::*;
+use crate::fdw::utils_share::cell::Cell;
 use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
 use std::alloc::{alloc, dealloc, Layout};
@@ -172,17 +173,9 @@ impl Serialize for Tuple {
                 continue;
             }
 
-            match attribute.atttypid {
-                pg_sys::INT4OID => {
-                    let value = unsafe { i32::from_datum(datum, isnull) };
-                    serde_state.serialize_field(attname, &(value.unwrap()))?
-                }
-                pg_sys::TEXTOID => {
-                    let value = unsafe { String::from_datum(datum, isnull) };
-                    serde_state.serialize_field(attname, &(value.unwrap()))?
-                }
-                _ => todo!(),
-            };
+            // the Cell serializer covers every type, NULLs are left out above
+            let value = unsafe { Cell::from_polymorphic_datum(datum, false, attribute.atttypid) };
+            serde_state.serialize_field(attname, &value)?;
         }
         serde_state.end()
     }